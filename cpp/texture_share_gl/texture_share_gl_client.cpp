@@ -40,6 +40,146 @@ const ShmemDataInternal *TextureShareGlClient::ClientImageDataGuard::read() cons
 	return gl_client_image_data_guard_read(this->_data);
 }
 
+TextureShareGlClient::ClientImageList::ClientImageList(::ClientImageList *list)
+	: _list(list)
+{}
+
+TextureShareGlClient::ClientImageList::~ClientImageList()
+{
+	gl_client_image_list_destroy(this->_list);
+	this->_list = nullptr;
+}
+
+TextureShareGlClient::ClientImageList::ClientImageList(ClientImageList &&other)
+	: _list(std::move(other._list))
+{
+	other._list = nullptr;
+}
+
+TextureShareGlClient::ClientImageList &TextureShareGlClient::ClientImageList::operator=(ClientImageList &&other)
+{
+	gl_client_image_list_destroy(this->_list);
+	this->_list = std::move(other._list);
+	other._list = nullptr;
+
+	return *this;
+}
+
+bool TextureShareGlClient::ClientImageList::is_valid() const
+{
+	return this->_list != nullptr;
+}
+
+size_t TextureShareGlClient::ClientImageList::size() const
+{
+	return gl_client_image_list_len(this->_list);
+}
+
+const CImgListEntry *TextureShareGlClient::ClientImageList::get(size_t index) const
+{
+	return gl_client_image_list_get(this->_list, index);
+}
+
+TextureShareGlClient::ClientImageMetadata::ClientImageMetadata(::ClientImageMetadata *metadata)
+	: _metadata(metadata)
+{}
+
+TextureShareGlClient::ClientImageMetadata::~ClientImageMetadata()
+{
+	gl_client_image_metadata_destroy(this->_metadata);
+	this->_metadata = nullptr;
+}
+
+TextureShareGlClient::ClientImageMetadata::ClientImageMetadata(ClientImageMetadata &&other)
+	: _metadata(std::move(other._metadata))
+{
+	other._metadata = nullptr;
+}
+
+TextureShareGlClient::ClientImageMetadata &TextureShareGlClient::ClientImageMetadata::operator=(ClientImageMetadata &&other)
+{
+	gl_client_image_metadata_destroy(this->_metadata);
+	this->_metadata = std::move(other._metadata);
+	other._metadata = nullptr;
+
+	return *this;
+}
+
+bool TextureShareGlClient::ClientImageMetadata::is_valid() const
+{
+	return this->_metadata != nullptr;
+}
+
+size_t TextureShareGlClient::ClientImageMetadata::size() const
+{
+	return gl_client_image_metadata_len(this->_metadata);
+}
+
+const CImgMetadataEntry *TextureShareGlClient::ClientImageMetadata::get(size_t index) const
+{
+	return gl_client_image_metadata_get(this->_metadata, index);
+}
+
+TextureShareGlClient::ClientServerStats::ClientServerStats(::ClientServerStats *stats)
+	: _stats(stats)
+{}
+
+TextureShareGlClient::ClientServerStats::~ClientServerStats()
+{
+	gl_client_stats_destroy(this->_stats);
+	this->_stats = nullptr;
+}
+
+TextureShareGlClient::ClientServerStats::ClientServerStats(ClientServerStats &&other)
+	: _stats(std::move(other._stats))
+{
+	other._stats = nullptr;
+}
+
+TextureShareGlClient::ClientServerStats &TextureShareGlClient::ClientServerStats::operator=(ClientServerStats &&other)
+{
+	gl_client_stats_destroy(this->_stats);
+	this->_stats = std::move(other._stats);
+	other._stats = nullptr;
+
+	return *this;
+}
+
+bool TextureShareGlClient::ClientServerStats::is_valid() const
+{
+	return this->_stats != nullptr;
+}
+
+uint32_t TextureShareGlClient::ClientServerStats::connection_count() const
+{
+	return gl_client_stats_connection_count(this->_stats);
+}
+
+uint64_t TextureShareGlClient::ClientServerStats::gpu_copy_count() const
+{
+	return gl_client_stats_gpu_copy_count(this->_stats);
+}
+
+size_t TextureShareGlClient::ClientServerStats::image_count() const
+{
+	return gl_client_stats_image_len(this->_stats);
+}
+
+const CImgStatsEntry *TextureShareGlClient::ClientServerStats::get_image(size_t index) const
+{
+	return gl_client_stats_image_get(this->_stats, index);
+}
+
+size_t TextureShareGlClient::ClientServerStats::device_count() const
+{
+	return gl_client_stats_device_len(this->_stats);
+}
+
+const DeviceStatsEntry *TextureShareGlClient::ClientServerStats::get_device(size_t index) const
+{
+	return gl_client_stats_device_get(this->_stats, index);
+}
+
 TextureShareGlClient::TextureShareGlClient() {}
 
 TextureShareGlClient::TextureShareGlClient::TextureShareGlClient(TextureShareGlClient &&other)
@@ -74,6 +214,15 @@ bool TextureShareGlClient::init(const char *socket_path, uint64_t timeout_in_mil
 	return this->_client != nullptr;
 }
 
+bool TextureShareGlClient::init_with_namespace(const char *namespace_name, const char *socket_path,
+                                               uint64_t timeout_in_millis)
+{
+	this->destroy_client();
+	this->_client = gl_client_new_with_namespace(socket_path, namespace_name, timeout_in_millis);
+
+	return this->_client != nullptr;
+}
+
 bool TextureShareGlClient::init_with_server_launch(const char *socket_path, uint64_t client_timeout_in_millis,
                                                    const char *server_program, const char *server_lock_path,
                                                    const char *server_socket_path, const char *shmem_prefix,
@@ -107,6 +256,17 @@ ImageLookupResult TextureShareGlClient::init_image(const char *image_name, uint3
 	return gl_client_init_image(this->_client, image_name, width, height, format, overwrite_existing);
 }
 
+ImageLookupResult TextureShareGlClient::init_image_buffered(const char *image_name, uint32_t width, uint32_t height,
+                                                            ImgFormat format, bool overwrite_existing,
+                                                            uint32_t buffer_count)
+{
+	if(!this->_client)
+		return ImageLookupResult::Error;
+
+	return gl_client_init_image_buffered(this->_client, image_name, width, height, format, overwrite_existing,
+	                                     buffer_count);
+}
+
 ImageLookupResult TextureShareGlClient::find_image(const char *image_name, bool force_update)
 {
 	if(!this->_client)
@@ -124,6 +284,144 @@ TextureShareGlClient::ClientImageDataGuard TextureShareGlClient::find_image_data
 	return ClientImageDataGuard(gl_client_find_image_data(this->_client, image_name, force_update));
 }
 
+int TextureShareGlClient::find_images(const char *const *image_names, size_t image_count,
+                                      ImageLookupResult *results, bool force_update)
+{
+	if(!this->_client)
+		return -1;
+
+	return gl_client_find_images(this->_client, image_names, image_count, force_update, results);
+}
+
+ImageLookupResult TextureShareGlClient::wait_for_image(const char *image_name, uint64_t timeout_in_millis,
+                                                       bool force_update)
+{
+	if(!this->_client)
+		return ImageLookupResult::Error;
+
+	return gl_client_wait_for_image(this->_client, image_name, timeout_in_millis, force_update);
+}
+
+ImageLookupResult TextureShareGlClient::rename_image(const char *old_image_name, const char *new_image_name)
+{
+	if(!this->_client)
+		return ImageLookupResult::Error;
+
+	return gl_client_rename_image(this->_client, old_image_name, new_image_name);
+}
+
+ImageLookupResult TextureShareGlClient::release_image(const char *image_name)
+{
+	if(!this->_client)
+		return ImageLookupResult::Error;
+
+	return gl_client_release_image(this->_client, image_name);
+}
+
+ImageLookupResult TextureShareGlClient::delete_image(const char *image_name)
+{
+	if(!this->_client)
+		return ImageLookupResult::Error;
+
+	return gl_client_delete_image(this->_client, image_name);
+}
+
+TextureShareGlClient::ClientImageList TextureShareGlClient::list_images()
+{
+	if(!this->_client)
+		return ClientImageList(nullptr);
+
+	return ClientImageList(gl_client_list_images(this->_client));
+}
+
+TextureShareGlClient::ClientImageList TextureShareGlClient::list_images_matching(const char *pattern)
+{
+	if(!this->_client)
+		return ClientImageList(nullptr);
+
+	return ClientImageList(gl_client_list_images_matching(this->_client, pattern));
+}
+
+TextureShareGlClient::ClientServerStats TextureShareGlClient::get_stats()
+{
+	if(!this->_client)
+		return ClientServerStats(nullptr);
+
+	return ClientServerStats(gl_client_get_stats(this->_client));
+}
+
+ImageLookupResult TextureShareGlClient::set_image_metadata(const char *image_name, const char *key, const char *value)
+{
+	if(!this->_client)
+		return ImageLookupResult::Error;
+
+	return gl_client_set_image_metadata(this->_client, image_name, key, value);
+}
+
+TextureShareGlClient::ClientImageMetadata TextureShareGlClient::get_image_metadata(const char *image_name)
+{
+	if(!this->_client)
+		return ClientImageMetadata(nullptr);
+
+	return ClientImageMetadata(gl_client_get_image_metadata(this->_client, image_name));
+}
+
+ImageLookupResult TextureShareGlClient::subscribe(const char *image_name, bool subscribe)
+{
+	if(!this->_client)
+		return ImageLookupResult::Error;
+
+	return gl_client_subscribe(this->_client, image_name, subscribe);
+}
+
+int TextureShareGlClient::poll_event(CImgEvent *event)
+{
+	if(!this->_client)
+		return -1;
+
+	return gl_client_poll_event(this->_client, event);
+}
+
+int TextureShareGlClient::set_heartbeat_interval(uint64_t interval_in_millis)
+{
+	if(!this->_client)
+		return -1;
+
+	return gl_client_set_heartbeat_interval(this->_client, interval_in_millis);
+}
+
+int TextureShareGlClient::ping()
+{
+	if(!this->_client)
+		return -1;
+
+	return gl_client_ping(this->_client);
+}
+
+int TextureShareGlClient::heartbeat()
+{
+	if(!this->_client)
+		return -1;
+
+	return gl_client_heartbeat(this->_client);
+}
+
+void TextureShareGlClient::set_reconnect_policy(uint32_t max_attempts, uint64_t retry_interval_in_millis)
+{
+	if(!this->_client)
+		return;
+
+	gl_client_set_reconnect_policy(this->_client, max_attempts, retry_interval_in_millis);
+}
+
+int TextureShareGlClient::reconnect()
+{
+	if(!this->_client)
+		return -1;
+
+	return gl_client_reconnect(this->_client);
+}
+
 int TextureShareGlClient::send_image(const char *image_name, GLuint src_texture_id, GLenum src_texture_target,
                                      bool invert, GLuint prev_fbo, const struct GlImageExtent *extents)
 {
@@ -135,11 +433,20 @@ int TextureShareGlClient::send_image(const char *image_name, GLuint src_texture_
 }
 
 int TextureShareGlClient::recv_image(const char *image_name, GLuint dst_texture_id, GLenum dst_texture_target,
-                                     bool invert, GLuint prev_fbo, const struct GlImageExtent *extents)
+                                     bool invert, GLuint prev_fbo, const struct GlImageExtent *extents,
+                                     ImgFrameInfo *frame_info)
 {
 	if(!this->_client)
 		return -1;
 
-	return gl_client_recv_image(this->_client, image_name, dst_texture_id, dst_texture_target, invert, prev_fbo,
-	                            extents);
+	return gl_client_recv_image_frame(this->_client, image_name, dst_texture_id, dst_texture_target, invert,
+	                                  prev_fbo, extents, frame_info);
+}
+
+int TextureShareGlClient::get_frame_info(const char *image_name, ImgFrameInfo *frame_info)
+{
+	if(!this->_client)
+		return -1;
+
+	return gl_client_get_frame_info(this->_client, image_name, frame_info);
 }
@@ -24,6 +24,64 @@ class TextureShareGlClient
 		::ClientImageDataGuard *_data = nullptr;
 	};
 
+	struct ClientImageList
+	{
+		ClientImageList(::ClientImageList *list);
+		~ClientImageList();
+
+		ClientImageList(const ClientImageList &)            = delete;
+		ClientImageList &operator=(const ClientImageList &) = delete;
+		ClientImageList(ClientImageList &&);
+		ClientImageList &operator=(ClientImageList &&);
+
+		bool is_valid() const;
+		size_t size() const;
+		const CImgListEntry *get(size_t index) const;
+
+		private:
+		::ClientImageList *_list = nullptr;
+	};
+
+	struct ClientImageMetadata
+	{
+		ClientImageMetadata(::ClientImageMetadata *metadata);
+		~ClientImageMetadata();
+
+		ClientImageMetadata(const ClientImageMetadata &)            = delete;
+		ClientImageMetadata &operator=(const ClientImageMetadata &) = delete;
+		ClientImageMetadata(ClientImageMetadata &&);
+		ClientImageMetadata &operator=(ClientImageMetadata &&);
+
+		bool is_valid() const;
+		size_t size() const;
+		const CImgMetadataEntry *get(size_t index) const;
+
+		private:
+		::ClientImageMetadata *_metadata = nullptr;
+	};
+
+	struct ClientServerStats
+	{
+		ClientServerStats(::ClientServerStats *stats);
+		~ClientServerStats();
+
+		ClientServerStats(const ClientServerStats &)            = delete;
+		ClientServerStats &operator=(const ClientServerStats &) = delete;
+		ClientServerStats(ClientServerStats &&);
+		ClientServerStats &operator=(ClientServerStats &&);
+
+		bool is_valid() const;
+		uint32_t connection_count() const;
+		uint64_t gpu_copy_count() const;
+		size_t image_count() const;
+		const CImgStatsEntry *get_image(size_t index) const;
+		size_t device_count() const;
+		const DeviceStatsEntry *get_device(size_t index) const;
+
+		private:
+		::ClientServerStats *_stats = nullptr;
+	};
+
 	static constexpr std::string_view DEFAULT_SHMEM_PREFIX  = "shmem_img_";
 	static constexpr std::string_view DEFAULT_LOCKFILE_PATH = VK_SERVER_DEFAULT_LOCKFILE_PATH;
 	static constexpr std::string_view DEFAULT_SOCKET_PATH   = VK_SERVER_DEFAULT_SOCKET_PATH;
@@ -39,6 +97,9 @@ class TextureShareGlClient
 	static bool initialize_gl_external();
 
 	bool init(const char *socket_path = DEFAULT_SOCKET_PATH.data(), uint64_t timeout_in_millis = 1000);
+	// Only images of the same namespace are visible to the client
+	bool init_with_namespace(const char *namespace_name, const char *socket_path = DEFAULT_SOCKET_PATH.data(),
+	                         uint64_t timeout_in_millis = 1000);
 	bool init_with_server_launch(
 		const char *socket_path = DEFAULT_SOCKET_PATH.data(), uint64_t client_timeout_in_millis = 1000,
 		const char *server_program = VK_SERVER_EXECUTABLE, const char *server_lock_path = DEFAULT_LOCKFILE_PATH.data(),
@@ -51,15 +112,53 @@ class TextureShareGlClient
 
 	ImageLookupResult init_image(const char *image_name, uint32_t width, uint32_t height, ImgFormat format,
 	                             bool overwrite_existing);
+	// Allocates buffer_count images, so that send_image and recv_image don't block each other
+	ImageLookupResult init_image_buffered(const char *image_name, uint32_t width, uint32_t height, ImgFormat format,
+	                                      bool overwrite_existing, uint32_t buffer_count);
 
 	ImageLookupResult find_image(const char *image_name, bool force_update);
 	ClientImageDataGuard find_image_data(const char *image_name, bool force_update);
+	// Fills results with image_count entries. Returns the number of found images or -1 on error
+	int find_images(const char *const *image_names, size_t image_count, ImageLookupResult *results,
+	                bool force_update);
+	// Blocks until the image is initialized or timeout passed. Returns NotFound on timeout
+	ImageLookupResult wait_for_image(const char *image_name, uint64_t timeout_in_millis, bool force_update);
+
+	ImageLookupResult rename_image(const char *old_image_name, const char *new_image_name);
+	ImageLookupResult release_image(const char *image_name);
+	ImageLookupResult delete_image(const char *image_name);
+
+	ClientImageList list_images();
+	// pattern is a glob like "studio/cam1/*", '*' also matches '/'
+	ClientImageList list_images_matching(const char *pattern);
+	ClientServerStats get_stats();
+
+	// A nullptr value removes the entry
+	ImageLookupResult set_image_metadata(const char *image_name, const char *key, const char *value);
+	ClientImageMetadata get_image_metadata(const char *image_name);
+
+	ImageLookupResult subscribe(const char *image_name, bool subscribe = true);
+	// Events returned with 1 have to be freed with img_event_destroy
+	int poll_event(CImgEvent *event);
+
+	// Return 1 if the server responded, 0 if it is unresponsive and -1 on error
+	int set_heartbeat_interval(uint64_t interval_in_millis);
+	int ping();
+	int heartbeat();
+
+	// Reconnect and restore images if the server went away. max_attempts == 0 disables reconnecting
+	void set_reconnect_policy(uint32_t max_attempts, uint64_t retry_interval_in_millis = 500);
+	// Return 1 on success and -1 on error
+	int reconnect();
 
 	int send_image(const char *image_name, GLuint src_texture_id, GLenum src_texture_target, bool invert,
 	               GLuint prev_fbo, const struct GlImageExtent *extents);
 
+	// Writes the number and timestamp of the received frame to frame_info if it isn't nullptr
 	int recv_image(const char *image_name, GLuint dst_texture_id, GLenum dst_texture_target, bool invert,
-	               GLuint prev_fbo, const struct GlImageExtent *extents);
+	               GLuint prev_fbo, const struct GlImageExtent *extents, ImgFrameInfo *frame_info = nullptr);
+	// Read the last frame of an image without copying it. Return 1 if found, 0 if not and -1 on error
+	int get_frame_info(const char *image_name, ImgFrameInfo *frame_info);
 
 	private:
 	struct GlClient *_client = nullptr;
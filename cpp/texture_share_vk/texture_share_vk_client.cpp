@@ -40,6 +40,146 @@ const ShmemDataInternal *TextureShareVkClient::ClientImageDataGuard::read() cons
 	return vk_client_image_data_guard_read(this->_data);
 }
 
+TextureShareVkClient::ClientImageList::ClientImageList(::ClientImageList *list)
+	: _list(list)
+{}
+
+TextureShareVkClient::ClientImageList::~ClientImageList()
+{
+	vk_client_image_list_destroy(this->_list);
+	this->_list = nullptr;
+}
+
+TextureShareVkClient::ClientImageList::ClientImageList(ClientImageList &&other)
+	: _list(std::move(other._list))
+{
+	other._list = nullptr;
+}
+
+TextureShareVkClient::ClientImageList &TextureShareVkClient::ClientImageList::operator=(ClientImageList &&other)
+{
+	vk_client_image_list_destroy(this->_list);
+	this->_list = std::move(other._list);
+	other._list = nullptr;
+
+	return *this;
+}
+
+bool TextureShareVkClient::ClientImageList::is_valid() const
+{
+	return this->_list != nullptr;
+}
+
+size_t TextureShareVkClient::ClientImageList::size() const
+{
+	return vk_client_image_list_len(this->_list);
+}
+
+const CImgListEntry *TextureShareVkClient::ClientImageList::get(size_t index) const
+{
+	return vk_client_image_list_get(this->_list, index);
+}
+
+TextureShareVkClient::ClientImageMetadata::ClientImageMetadata(::ClientImageMetadata *metadata)
+	: _metadata(metadata)
+{}
+
+TextureShareVkClient::ClientImageMetadata::~ClientImageMetadata()
+{
+	vk_client_image_metadata_destroy(this->_metadata);
+	this->_metadata = nullptr;
+}
+
+TextureShareVkClient::ClientImageMetadata::ClientImageMetadata(ClientImageMetadata &&other)
+	: _metadata(std::move(other._metadata))
+{
+	other._metadata = nullptr;
+}
+
+TextureShareVkClient::ClientImageMetadata &TextureShareVkClient::ClientImageMetadata::operator=(ClientImageMetadata &&other)
+{
+	vk_client_image_metadata_destroy(this->_metadata);
+	this->_metadata = std::move(other._metadata);
+	other._metadata = nullptr;
+
+	return *this;
+}
+
+bool TextureShareVkClient::ClientImageMetadata::is_valid() const
+{
+	return this->_metadata != nullptr;
+}
+
+size_t TextureShareVkClient::ClientImageMetadata::size() const
+{
+	return vk_client_image_metadata_len(this->_metadata);
+}
+
+const CImgMetadataEntry *TextureShareVkClient::ClientImageMetadata::get(size_t index) const
+{
+	return vk_client_image_metadata_get(this->_metadata, index);
+}
+
+TextureShareVkClient::ClientServerStats::ClientServerStats(::ClientServerStats *stats)
+	: _stats(stats)
+{}
+
+TextureShareVkClient::ClientServerStats::~ClientServerStats()
+{
+	vk_client_stats_destroy(this->_stats);
+	this->_stats = nullptr;
+}
+
+TextureShareVkClient::ClientServerStats::ClientServerStats(ClientServerStats &&other)
+	: _stats(std::move(other._stats))
+{
+	other._stats = nullptr;
+}
+
+TextureShareVkClient::ClientServerStats &TextureShareVkClient::ClientServerStats::operator=(ClientServerStats &&other)
+{
+	vk_client_stats_destroy(this->_stats);
+	this->_stats = std::move(other._stats);
+	other._stats = nullptr;
+
+	return *this;
+}
+
+bool TextureShareVkClient::ClientServerStats::is_valid() const
+{
+	return this->_stats != nullptr;
+}
+
+uint32_t TextureShareVkClient::ClientServerStats::connection_count() const
+{
+	return vk_client_stats_connection_count(this->_stats);
+}
+
+uint64_t TextureShareVkClient::ClientServerStats::gpu_copy_count() const
+{
+	return vk_client_stats_gpu_copy_count(this->_stats);
+}
+
+size_t TextureShareVkClient::ClientServerStats::image_count() const
+{
+	return vk_client_stats_image_len(this->_stats);
+}
+
+const CImgStatsEntry *TextureShareVkClient::ClientServerStats::get_image(size_t index) const
+{
+	return vk_client_stats_image_get(this->_stats, index);
+}
+
+size_t TextureShareVkClient::ClientServerStats::device_count() const
+{
+	return vk_client_stats_device_len(this->_stats);
+}
+
+const DeviceStatsEntry *TextureShareVkClient::ClientServerStats::get_device(size_t index) const
+{
+	return vk_client_stats_device_get(this->_stats, index);
+}
+
 TextureShareVkClient::TextureShareVkClient() {}
 
 TextureShareVkClient::TextureShareVkClient::TextureShareVkClient(TextureShareVkClient &&other)
@@ -69,6 +209,15 @@ bool TextureShareVkClient::init(VkSetup *vk_setup, const char *socket_path, uint
 	return this->_client != nullptr;
 }
 
+bool TextureShareVkClient::init_with_namespace(VkSetup *vk_setup, const char *namespace_name, const char *socket_path,
+                                               uint64_t timeout_in_millis)
+{
+	this->destroy_client();
+	this->_client = vk_client_new_with_namespace(socket_path, namespace_name, vk_setup, timeout_in_millis);
+
+	return this->_client != nullptr;
+}
+
 bool TextureShareVkClient::init_with_server_launch(VkSetup *vk_setup, const char *socket_path,
                                                    uint64_t client_timeout_in_millis, const char *server_program,
                                                    const char *server_lock_path, const char *server_socket_path,
@@ -102,6 +251,17 @@ ImageLookupResult TextureShareVkClient::init_image(const char *image_name, uint3
 	return vk_client_init_image(this->_client, image_name, width, height, format, overwrite_existing);
 }
 
+ImageLookupResult TextureShareVkClient::init_image_buffered(const char *image_name, uint32_t width, uint32_t height,
+                                                            ImgFormat format, bool overwrite_existing,
+                                                            uint32_t buffer_count)
+{
+	if(!this->_client)
+		return ImageLookupResult::Error;
+
+	return vk_client_init_image_buffered(this->_client, image_name, width, height, format, overwrite_existing,
+	                                     buffer_count);
+}
+
 ImageLookupResult TextureShareVkClient::find_image(const char *image_name, bool force_update)
 {
 	if(!this->_client)
@@ -119,6 +279,144 @@ TextureShareVkClient::ClientImageDataGuard TextureShareVkClient::find_image_data
 	return ClientImageDataGuard(vk_client_find_image_data(this->_client, image_name, force_update));
 }
 
+int TextureShareVkClient::find_images(const char *const *image_names, size_t image_count,
+                                      ImageLookupResult *results, bool force_update)
+{
+	if(!this->_client)
+		return -1;
+
+	return vk_client_find_images(this->_client, image_names, image_count, force_update, results);
+}
+
+ImageLookupResult TextureShareVkClient::wait_for_image(const char *image_name, uint64_t timeout_in_millis,
+                                                       bool force_update)
+{
+	if(!this->_client)
+		return ImageLookupResult::Error;
+
+	return vk_client_wait_for_image(this->_client, image_name, timeout_in_millis, force_update);
+}
+
+ImageLookupResult TextureShareVkClient::rename_image(const char *old_image_name, const char *new_image_name)
+{
+	if(!this->_client)
+		return ImageLookupResult::Error;
+
+	return vk_client_rename_image(this->_client, old_image_name, new_image_name);
+}
+
+ImageLookupResult TextureShareVkClient::release_image(const char *image_name)
+{
+	if(!this->_client)
+		return ImageLookupResult::Error;
+
+	return vk_client_release_image(this->_client, image_name);
+}
+
+ImageLookupResult TextureShareVkClient::delete_image(const char *image_name)
+{
+	if(!this->_client)
+		return ImageLookupResult::Error;
+
+	return vk_client_delete_image(this->_client, image_name);
+}
+
+TextureShareVkClient::ClientImageList TextureShareVkClient::list_images()
+{
+	if(!this->_client)
+		return ClientImageList(nullptr);
+
+	return ClientImageList(vk_client_list_images(this->_client));
+}
+
+TextureShareVkClient::ClientImageList TextureShareVkClient::list_images_matching(const char *pattern)
+{
+	if(!this->_client)
+		return ClientImageList(nullptr);
+
+	return ClientImageList(vk_client_list_images_matching(this->_client, pattern));
+}
+
+TextureShareVkClient::ClientServerStats TextureShareVkClient::get_stats()
+{
+	if(!this->_client)
+		return ClientServerStats(nullptr);
+
+	return ClientServerStats(vk_client_get_stats(this->_client));
+}
+
+ImageLookupResult TextureShareVkClient::set_image_metadata(const char *image_name, const char *key, const char *value)
+{
+	if(!this->_client)
+		return ImageLookupResult::Error;
+
+	return vk_client_set_image_metadata(this->_client, image_name, key, value);
+}
+
+TextureShareVkClient::ClientImageMetadata TextureShareVkClient::get_image_metadata(const char *image_name)
+{
+	if(!this->_client)
+		return ClientImageMetadata(nullptr);
+
+	return ClientImageMetadata(vk_client_get_image_metadata(this->_client, image_name));
+}
+
+ImageLookupResult TextureShareVkClient::subscribe(const char *image_name, bool subscribe)
+{
+	if(!this->_client)
+		return ImageLookupResult::Error;
+
+	return vk_client_subscribe(this->_client, image_name, subscribe);
+}
+
+int TextureShareVkClient::poll_event(CImgEvent *event)
+{
+	if(!this->_client)
+		return -1;
+
+	return vk_client_poll_event(this->_client, event);
+}
+
+int TextureShareVkClient::set_heartbeat_interval(uint64_t interval_in_millis)
+{
+	if(!this->_client)
+		return -1;
+
+	return vk_client_set_heartbeat_interval(this->_client, interval_in_millis);
+}
+
+int TextureShareVkClient::ping()
+{
+	if(!this->_client)
+		return -1;
+
+	return vk_client_ping(this->_client);
+}
+
+int TextureShareVkClient::heartbeat()
+{
+	if(!this->_client)
+		return -1;
+
+	return vk_client_heartbeat(this->_client);
+}
+
+void TextureShareVkClient::set_reconnect_policy(uint32_t max_attempts, uint64_t retry_interval_in_millis)
+{
+	if(!this->_client)
+		return;
+
+	vk_client_set_reconnect_policy(this->_client, max_attempts, retry_interval_in_millis);
+}
+
+int TextureShareVkClient::reconnect()
+{
+	if(!this->_client)
+		return -1;
+
+	return vk_client_reconnect(this->_client);
+}
+
 int TextureShareVkClient::send_image(const char *image_name, VkImage image, VkImageLayout orig_layout,
                                      VkImageLayout target_layout, VkFence fence, VkOffset3D *extents)
 {
@@ -129,10 +427,20 @@ int TextureShareVkClient::send_image(const char *image_name, VkImage image, VkIm
 }
 
 int TextureShareVkClient::recv_image(const char *image_name, VkImage image, VkImageLayout orig_layout,
-                                     VkImageLayout target_layout, VkFence fence, VkOffset3D *extents)
+                                     VkImageLayout target_layout, VkFence fence, VkOffset3D *extents,
+                                     ImgFrameInfo *frame_info)
+{
+	if(!this->_client)
+		return -1;
+
+	return vk_client_recv_image_frame(this->_client, image_name, image, orig_layout, target_layout, fence, extents,
+	                                  frame_info);
+}
+
+int TextureShareVkClient::get_frame_info(const char *image_name, ImgFrameInfo *frame_info)
 {
 	if(!this->_client)
 		return -1;
 
-	return vk_client_recv_image(this->_client, image_name, image, orig_layout, target_layout, fence, extents);
+	return vk_client_get_frame_info(this->_client, image_name, frame_info);
 }
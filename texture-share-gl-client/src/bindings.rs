@@ -1,12 +1,19 @@
 use std::{
 	borrow::Cow,
 	ffi::CStr,
-	ptr::{null_mut, NonNull},
+	ptr::{self, null_mut, NonNull},
 	time::Duration,
 };
 
 use libc::{c_char, c_int};
-use texture_share_ipc::platform::{img_data::ImgFormat, ReadLockGuard, ShmemDataInternal};
+use texture_share_ipc::platform::{
+	daemon_launch::ReconnectPolicy,
+	img_data::{DeviceStatsEntry, ImgFormat, ImgFrameInfo},
+	ipc_commands::{ServerStats, DEFAULT_NAMESPACE},
+	ShmemDataInternal,
+};
+use texture_share_ipc::bindings::{CImgEvent, CImgListEntry, CImgMetadataEntry, CImgStatsEntry};
+use texture_share_ipc::Error;
 
 use crate::GlClient;
 use crate::{gl_shared_image::GlImageExtent, opengl::glad};
@@ -23,18 +30,31 @@ fn get_str<'a>(buf: &'a *const c_char) -> Cow<'a, str> {
 	unsafe { CStr::from_ptr(buf.to_owned()) }.to_string_lossy()
 }
 
-struct ClientImageDataGuard<'a> {
-	_read_lock: ReadLockGuard<'a>,
-	image_data: &'a ShmemDataInternal,
+// Holds a copy of the image data, so that no lock on the image is kept
+struct ClientImageDataGuard {
+	image_data: ShmemDataInternal,
 }
 
-impl<'a> ClientImageDataGuard<'a> {
+impl ClientImageDataGuard {
 	#[no_mangle]
 	extern "C" fn get_image_data(&self) -> &ShmemDataInternal {
 		&self.image_data
 	}
 }
 
+struct ClientImageList {
+	images: Vec<CImgListEntry>,
+}
+
+struct ClientImageMetadata {
+	entries: Vec<CImgMetadataEntry>,
+}
+
+struct ClientServerStats {
+	stats: ServerStats,
+	images: Vec<CImgStatsEntry>,
+}
+
 #[no_mangle]
 extern "C" fn gl_client_initialize_external_gl() -> bool {
 	GlClient::initialize_gl_external()
@@ -42,8 +62,23 @@ extern "C" fn gl_client_initialize_external_gl() -> bool {
 
 #[no_mangle]
 extern "C" fn gl_client_new(socket_path: *const c_char, timeout_in_millis: u64) -> *mut GlClient {
-	let gl_client = GlClient::new(
+	gl_client_new_with_namespace(socket_path, ptr::null(), timeout_in_millis)
+}
+
+// A NULL namespace selects the default namespace
+#[no_mangle]
+extern "C" fn gl_client_new_with_namespace(
+	socket_path: *const c_char,
+	namespace: *const c_char,
+	timeout_in_millis: u64,
+) -> *mut GlClient {
+	let namespace = match namespace.is_null() {
+		true => Cow::from(DEFAULT_NAMESPACE),
+		false => get_str(&namespace),
+	};
+	let gl_client = GlClient::new_with_namespace(
 		&get_str(&socket_path),
+		&namespace,
 		Duration::from_millis(timeout_in_millis),
 	);
 
@@ -129,6 +164,34 @@ extern "C" fn gl_client_init_image(
 	}
 }
 
+#[no_mangle]
+extern "C" fn gl_client_init_image_buffered(
+	gl_client: *mut GlClient,
+	image_name: *const c_char,
+	width: u32,
+	height: u32,
+	format: ImgFormat,
+	overwrite_existing: bool,
+	buffer_count: u32,
+) -> ImageLookupResult {
+	match unsafe { gl_client.as_mut() }.unwrap().init_image_buffered(
+		&get_str(&image_name),
+		width,
+		height,
+		format,
+		overwrite_existing,
+		buffer_count,
+	) {
+		Ok(Some(true)) => return ImageLookupResult::RequiresUpdate,
+		Ok(Some(false)) => return ImageLookupResult::Found,
+		Ok(None) => return ImageLookupResult::NotFound,
+		Err(e) => {
+			println!("Failed to init image with err '{:}'", e);
+			return ImageLookupResult::Error;
+		}
+	}
+}
+
 #[no_mangle]
 extern "C" fn gl_client_find_image(
 	gl_client: *mut GlClient,
@@ -150,23 +213,150 @@ extern "C" fn gl_client_find_image(
 	}
 }
 
+// Block for up to timeout_in_millis until the image is initialized, then import it like
+// find_image. Returns NotFound if the image didn't appear in time
 #[no_mangle]
-extern "C" fn gl_client_find_image_data<'a>(
+extern "C" fn gl_client_wait_for_image(
 	gl_client: *mut GlClient,
 	image_name: *const c_char,
+	timeout_in_millis: u64,
+	force_update: bool,
+) -> ImageLookupResult {
+	let local_image = unsafe { gl_client.as_mut() }.unwrap().wait_for_image(
+		&get_str(&image_name),
+		Duration::from_millis(timeout_in_millis),
+		force_update,
+	);
+
+	match local_image {
+		Ok(Some(true)) => return ImageLookupResult::RequiresUpdate,
+		Ok(Some(false)) => return ImageLookupResult::Found,
+		Ok(None) => return ImageLookupResult::NotFound,
+		Err(e) => {
+			println!("Failed to wait for image with err '{:}'", e);
+			return ImageLookupResult::Error;
+		}
+	}
+}
+
+// Look up image_count images at once. results must hold image_count entries, which are filled in
+// the order of image_names. Returns the number of found images or -1 on error
+#[no_mangle]
+extern "C" fn gl_client_find_images(
+	gl_client: *mut GlClient,
+	image_names: *const *const c_char,
+	image_count: usize,
 	force_update: bool,
-) -> *mut ClientImageDataGuard<'a> {
+	results: *mut ImageLookupResult,
+) -> c_int {
+	let image_names = match image_count {
+		0 => Vec::new(),
+		_ => unsafe { std::slice::from_raw_parts(image_names, image_count) }
+			.iter()
+			.map(get_str)
+			.collect::<Vec<_>>(),
+	};
+	let image_names = image_names.iter().map(|x| x.as_ref()).collect::<Vec<_>>();
+
+	let res = unsafe { gl_client.as_mut() }
+		.unwrap()
+		.find_images(&image_names, force_update);
+
+	let local_images = match res {
+		Ok(Some(local_images)) => local_images,
+		Ok(None) => vec![None; image_count],
+		Err(e) => {
+			println!("Failed to find images with err '{:}'", e);
+			return -1;
+		}
+	};
+
+	let mut found_count = 0;
+	for (i, local_image) in local_images.into_iter().enumerate() {
+		let res = match local_image {
+			Some(true) => ImageLookupResult::RequiresUpdate,
+			Some(false) => ImageLookupResult::Found,
+			None => ImageLookupResult::NotFound,
+		};
+		if local_image.is_some() {
+			found_count += 1;
+		}
+		unsafe { results.add(i).write(res) };
+	}
+
+	found_count
+}
+
+#[no_mangle]
+extern "C" fn gl_client_rename_image(
+	gl_client: *mut GlClient,
+	old_image_name: *const c_char,
+	new_image_name: *const c_char,
+) -> ImageLookupResult {
+	let res = unsafe { gl_client.as_mut() }
+		.unwrap()
+		.rename_image(&get_str(&old_image_name), &get_str(&new_image_name));
+
+	match res {
+		Ok(Some(_)) => return ImageLookupResult::Found,
+		Ok(None) => return ImageLookupResult::NotFound,
+		Err(e) => {
+			println!("Failed to rename image with err '{:}'", e);
+			return ImageLookupResult::Error;
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_release_image(
+	gl_client: *mut GlClient,
+	image_name: *const c_char,
+) -> ImageLookupResult {
+	let res = unsafe { gl_client.as_mut() }
+		.unwrap()
+		.release_image(&get_str(&image_name));
+
+	match res {
+		Ok(Some(_)) => return ImageLookupResult::Found,
+		Ok(None) => return ImageLookupResult::NotFound,
+		Err(e) => {
+			println!("Failed to release image with err '{:}'", e);
+			return ImageLookupResult::Error;
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_delete_image(
+	gl_client: *mut GlClient,
+	image_name: *const c_char,
+) -> ImageLookupResult {
+	let res = unsafe { gl_client.as_mut() }
+		.unwrap()
+		.delete_image(&get_str(&image_name));
+
+	match res {
+		Ok(Some(_)) => return ImageLookupResult::Found,
+		Ok(None) => return ImageLookupResult::NotFound,
+		Err(e) => {
+			println!("Failed to delete image with err '{:}'", e);
+			return ImageLookupResult::Error;
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_find_image_data(
+	gl_client: *mut GlClient,
+	image_name: *const c_char,
+	force_update: bool,
+) -> *mut ClientImageDataGuard {
 	let local_image = unsafe { gl_client.as_mut() }
 		.unwrap()
-		.find_image_data(&get_str(&image_name), force_update);
+		.read_image_data(&get_str(&image_name), force_update);
 
 	match local_image {
-		Ok(Some(d)) => {
-			return Box::into_raw(Box::new(ClientImageDataGuard {
-				_read_lock: d.0,
-				image_data: d.1,
-			}))
-		}
+		Ok(Some(d)) => return Box::into_raw(Box::new(ClientImageDataGuard { image_data: d })),
 		Ok(None) => return null_mut(),
 		Err(e) => {
 			println!("Failed to find image with error '{:}'", e);
@@ -177,7 +367,7 @@ extern "C" fn gl_client_find_image_data<'a>(
 
 #[no_mangle]
 extern "C" fn gl_client_image_data_guard_read<'a>(
-	image_data_guard: *const ClientImageDataGuard<'a>,
+	image_data_guard: *const ClientImageDataGuard,
 ) -> &'a ShmemDataInternal {
 	&unsafe { image_data_guard.as_ref() }.unwrap().image_data
 }
@@ -194,6 +384,337 @@ extern "C" fn gl_client_image_data_guard_destroy(
 	drop(unsafe { Box::from_raw(image_data_guard) });
 }
 
+#[no_mangle]
+extern "C" fn gl_client_list_images(gl_client: *mut GlClient) -> *mut ClientImageList {
+	let res = unsafe { gl_client.as_mut() }.unwrap().list_images();
+
+	match res {
+		Ok(Some(images)) => {
+			let images = images.iter().map(CImgListEntry::from).collect();
+			return Box::into_raw(Box::new(ClientImageList { images }));
+		}
+		Ok(None) => {
+			println!("Timed out while listing images");
+			return null_mut();
+		}
+		Err(e) => {
+			println!("Failed to list images with error '{:}'", e);
+			return null_mut();
+		}
+	}
+}
+
+// List the images whose name matches a glob pattern like "studio/cam1/*"
+#[no_mangle]
+extern "C" fn gl_client_list_images_matching(
+	gl_client: *mut GlClient,
+	pattern: *const c_char,
+) -> *mut ClientImageList {
+	let res = unsafe { gl_client.as_mut() }
+		.unwrap()
+		.list_images_matching(&get_str(&pattern));
+
+	match res {
+		Ok(Some(images)) => {
+			let images = images.iter().map(CImgListEntry::from).collect();
+			return Box::into_raw(Box::new(ClientImageList { images }));
+		}
+		Ok(None) => {
+			println!("Timed out while listing images");
+			return null_mut();
+		}
+		Err(e) => {
+			println!("Failed to list images with error '{:}'", e);
+			return null_mut();
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_image_list_len(image_list: *const ClientImageList) -> usize {
+	unsafe { image_list.as_ref() }.map_or(0, |x| x.images.len())
+}
+
+#[no_mangle]
+extern "C" fn gl_client_image_list_get(
+	image_list: *const ClientImageList,
+	index: usize,
+) -> *const CImgListEntry {
+	match unsafe { image_list.as_ref() }.and_then(|x| x.images.get(index)) {
+		Some(entry) => entry,
+		None => ptr::null(),
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_image_list_destroy(image_list: Option<NonNull<ClientImageList>>) {
+	if let Some(image_list) = image_list {
+		drop(unsafe { Box::from_raw(image_list.as_ptr()) });
+	}
+}
+
+// A NULL value removes the entry
+#[no_mangle]
+extern "C" fn gl_client_set_image_metadata(
+	gl_client: *mut GlClient,
+	image_name: *const c_char,
+	key: *const c_char,
+	value: *const c_char,
+) -> ImageLookupResult {
+	let value = match value.is_null() {
+		true => None,
+		false => Some(get_str(&value)),
+	};
+	let res = unsafe { gl_client.as_mut() }.unwrap().set_image_metadata(
+		&get_str(&image_name),
+		&get_str(&key),
+		value.as_deref(),
+	);
+
+	match res {
+		Ok(Some(_)) => return ImageLookupResult::Found,
+		Ok(None) => return ImageLookupResult::NotFound,
+		Err(e) => {
+			println!("Failed to set image metadata with err '{:}'", e);
+			return ImageLookupResult::Error;
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_get_image_metadata(
+	gl_client: *mut GlClient,
+	image_name: *const c_char,
+) -> *mut ClientImageMetadata {
+	let res = unsafe { gl_client.as_mut() }
+		.unwrap()
+		.get_image_metadata(&get_str(&image_name));
+
+	match res {
+		Ok(Some(entries)) => {
+			let entries = entries.iter().map(CImgMetadataEntry::from).collect();
+			return Box::into_raw(Box::new(ClientImageMetadata { entries }));
+		}
+		Ok(None) => return null_mut(),
+		Err(e) => {
+			println!("Failed to get image metadata with error '{:}'", e);
+			return null_mut();
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_image_metadata_len(metadata: *const ClientImageMetadata) -> usize {
+	unsafe { metadata.as_ref() }.map_or(0, |x| x.entries.len())
+}
+
+#[no_mangle]
+extern "C" fn gl_client_image_metadata_get(
+	metadata: *const ClientImageMetadata,
+	index: usize,
+) -> *const CImgMetadataEntry {
+	match unsafe { metadata.as_ref() }.and_then(|x| x.entries.get(index)) {
+		Some(entry) => entry,
+		None => ptr::null(),
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_image_metadata_destroy(metadata: Option<NonNull<ClientImageMetadata>>) {
+	if let Some(metadata) = metadata {
+		drop(unsafe { Box::from_raw(metadata.as_ptr()) });
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_get_stats(gl_client: *mut GlClient) -> *mut ClientServerStats {
+	let res = unsafe { gl_client.as_mut() }.unwrap().get_stats();
+
+	match res {
+		Ok(Some(stats)) => {
+			let images = stats.images.iter().map(CImgStatsEntry::from).collect();
+			return Box::into_raw(Box::new(ClientServerStats { stats, images }));
+		}
+		Ok(None) => {
+			println!("Timed out while requesting server stats");
+			return null_mut();
+		}
+		Err(e) => {
+			println!("Failed to get server stats with error '{:}'", e);
+			return null_mut();
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_stats_connection_count(stats: *const ClientServerStats) -> u32 {
+	unsafe { stats.as_ref() }.map_or(0, |x| x.stats.connection_count)
+}
+
+#[no_mangle]
+extern "C" fn gl_client_stats_gpu_copy_count(stats: *const ClientServerStats) -> u64 {
+	unsafe { stats.as_ref() }.map_or(0, |x| x.stats.gpu_copy_count)
+}
+
+#[no_mangle]
+extern "C" fn gl_client_stats_image_len(stats: *const ClientServerStats) -> usize {
+	unsafe { stats.as_ref() }.map_or(0, |x| x.images.len())
+}
+
+#[no_mangle]
+extern "C" fn gl_client_stats_image_get(
+	stats: *const ClientServerStats,
+	index: usize,
+) -> *const CImgStatsEntry {
+	match unsafe { stats.as_ref() }.and_then(|x| x.images.get(index)) {
+		Some(entry) => entry,
+		None => ptr::null(),
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_stats_device_len(stats: *const ClientServerStats) -> usize {
+	unsafe { stats.as_ref() }.map_or(0, |x| x.stats.devices.len())
+}
+
+#[no_mangle]
+extern "C" fn gl_client_stats_device_get(
+	stats: *const ClientServerStats,
+	index: usize,
+) -> *const DeviceStatsEntry {
+	match unsafe { stats.as_ref() }.and_then(|x| x.stats.devices.get(index)) {
+		Some(entry) => entry,
+		None => ptr::null(),
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_stats_destroy(stats: Option<NonNull<ClientServerStats>>) {
+	if let Some(stats) = stats {
+		drop(unsafe { Box::from_raw(stats.as_ptr()) });
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_subscribe(
+	gl_client: *mut GlClient,
+	image_name: *const c_char,
+	subscribe: bool,
+) -> ImageLookupResult {
+	let res = unsafe { gl_client.as_mut() }
+		.unwrap()
+		.subscribe(&get_str(&image_name), subscribe);
+
+	match res {
+		Ok(Some(true)) => return ImageLookupResult::Found,
+		Ok(_) => return ImageLookupResult::NotFound,
+		Err(e) => {
+			println!("Failed to subscribe to image with err '{:}'", e);
+			return ImageLookupResult::Error;
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_poll_event(gl_client: *mut GlClient, event: *mut CImgEvent) -> c_int {
+	let res = unsafe { gl_client.as_mut() }.unwrap().poll_event();
+
+	match res {
+		Ok(Some(e)) => {
+			unsafe { event.write(CImgEvent::from(&e)) };
+			return 1;
+		}
+		Ok(None) => return 0,
+		Err(e) => {
+			println!("Failed to poll event with error '{:}'", e);
+			return -1;
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_set_heartbeat_interval(
+	gl_client: *mut GlClient,
+	interval_in_millis: u64,
+) -> c_int {
+	let interval = match interval_in_millis {
+		0 => None,
+		millis => Some(Duration::from_millis(millis)),
+	};
+	let res = unsafe { gl_client.as_mut() }
+		.unwrap()
+		.set_heartbeat_interval(interval);
+
+	match res {
+		Ok(_) => 1,
+		Err(Error::ServerUnresponsive(_)) => 0,
+		Err(e) => {
+			println!("Failed to set heartbeat interval with error '{:}'", e);
+			-1
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_ping(gl_client: *mut GlClient) -> c_int {
+	let res = unsafe { gl_client.as_mut() }.unwrap().ping();
+
+	match res {
+		Ok(_) => 1,
+		Err(Error::ServerUnresponsive(_)) => 0,
+		Err(e) => {
+			println!("Failed to ping server with error '{:}'", e);
+			-1
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn gl_client_heartbeat(gl_client: *mut GlClient) -> c_int {
+	let res = unsafe { gl_client.as_mut() }.unwrap().heartbeat();
+
+	match res {
+		Ok(_) => 1,
+		Err(Error::ServerUnresponsive(_)) => 0,
+		Err(e) => {
+			println!("Failed to send heartbeat with error '{:}'", e);
+			-1
+		}
+	}
+}
+
+// max_attempts == 0 disables reconnecting
+#[no_mangle]
+extern "C" fn gl_client_set_reconnect_policy(
+	gl_client: *mut GlClient,
+	max_attempts: u32,
+	retry_interval_in_millis: u64,
+) {
+	let policy = match max_attempts {
+		0 => None,
+		max_attempts => Some(ReconnectPolicy::new(
+			max_attempts,
+			Duration::from_millis(retry_interval_in_millis),
+		)),
+	};
+	unsafe { gl_client.as_mut() }
+		.unwrap()
+		.set_reconnect_policy(policy);
+}
+
+#[no_mangle]
+extern "C" fn gl_client_reconnect(gl_client: *mut GlClient) -> c_int {
+	let res = unsafe { gl_client.as_mut() }.unwrap().reconnect();
+
+	match res {
+		Ok(_) => 1,
+		Err(e) => {
+			println!("Failed to reconnect to server with error '{:}'", e);
+			-1
+		}
+	}
+}
+
 #[no_mangle]
 extern "C" fn gl_client_send_image(
 	gl_client: *mut GlClient,
@@ -246,12 +767,37 @@ extern "C" fn gl_client_recv_image(
 	invert: bool,
 	prev_fbo: glad::GLuint,
 	extents: *const GlImageExtent,
+) -> c_int {
+	gl_client_recv_image_frame(
+		gl_client,
+		image_name,
+		dst_texture_id,
+		dst_texture_target,
+		invert,
+		prev_fbo,
+		extents,
+		null_mut(),
+	)
+}
+
+// Like gl_client_recv_image, but also writes the number and timestamp of the received frame to
+// frame_info if it isn't NULL
+#[no_mangle]
+extern "C" fn gl_client_recv_image_frame(
+	gl_client: *mut GlClient,
+	image_name: *const c_char,
+	dst_texture_id: glad::GLuint,
+	dst_texture_target: glad::GLenum,
+	invert: bool,
+	prev_fbo: glad::GLuint,
+	extents: *const GlImageExtent,
+	frame_info: *mut ImgFrameInfo,
 ) -> c_int {
 	let gl_client = unsafe { gl_client.as_mut() }.unwrap();
 	let image_name = &get_str(&image_name);
 
 	let res = match extents.is_null() {
-		true => gl_client.recv_image(
+		true => gl_client.recv_image_frame(
 			image_name,
 			dst_texture_id,
 			dst_texture_target,
@@ -259,7 +805,7 @@ extern "C" fn gl_client_recv_image(
 			prev_fbo,
 		),
 		false => unsafe {
-			gl_client.recv_image_with_extents(
+			gl_client.recv_image_frame_with_extents(
 				image_name,
 				dst_texture_id,
 				dst_texture_target,
@@ -271,7 +817,12 @@ extern "C" fn gl_client_recv_image(
 	};
 
 	match res {
-		Ok(Some(_)) => return 1,
+		Ok(Some(info)) => {
+			if let Some(frame_info) = unsafe { frame_info.as_mut() } {
+				*frame_info = info;
+			}
+			return 1;
+		}
 		Ok(None) => return 0,
 		Err(e) => {
 			println!("Failed to send image with error '{:}'", e);
@@ -279,3 +830,30 @@ extern "C" fn gl_client_recv_image(
 		}
 	}
 }
+
+// Read the last frame of an image without copying it. Returns 1 if frame_info was written, 0
+// if the image wasn't found and -1 on error
+#[no_mangle]
+extern "C" fn gl_client_get_frame_info(
+	gl_client: *mut GlClient,
+	image_name: *const c_char,
+	frame_info: *mut ImgFrameInfo,
+) -> c_int {
+	let res = unsafe { gl_client.as_ref() }
+		.unwrap()
+		.get_frame_info(&get_str(&image_name));
+
+	match res {
+		Ok(Some(info)) => {
+			if let Some(frame_info) = unsafe { frame_info.as_mut() } {
+				*frame_info = info;
+			}
+			return 1;
+		}
+		Ok(None) => return 0,
+		Err(e) => {
+			println!("Failed to read frame info with error '{:}'", e);
+			return -1;
+		}
+	}
+}
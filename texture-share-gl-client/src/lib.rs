@@ -5,5 +5,7 @@ mod opengl;
 // cbindgen:ignore
 mod gl_client;
 
+#[cfg(feature = "tokio")]
+pub use gl_client::AsyncGlClient;
 pub use gl_client::GlClient;
 pub use opengl::gl_shared_image;
@@ -1,6 +1,182 @@
+use std::ffi::{c_char, CString};
+
+use crate::platform::img_data::{
+	ImgEvent, ImgEventKind, ImgFormat, ImgListEntry, ImgMetadataEntry, ImgStatsEntry,
+	IMG_LIST_MAX_GPU_DEVICES,
+};
 use crate::platform::ShmemDataInternal;
 
+// Name handed to C as a NUL-terminated UTF-8 string, owned by the struct containing it. ptr is
+// NULL for empty names
+#[repr(C)]
+pub struct CImgName {
+	ptr: *mut c_char,
+	len: usize,
+}
+
+// C view of an ImgEvent, free it with img_event_destroy
+#[repr(C)]
+pub struct CImgEvent {
+	pub kind: ImgEventKind,
+	pub image_name: CImgName,
+	pub data: ShmemDataInternal,
+}
+
+// C view of an ImgListEntry
+#[repr(C)]
+pub struct CImgListEntry {
+	pub name: CImgName,
+	pub handle_id: u32,
+	pub width: u32,
+	pub height: u32,
+	pub format: ImgFormat,
+	pub gpu_device_count: u32,
+	pub gpu_device_uuids: [[u64; 2]; IMG_LIST_MAX_GPU_DEVICES],
+}
+
+// C view of an ImgStatsEntry
+#[repr(C)]
+pub struct CImgStatsEntry {
+	pub image: CImgListEntry,
+	pub allocation_size: u64,
+	pub producer_count: u32,
+	pub consumer_count: u32,
+	pub last_update_millis: u64,
+}
+
+// C view of an ImgMetadataEntry
+#[repr(C)]
+pub struct CImgMetadataEntry {
+	pub key: CImgName,
+	pub value: CImgName,
+}
+
+impl CImgName {
+	// C strings end at the first NUL byte, so names are truncated there
+	pub fn new(name: &str) -> CImgName {
+		let name = name.split('\0').next().unwrap_or_default();
+		if name.is_empty() {
+			return CImgName::default();
+		}
+
+		CImgName {
+			len: name.len(),
+			ptr: CString::new(name).unwrap().into_raw(),
+		}
+	}
+
+	pub fn as_str(&self) -> &str {
+		if self.ptr.is_null() {
+			return "";
+		}
+
+		// ptr and len are private and always describe the valid UTF-8 string allocated by new
+		unsafe {
+			std::str::from_utf8_unchecked(std::slice::from_raw_parts(
+				self.ptr.cast::<u8>(),
+				self.len,
+			))
+		}
+	}
+}
+
+impl Default for CImgName {
+	fn default() -> Self {
+		CImgName {
+			ptr: std::ptr::null_mut(),
+			len: 0,
+		}
+	}
+}
+
+impl Drop for CImgName {
+	fn drop(&mut self) {
+		if !self.ptr.is_null() {
+			drop(unsafe { CString::from_raw(self.ptr) });
+		}
+	}
+}
+
+impl From<&ImgEvent> for CImgEvent {
+	fn from(value: &ImgEvent) -> Self {
+		CImgEvent {
+			kind: value.kind,
+			image_name: CImgName::new(&value.image_name),
+			data: value.data.clone(),
+		}
+	}
+}
+
+impl From<&ImgListEntry> for CImgListEntry {
+	fn from(value: &ImgListEntry) -> Self {
+		CImgListEntry {
+			name: CImgName::new(&value.name),
+			handle_id: value.handle_id,
+			width: value.width,
+			height: value.height,
+			format: value.format,
+			gpu_device_count: value.gpu_device_count,
+			gpu_device_uuids: value.gpu_device_uuids,
+		}
+	}
+}
+
+impl From<&ImgStatsEntry> for CImgStatsEntry {
+	fn from(value: &ImgStatsEntry) -> Self {
+		CImgStatsEntry {
+			image: CImgListEntry::from(&value.image),
+			allocation_size: value.allocation_size,
+			producer_count: value.producer_count,
+			consumer_count: value.consumer_count,
+			last_update_millis: value.last_update_millis,
+		}
+	}
+}
+
+impl From<&ImgMetadataEntry> for CImgMetadataEntry {
+	fn from(value: &ImgMetadataEntry) -> Self {
+		CImgMetadataEntry {
+			key: CImgName::new(&value.key),
+			value: CImgName::new(&value.value),
+		}
+	}
+}
+
 #[no_mangle]
 extern "C" fn shmem_data_internal_default() -> ShmemDataInternal {
 	ShmemDataInternal::default()
 }
+
+#[no_mangle]
+extern "C" fn img_list_entry_default() -> CImgListEntry {
+	CImgListEntry::from(&ImgListEntry::default())
+}
+
+#[no_mangle]
+extern "C" fn img_event_default() -> CImgEvent {
+	CImgEvent::from(&ImgEvent::default())
+}
+
+// Free the name of an event returned by poll_event. The event is reset to its default
+#[no_mangle]
+extern "C" fn img_event_destroy(event: *mut CImgEvent) {
+	if let Some(event) = unsafe { event.as_mut() } {
+		*event = img_event_default();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::CImgName;
+
+	#[test]
+	fn c_img_name() {
+		assert_eq!(CImgName::new("img").as_str(), "img");
+		assert_eq!(CImgName::new("").as_str(), "");
+
+		// The stored string and its length match, even for names C can't represent
+		let name = CImgName::new("img\0suffix");
+		assert_eq!(name.as_str(), "img");
+		assert_eq!(CImgName::new("\0img").as_str(), "");
+	}
+}
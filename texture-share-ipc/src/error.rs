@@ -0,0 +1,132 @@
+use std::fmt::Display;
+use std::io::{self, ErrorKind};
+use std::time::Duration;
+
+use ash::vk;
+use shared_memory::ShmemError;
+
+use crate::platform::ipc_commands::ServerError;
+
+#[derive(Debug)]
+pub enum Error {
+	// Socket or process error
+	Io(io::Error),
+	// The peer did not answer in time
+	Timeout(String),
+	// The peer sent data that does not follow the protocol
+	Protocol(String),
+	// Name does not fit into a fixed size name buffer
+	NameTooLong { name: String, max_len: usize },
+	// Name is empty or contains characters that can't be sent
+	InvalidName { name: String, reason: String },
+	Shmem(ShmemError),
+	Lock(String),
+	// The server failed to process a command
+	Server(ServerError),
+	// The server did not answer a ping within the given time
+	ServerUnresponsive(Duration),
+	// Encoded metadata entries don't fit into a shared memory segment
+	MetadataTooLarge { len: usize, max_len: usize },
+	// Shared memory segment was created with a different layout, e.g. by another library version
+	IncompatibleShmem { name: String, reason: String },
+	// Vulkan call of a client failed
+	Vulkan(vk::Result),
+	// OpenGL call of a client failed with the given error code
+	Gl(u32),
+}
+
+impl Error {
+	// True if the peer closed the connection. Such connections are unusable
+	pub fn is_disconnect(&self) -> bool {
+		match self {
+			Error::Io(e) => matches!(
+				e.kind(),
+				ErrorKind::BrokenPipe
+					| ErrorKind::ConnectionReset
+					| ErrorKind::ConnectionAborted
+					| ErrorKind::UnexpectedEof
+			),
+			_ => false,
+		}
+	}
+
+	// True if the peer did not answer in time. Unlike disconnects, the peer may still be alive,
+	// e.g. while it is busy with a large copy
+	pub fn is_timeout(&self) -> bool {
+		match self {
+			Error::Io(e) => matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock),
+			Error::Timeout(_) | Error::ServerUnresponsive(_) => true,
+			_ => false,
+		}
+	}
+}
+
+impl Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Error::Io(e) => write!(f, "IO error: {}", e),
+			Error::Timeout(msg) => write!(f, "Timeout: {}", msg),
+			Error::Protocol(msg) => write!(f, "Protocol error: {}", msg),
+			Error::NameTooLong { name, max_len } => write!(
+				f,
+				"Name '{}' too long. Should be at most {} bytes",
+				name, max_len
+			),
+			Error::InvalidName { name, reason } => write!(f, "Invalid name '{}': {}", name, reason),
+			Error::Shmem(e) => write!(f, "Shared memory error: {}", e),
+			Error::Lock(msg) => write!(f, "Lock error: {}", msg),
+			Error::Server(e) => e.fmt(f),
+			Error::ServerUnresponsive(timeout) => {
+				write!(f, "Server did not respond within {:?}", timeout)
+			}
+			Error::MetadataTooLarge { len, max_len } => write!(
+				f,
+				"Image metadata of {} bytes too large. Should be at most {} bytes",
+				len, max_len
+			),
+			Error::IncompatibleShmem { name, reason } => write!(
+				f,
+				"Incompatible shared memory segment '{}': {}",
+				name, reason
+			),
+			Error::Vulkan(e) => write!(f, "Vulkan error: {}", e),
+			Error::Gl(code) => write!(f, "GL error: {}", code),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io(e) => Some(e),
+			Error::Shmem(e) => Some(e),
+			Error::Server(e) => Some(e),
+			Error::Vulkan(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<io::Error> for Error {
+	fn from(value: io::Error) -> Self {
+		Error::Io(value)
+	}
+}
+
+impl From<ShmemError> for Error {
+	fn from(value: ShmemError) -> Self {
+		Error::Shmem(value)
+	}
+}
+
+impl From<ServerError> for Error {
+	fn from(value: ServerError) -> Self {
+		Error::Server(value)
+	}
+}
+
+impl From<vk::Result> for Error {
+	fn from(value: vk::Result) -> Self {
+		Error::Vulkan(value)
+	}
+}
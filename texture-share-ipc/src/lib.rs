@@ -3,10 +3,15 @@
 
 pub use uuid;
 
-mod bindings;
+pub mod bindings;
+mod error;
 
 // cbindgen:ignore
 pub mod platform;
 
+pub use error::Error;
 pub use platform::ipc_shmem::IpcShmem;
 pub use platform::ipc_unix_socket::{IpcConnection, IpcSocket};
+#[cfg(feature = "tokio")]
+pub use platform::ipc_wait::TokioWait;
+pub use platform::ipc_wait::{BlockingWait, IpcWait};
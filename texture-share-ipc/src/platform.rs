@@ -1,6 +1,7 @@
 pub mod daemon_launch;
 pub mod img_data;
 pub mod ipc_commands;
+pub mod ipc_protocol;
 pub mod ipc_shmem;
 
 #[cfg(target_os = "linux")]
@@ -8,6 +9,7 @@ mod linux;
 #[cfg(target_os = "linux")]
 pub(crate) use linux::*;
 
+pub(crate) use ipc_shmem_lock::ShmemLock;
 pub use ipc_shmem_lock::{LockGuard, ReadLockGuard, RwLockInternalData};
 
 pub use ipc_shmem::ShmemDataInternal;
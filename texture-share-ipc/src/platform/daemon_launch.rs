@@ -1,9 +1,121 @@
 use std::{
-	io::Error,
+	io::ErrorKind,
 	process::{self, Child},
-	time::{Duration, SystemTime},
+	thread,
+	time::{Duration, Instant},
 };
 
+use crate::{Error, IpcConnection};
+
+// Delay between connection attempts while the daemon starts up
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+// Arguments of server_connect_and_daemon_launch. Kept by clients to relaunch the server after it
+// stopped
+#[derive(Clone, Debug)]
+pub struct DaemonLaunchConfig {
+	pub program_path: String,
+	pub lock_file_path: String,
+	pub socket_path: String,
+	pub shmem_prefix: String,
+	pub socket_timeout: Duration,
+	pub connection_wait_timeout: Duration,
+	pub ipc_timeout: Duration,
+	pub lockfile_timeout: Duration,
+	pub spawn_timeout: Duration,
+}
+
+// How clients restore a broken connection to the server
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+	// Connection attempts before giving up
+	pub max_attempts: u32,
+	// Delay between attempts
+	pub retry_interval: Duration,
+}
+
+impl DaemonLaunchConfig {
+	// Connect to client_socket_path and join namespace, launching the server if it isn't running
+	pub fn connect(
+		&self,
+		client_socket_path: &str,
+		namespace: &str,
+		client_timeout: Duration,
+		gpu_device_uuid: Option<uuid::Uuid>,
+	) -> Result<Option<IpcConnection>, Error> {
+		// A socket without server refuses connections, launch the server in that case
+		let conn_fn = || match IpcConnection::try_connect_namespace(
+			client_socket_path,
+			namespace,
+			client_timeout,
+		) {
+			Err(Error::Io(e)) if e.kind() == ErrorKind::ConnectionRefused => Ok(None),
+			s => s,
+		};
+
+		server_connect_and_daemon_launch(
+			&self.program_path,
+			&self.lock_file_path,
+			&self.socket_path,
+			&self.shmem_prefix,
+			self.socket_timeout,
+			self.connection_wait_timeout,
+			self.ipc_timeout,
+			self.lockfile_timeout,
+			self.spawn_timeout,
+			gpu_device_uuid,
+			&conn_fn,
+		)
+	}
+}
+
+impl ReconnectPolicy {
+	pub fn new(max_attempts: u32, retry_interval: Duration) -> ReconnectPolicy {
+		ReconnectPolicy {
+			max_attempts,
+			retry_interval,
+		}
+	}
+
+	// Connect to socket_path, relaunching the server through daemon_launch if given. Returns the
+	// error of the last attempt if all of them failed
+	pub fn connect(
+		&self,
+		socket_path: &str,
+		namespace: &str,
+		timeout: Duration,
+		daemon_launch: Option<&DaemonLaunchConfig>,
+		gpu_device_uuid: Option<uuid::Uuid>,
+	) -> Result<IpcConnection, Error> {
+		let mut last_err = None;
+		for attempt in 0..self.max_attempts.max(1) {
+			if attempt > 0 {
+				thread::sleep(self.retry_interval);
+			}
+
+			let res = match daemon_launch {
+				Some(config) => config.connect(socket_path, namespace, timeout, gpu_device_uuid),
+				None => IpcConnection::try_connect_namespace(socket_path, namespace, timeout),
+			};
+			match res {
+				Ok(Some(connection)) => return Ok(connection),
+				Ok(None) => {
+					last_err = Some(Error::Timeout(format!("Connecting to '{}'", socket_path)))
+				}
+				Err(e) => last_err = Some(e),
+			}
+		}
+
+		Err(last_err.unwrap())
+	}
+}
+
+impl Default for ReconnectPolicy {
+	fn default() -> Self {
+		ReconnectPolicy::new(5, Duration::from_millis(500))
+	}
+}
+
 // Tries to connect to server. If that fails, spawn daemon and retry
 pub fn server_connect_and_daemon_launch<T>(
 	program_path: &str,
@@ -18,7 +130,7 @@ pub fn server_connect_and_daemon_launch<T>(
 	gpu_device_uuid: Option<uuid::Uuid>,
 	f: &dyn Fn() -> Result<Option<T>, Error>,
 ) -> Result<Option<T>, Error> {
-	let stop_time = SystemTime::now() + spawn_timeout;
+	let stop_time = Instant::now() + spawn_timeout;
 	let mut child = Box::new(None);
 	let conn: Result<Option<T>, Error> = loop {
 		// Execute function to launch and connect client
@@ -41,10 +153,12 @@ pub fn server_connect_and_daemon_launch<T>(
 			break Ok(conn);
 		}
 
-		if SystemTime::now() > stop_time {
+		if Instant::now() > stop_time {
 			// Kill child if no connection could be established in time
 			break Ok(None);
 		}
+
+		thread::sleep(RETRY_INTERVAL.min(stop_time.saturating_duration_since(Instant::now())));
 	};
 
 	if conn.is_ok() {
@@ -52,8 +166,8 @@ pub fn server_connect_and_daemon_launch<T>(
 		return conn;
 	} else {
 		// If an error occured or the connection could not be established in time, kill child
-		if child.is_some() {
-			child.unwrap().kill()?;
+		if let Some(mut child) = *child {
+			child.kill()?;
 		}
 		return Ok(None);
 	}
@@ -79,14 +193,14 @@ fn try_connect<T>(
 	}
 
 	// (Re-)start child if process has not been started or has exited
-	if child.is_none() || child.as_mut().as_mut().unwrap().try_wait()?.is_some() {
+	let child_exited = match child.as_mut() {
+		Some(c) => c.try_wait()?.is_some(),
+		None => true,
+	};
+	if child_exited {
 		// Kill running process
-		if child.is_some() {
-			child
-				.as_mut()
-				.as_mut()
-				.expect("Failed to get child process handle")
-				.kill()?;
+		if let Some(c) = child.as_mut() {
+			c.kill()?;
 		}
 
 		// Spawn new process
@@ -136,5 +250,6 @@ fn spawn(
 		));
 	}
 
-	process::Command::new(program_path).args(args).spawn()
+	let child = process::Command::new(program_path).args(args).spawn()?;
+	Ok(child)
 }
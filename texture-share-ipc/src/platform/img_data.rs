@@ -1,30 +1,229 @@
 use std::mem::size_of;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use super::ipc_protocol::{decode_string, impl_wire_format, impl_wire_format_enum, WireFormat};
+use super::ipc_shmem::get_monotonic_time;
 use super::ShmemDataInternal;
+use crate::Error;
 
-pub(crate) type ImgName = [u8; 1024];
-pub(crate) type ShmemName = [u8; 1024];
+// Longest accepted image or shared memory name in bytes
+pub const IMG_NAME_MAX_LEN: usize = 1024;
+
+// Validate a name before it is sent to the server
+pub fn img_name(name: &str) -> Result<String, Error> {
+	if name.is_empty() {
+		return Err(Error::InvalidName {
+			name: name.to_string(),
+			reason: "Name is empty".to_string(),
+		});
+	}
+
+	img_name_or_empty(name)
+}
+
+// Like img_name, but accepts empty names, e.g. for the default namespace
+pub fn img_name_or_empty(name: &str) -> Result<String, Error> {
+	if name.len() > IMG_NAME_MAX_LEN {
+		return Err(Error::NameTooLong {
+			name: name.to_string(),
+			max_len: IMG_NAME_MAX_LEN,
+		});
+	}
+
+	img_name_unlimited(name)
+}
+
+// Names are handed to C as NUL-terminated strings. Metadata values are limited by
+// IMG_METADATA_MAX_LEN instead of IMG_NAME_MAX_LEN
+fn img_name_unlimited(name: &str) -> Result<String, Error> {
+	if name.contains('\0') {
+		return Err(Error::InvalidName {
+			name: name.to_string(),
+			reason: "Name contains NUL".to_string(),
+		});
+	}
+
+	Ok(name.to_string())
+}
+
+// Match an image name against a glob pattern. '*' matches any sequence of characters including
+// '/', so that "studio/cam1/*" selects all images below studio/cam1/. '?' matches a single
+// character, all other characters only themselves
+pub fn img_name_matches(name: &str, pattern: &str) -> bool {
+	let name = name.chars().collect::<Vec<_>>();
+	let pattern = pattern.chars().collect::<Vec<_>>();
+
+	// Position after the last '*' and the name position it currently covers up to
+	let mut star: Option<(usize, usize)> = None;
+	let (mut ni, mut pi) = (0, 0);
+	while ni < name.len() {
+		match pattern.get(pi) {
+			Some('*') => {
+				pi += 1;
+				star = Some((pi, ni));
+			}
+			Some(c) if *c == '?' || *c == name[ni] => {
+				pi += 1;
+				ni += 1;
+			}
+			// Let the last '*' cover one more character and retry
+			_ => match star {
+				Some((star_pi, star_ni)) => {
+					pi = star_pi;
+					ni = star_ni + 1;
+					star = Some((star_pi, ni));
+				}
+				None => return false,
+			},
+		}
+	}
+
+	pattern[pi..].iter().all(|c| *c == '*')
+}
+
+// Largest accepted image width and height
+pub const IMG_MAX_DIMENSION: u32 = 32768;
+
+// Most images a single name can be buffered with. Keeps the file descriptors of a full
+// FindImages reply below the SCM_RIGHTS limit
+pub const IMG_MAX_BUFFER_COUNT: u32 = 4;
+
+// Space for the encoded metadata entries of an image in its shared memory segment
+pub const IMG_METADATA_MAX_LEN: usize = 8192;
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
 pub enum ImgFormat {
 	R8G8B8A8,
 	R8G8B8,
 	B8G8R8A8,
 	B8G8R8,
+	#[default]
 	Undefined,
 }
 
 #[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImgEventKind {
+	// The image was (re)initialized, local copies have to be updated with find_image
+	ImageResized,
+	ImageRemoved,
+	ProducerDisconnected,
+	// An entry was set or removed, read the new entries with get_image_metadata
+	MetadataChanged,
+}
+
+#[repr(C)]
+#[derive(Clone)]
+pub struct ImgEvent {
+	pub kind: ImgEventKind,
+	pub image_name: String,
+	pub data: ShmemDataInternal,
+}
+
+// Maximum number of GPU copies reported per image by ListImages
+pub const IMG_LIST_MAX_GPU_DEVICES: usize = 8;
+
+#[repr(C)]
+#[derive(Clone)]
+pub struct ImgListEntry {
+	pub name: String,
+	pub handle_id: u32,
+	pub width: u32,
+	pub height: u32,
+	pub format: ImgFormat,
+	pub gpu_device_count: u32,
+	pub gpu_device_uuids: [[u64; 2]; IMG_LIST_MAX_GPU_DEVICES],
+}
+
+#[repr(C)]
+#[derive(Clone, Default)]
+pub struct ImgStatsEntry {
+	pub image: ImgListEntry,
+	// Size of all buffers of one GPU copy
+	pub allocation_size: u64,
+	pub producer_count: u32,
+	// Connections that imported the image without creating it
+	pub consumer_count: u32,
+	// Unix time in milliseconds of the last InitImage or CopyImage, 0 if never updated
+	pub last_update_millis: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Default)]
+pub struct DeviceStatsEntry {
+	pub gpu_device_uuid: [u64; 2],
+	pub image_count: u32,
+	// Bytes of GPU memory allocated for shared images
+	pub memory_usage: u64,
+}
+
+// Frame written by send_image, read from ShmemDataInternal::get_frame_info
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ImgFrameInfo {
+	pub frame_number: u64,
+	// CLOCK_MONOTONIC time at which the frame was written
+	pub timestamp_nanos: u64,
+}
+
+// Key/value pair attached to an image, e.g. its color space or a label. Keys are unique per image
+#[repr(C)]
+#[derive(Clone, Default, PartialEq)]
+pub struct ImgMetadataEntry {
+	pub key: String,
+	pub value: String,
+}
+
+#[repr(C)]
+#[derive(Clone, Default)]
 pub struct ImgData {
-	pub shmem_name: ShmemName,
+	pub shmem_name: String,
+	pub image_name: String,
 	pub data: ShmemDataInternal,
 }
 
+impl ImgFrameInfo {
+	// Time since the frame was written, e.g. to measure the latency between producer and consumer
+	pub fn get_age(&self) -> Duration {
+		get_monotonic_time().saturating_sub(Duration::from_nanos(self.timestamp_nanos))
+	}
+}
+
+impl ImgMetadataEntry {
+	// Keys must not be empty, values may be. Values are decoded with the same limit on the server
+	pub fn new(key: &str, value: &str) -> Result<ImgMetadataEntry, Error> {
+		if value.len() > IMG_METADATA_MAX_LEN {
+			return Err(Error::MetadataTooLarge {
+				len: value.len(),
+				max_len: IMG_METADATA_MAX_LEN,
+			});
+		}
+
+		Ok(ImgMetadataEntry {
+			key: img_name(key)?,
+			value: img_name_unlimited(value)?,
+		})
+	}
+
+	pub fn get_key(&self) -> &str {
+		&self.key
+	}
+
+	pub fn get_value(&self) -> &str {
+		&self.value
+	}
+
+	// Bytes needed to store the entry in a shared memory segment
+	pub fn encoded_len(&self) -> usize {
+		2 * size_of::<u32>() + self.key.len() + self.value.len()
+	}
+}
+
 impl ImgData {
 	pub fn new(
-		shmem_name: ShmemName,
-		image_name: ImgName,
+		shmem_name: String,
+		image_name: String,
 		handle_id: u32,
 		width: u32,
 		height: u32,
@@ -34,8 +233,8 @@ impl ImgData {
 	) -> ImgData {
 		ImgData {
 			shmem_name,
+			image_name,
 			data: ShmemDataInternal::new(
-				image_name,
 				handle_id,
 				width,
 				height,
@@ -47,52 +246,260 @@ impl ImgData {
 	}
 
 	pub fn from_shmem_data_internal(
-		shmem_name: ShmemName,
+		shmem_name: String,
+		image_name: String,
 		shmem_data_internal: ShmemDataInternal,
 	) -> ImgData {
 		ImgData {
 			shmem_name,
+			image_name,
 			data: shmem_data_internal,
 		}
 	}
+}
+
+impl ImgListEntry {
+	pub fn new(
+		name: String,
+		shmem_data_internal: &ShmemDataInternal,
+		gpu_device_uuids: &[u128],
+	) -> ImgListEntry {
+		let mut entry = ImgListEntry {
+			name,
+			handle_id: shmem_data_internal.handle_id,
+			width: shmem_data_internal.width,
+			height: shmem_data_internal.height,
+			format: shmem_data_internal.format,
+			gpu_device_count: 0,
+			gpu_device_uuids: [[0u64; 2]; IMG_LIST_MAX_GPU_DEVICES],
+		};
 
-	pub fn convert_shmem_str_to_array(shmem_name: &str) -> ShmemName {
-		// Generate ResultMsg data
-		let shmem_name_array = shmem_name.as_bytes().to_owned();
-		let shmem_name_len = shmem_name_array.len();
-		if shmem_name_len >= size_of::<ShmemName>() {
-			panic!("Shmem Name '{}' too long. Stopping", shmem_name);
+		// Additional GPU copies are dropped from the list
+		for gpu_device_uuid in gpu_device_uuids.iter().take(IMG_LIST_MAX_GPU_DEVICES) {
+			let pair = uuid::Uuid::from_u128(*gpu_device_uuid).as_u64_pair();
+			entry.gpu_device_uuids[entry.gpu_device_count as usize] = [pair.0, pair.1];
+			entry.gpu_device_count += 1;
 		}
 
-		let mut buf = [0 as u8; size_of::<ShmemName>()];
-		buf[0..shmem_name_len].copy_from_slice(&shmem_name_array[0..shmem_name_len]);
-		buf
+		entry
+	}
+
+	pub fn get_gpu_device_uuids(&self) -> Vec<u128> {
+		self.gpu_device_uuids[0..self.gpu_device_count as usize]
+			.iter()
+			.map(|pair| uuid::Uuid::from_u64_pair(pair[0], pair[1]).as_u128())
+			.collect()
 	}
+}
 
-	pub fn convert_shmem_array_to_str(shmem_name: &ShmemName) -> String {
-		let end = shmem_name.iter().position(|it| *it == 0 as u8);
-		if end.is_none() {
-			panic!(
-				"Shmem Name '{}' contains no end",
-				String::from_utf8_lossy(shmem_name)
-			);
+impl ImgEvent {
+	// Events are only created for names that were validated when their command was received
+	pub fn new(kind: ImgEventKind, image_name: &str) -> ImgEvent {
+		ImgEvent {
+			kind,
+			image_name: image_name.to_string(),
+			data: ShmemDataInternal::default(),
 		}
+	}
 
-		String::from_utf8_lossy(&shmem_name[0..end.unwrap()]).to_string()
+	pub fn get_image_name(&self) -> &str {
+		&self.image_name
 	}
 }
 
-impl Default for ImgFormat {
+impl Default for ImgListEntry {
 	fn default() -> Self {
-		ImgFormat::Undefined
+		ImgListEntry::new(String::new(), &ShmemDataInternal::default(), &[])
+	}
+}
+
+impl ImgStatsEntry {
+	pub fn get_last_update(&self) -> Option<SystemTime> {
+		match self.last_update_millis {
+			0 => None,
+			millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+		}
+	}
+}
+
+impl DeviceStatsEntry {
+	pub fn new(gpu_device_uuid: u128, image_count: u32, memory_usage: u64) -> DeviceStatsEntry {
+		let pair = uuid::Uuid::from_u128(gpu_device_uuid).as_u64_pair();
+		DeviceStatsEntry {
+			gpu_device_uuid: [pair.0, pair.1],
+			image_count,
+			memory_usage,
+		}
+	}
+
+	pub fn get_gpu_device_uuid(&self) -> u128 {
+		uuid::Uuid::from_u64_pair(self.gpu_device_uuid[0], self.gpu_device_uuid[1]).as_u128()
 	}
 }
 
-impl Default for ImgData {
+impl Default for ImgEvent {
 	fn default() -> Self {
 		Self {
-			shmem_name: [0 as u8; size_of::<ShmemName>()],
+			kind: ImgEventKind::ImageRemoved,
+			image_name: String::new(),
 			data: ShmemDataInternal::default(),
 		}
 	}
 }
+
+impl_wire_format_enum!(ImgFormat {
+	R8G8B8A8,
+	R8G8B8,
+	B8G8R8A8,
+	B8G8R8,
+	Undefined,
+});
+impl_wire_format_enum!(ImgEventKind {
+	ImageResized,
+	ImageRemoved,
+	ProducerDisconnected,
+	MetadataChanged,
+});
+impl_wire_format!(ImgEvent {
+	kind,
+	image_name,
+	data,
+});
+impl_wire_format!(ImgListEntry {
+	name,
+	handle_id,
+	width,
+	height,
+	format,
+	gpu_device_count,
+	gpu_device_uuids,
+});
+impl_wire_format!(ImgStatsEntry {
+	image,
+	allocation_size,
+	producer_count,
+	consumer_count,
+	last_update_millis,
+});
+impl_wire_format!(DeviceStatsEntry {
+	gpu_device_uuid,
+	image_count,
+	memory_usage,
+});
+
+// Values may be longer than names, up to IMG_METADATA_MAX_LEN
+impl WireFormat for ImgMetadataEntry {
+	fn encode(&self, buf: &mut Vec<u8>) {
+		self.key.encode(buf);
+		self.value.encode(buf);
+	}
+
+	fn decode(buf: &mut &[u8]) -> Result<Self, Error> {
+		Ok(ImgMetadataEntry {
+			key: String::decode(buf)?,
+			value: decode_string(buf, IMG_METADATA_MAX_LEN)?,
+		})
+	}
+}
+impl_wire_format!(ImgData {
+	shmem_name,
+	image_name,
+	data,
+});
+
+#[cfg(test)]
+mod tests {
+	use super::{img_name, img_name_matches, img_name_or_empty, ImgFormat, IMG_NAME_MAX_LEN};
+	use crate::platform::ipc_protocol::WireFormat;
+	use crate::Error;
+
+	#[test]
+	fn name_conversion() {
+		assert_eq!(img_name("img_name").unwrap(), "img_name");
+		assert_eq!(img_name_or_empty("").unwrap(), "");
+	}
+
+	#[test]
+	fn name_too_long() {
+		let name = "a".repeat(IMG_NAME_MAX_LEN + 1);
+		let res = img_name(&name);
+		assert!(matches!(res, Err(Error::NameTooLong { .. })));
+
+		// Longest accepted name
+		let name = "a".repeat(IMG_NAME_MAX_LEN);
+		assert!(img_name(&name).is_ok());
+	}
+
+	#[test]
+	fn name_invalid() {
+		assert!(matches!(img_name(""), Err(Error::InvalidName { .. })));
+		assert!(matches!(
+			img_name("img\0name"),
+			Err(Error::InvalidName { .. })
+		));
+	}
+
+	#[test]
+	fn name_encoding() {
+		let mut buf = Vec::new();
+		img_name("img_name").unwrap().encode(&mut buf);
+		String::new().encode(&mut buf);
+		assert_eq!(buf.len(), 2 * 4 + "img_name".len());
+
+		let mut rest = &buf[..];
+		assert_eq!(String::decode(&mut rest).unwrap(), "img_name");
+		assert!(String::decode(&mut rest).unwrap().is_empty());
+		assert!(rest.is_empty());
+	}
+
+	#[test]
+	fn name_decode_invalid() {
+		let decode = |len: u32, bytes: &[u8]| {
+			let mut buf = len.to_ne_bytes().to_vec();
+			buf.extend_from_slice(bytes);
+			String::decode(&mut &buf[..])
+		};
+
+		// Truncated, too long, invalid UTF-8 and embedded NUL
+		assert!(matches!(decode(4, b"abc"), Err(Error::Protocol(_))));
+		let long_name = vec![b'a'; IMG_NAME_MAX_LEN + 1];
+		assert!(matches!(
+			decode(long_name.len() as u32, &long_name),
+			Err(Error::Protocol(_))
+		));
+		assert!(matches!(decode(2, &[0xc3, 0x28]), Err(Error::Protocol(_))));
+		assert!(matches!(decode(3, b"a\0b"), Err(Error::Protocol(_))));
+	}
+
+	#[test]
+	fn enum_decode_invalid() {
+		let mut buf = Vec::new();
+		ImgFormat::B8G8R8.encode(&mut buf);
+		assert_eq!(ImgFormat::decode(&mut &buf[..]).unwrap(), ImgFormat::B8G8R8);
+
+		let buf = (ImgFormat::Undefined as u32 + 1).to_ne_bytes();
+		assert!(matches!(
+			ImgFormat::decode(&mut &buf[..]),
+			Err(Error::Protocol(_))
+		));
+		assert!(matches!(bool::decode(&mut &[2][..]), Err(Error::Protocol(_))));
+	}
+
+	#[test]
+	fn name_pattern_matching() {
+		assert!(img_name_matches("studio/cam1/color", "studio/cam1/*"));
+		assert!(img_name_matches("studio/cam1/depth/raw", "studio/cam1/*"));
+		assert!(img_name_matches("studio/cam1/color", "studio/*/color"));
+		assert!(img_name_matches("studio/cam1/color", "studio/cam?/color"));
+		assert!(img_name_matches("studio/cam1/color", "studio/cam1/color"));
+		assert!(img_name_matches("studio/cam1/color", "*"));
+		assert!(img_name_matches("a*b", "a**b"));
+		assert!(img_name_matches("", "*"));
+
+		assert!(!img_name_matches("studio/cam2/color", "studio/cam1/*"));
+		assert!(!img_name_matches("studio/cam1", "studio/cam1/*"));
+		assert!(!img_name_matches("studio/cam1/color", "studio/cam1/colo"));
+		assert!(!img_name_matches("studio/cam10/color", "studio/cam?/color"));
+		assert!(!img_name_matches("studio/cam1/color", ""));
+	}
+}
@@ -1,49 +1,111 @@
-use crate::platform::img_data::{ImgData, ImgFormat, ImgName, ShmemName};
+use crate::platform::img_data::{
+	DeviceStatsEntry, ImgData, ImgEvent, ImgFormat, ImgListEntry, ImgMetadataEntry, ImgStatsEntry,
+	IMG_LIST_MAX_GPU_DEVICES, IMG_MAX_BUFFER_COUNT, IMG_MAX_DIMENSION, IMG_METADATA_MAX_LEN,
+	IMG_NAME_MAX_LEN,
+};
+use crate::platform::ipc_protocol::{impl_wire_format, impl_wire_format_enum, WireFormat};
+use crate::platform::ShmemDataInternal;
+use crate::Error;
 
-use std::mem::{size_of, ManuallyDrop};
+use std::fmt::Display;
+use std::mem::size_of;
 
-#[repr(C)]
-pub struct CommandMsg {
-	pub tag: CommandTag,
-	pub data: CommandData,
+// Tag values are sent on the wire, don't reorder
+#[repr(u32)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CommandTag {
+	Hello = 0,
+	InitImage = 1,
+	FindImage = 2,
+	CopyImage = 3,
+	RenameImage = 4,
+	ListImages = 5,
+	ReleaseImage = 6,
+	DeleteImage = 7,
+	Subscribe = 8,
+	ImageEvent = 9,
+	Error = 10,
+	Ping = 11,
+	GetStats = 12,
+	FindImages = 13,
+	SetImageMetadata = 14,
+	GetImageMetadata = 15,
+	WaitForImage = 16,
+	ListImagesMatching = 17,
 }
 
-#[repr(C)]
-pub struct ResultMsg {
-	pub tag: CommandTag,
-	pub data: ResultData,
+// Commands sent by clients, with their data. The tag of each variant is sent on the wire, see
+// CommandTag. Messages are short-lived, so FindImages is kept inline despite its size
+#[allow(clippy::large_enum_variant)]
+pub enum CommandMsg {
+	Hello(CommHello),
+	InitImage(CommInitImage),
+	FindImage(CommFindImage),
+	CopyImage(CommCopyImage),
+	RenameImage(CommRenameImage),
+	ListImages(CommListImages),
+	ReleaseImage(CommReleaseImage),
+	DeleteImage(CommDeleteImage),
+	Subscribe(CommSubscribe),
+	Ping(CommPing),
+	GetStats(CommGetStats),
+	FindImages(CommFindImages),
+	SetImageMetadata(CommSetImageMetadata),
+	GetImageMetadata(CommGetImageMetadata),
+	WaitForImage(CommWaitForImage),
+	ListImagesMatching(CommListImagesMatching),
 }
 
-#[repr(u32)]
-#[derive(Debug, PartialEq)]
-pub enum CommandTag {
-	InitImage,
-	//RenameImage,
-	FindImage,
-	CopyImage,
+// Results sent by the server, tagged like the command they answer. ImageEvent and Error results
+// may arrive at any time
+pub enum ResultMsg {
+	Hello(ResultHelloAck),
+	InitImage(ResultInitImage),
+	FindImage(ResultFindImage),
+	RenameImage(ResultRenameImage),
+	ListImages(ResultListImages),
+	ListImagesMatching(ResultListImages),
+	ReleaseImage(ResultReleaseImage),
+	DeleteImage(ResultDeleteImage),
+	Subscribe(ResultSubscribe),
+	ImageEvent(ImgEvent),
+	Error(ResultError),
+	Ping(ResultPong),
+	GetStats(ResultGetStats),
+	FindImages(ResultFindImages),
+	SetImageMetadata(ResultSetImageMetadata),
+	GetImageMetadata(ResultGetImageMetadata),
+	WaitForImage(ResultWaitForImage),
 }
 
-#[repr(C)]
-pub union CommandData {
-	pub init_img: ManuallyDrop<CommInitImage>,
-	pub find_img: ManuallyDrop<CommFindImage>,
-	pub copy_img: ManuallyDrop<CommCopyImage>,
+// Namespace of connections that don't choose one
+pub const DEFAULT_NAMESPACE: &str = "";
+
+pub struct CommHello {
+	pub min_protocol_version: u32,
+	pub max_protocol_version: u32,
+	// Images of a connection are only visible to connections of the same namespace
+	pub namespace: String,
 }
 
-#[repr(C)]
-pub union ResultData {
-	pub init_img: ManuallyDrop<ResultInitImage>,
-	pub find_img: ManuallyDrop<ResultFindImage>,
+pub struct ResultHelloAck {
+	pub accepted: bool,
+	pub protocol_version: u32,
+	// Random id of the server process, changes when the server restarts
+	pub server_instance_id: u128,
 }
 
 pub struct CommInitImage {
-	pub image_name: ImgName,
-	pub shmem_name: ShmemName,
+	pub image_name: String,
+	pub shmem_name: String,
 	pub width: u32,
 	pub height: u32,
 	pub format: ImgFormat,
 	pub overwrite_existing: bool,
 	pub gpu_device_uuid: u128,
+	// Number of images to allocate, 1 for a single image. With more, frames rotate through them
+	// so a reader never blocks the writer
+	pub buffer_count: u32,
 }
 
 pub struct ResultInitImage {
@@ -52,8 +114,8 @@ pub struct ResultInitImage {
 }
 
 pub struct CommRenameImage {
-	pub old_image_name: ImgName,
-	pub new_image_name: ImgName,
+	pub old_image_name: String,
+	pub new_image_name: String,
 }
 
 pub struct ResultRenameImage {
@@ -62,7 +124,7 @@ pub struct ResultRenameImage {
 }
 
 pub struct CommFindImage {
-	pub image_name: ImgName,
+	pub image_name: String,
 	pub gpu_device_uuid: u128,
 }
 
@@ -71,46 +133,528 @@ pub struct ResultFindImage {
 	pub img_data: ImgData,
 }
 
+// Maximum number of names looked up by a single FindImages command
+pub const FIND_IMAGES_MAX_COUNT: usize = 32;
+
+// Look up several images at once. Only the first image_count names are used, the others are empty
+pub struct CommFindImages {
+	pub image_count: u32,
+	pub gpu_device_uuid: u128,
+	pub image_names: [String; FIND_IMAGES_MAX_COUNT],
+}
+
+// The server replies with one ResultFindImages per requested name, ordered by image_index. If any
+// image was found, the memory handles of all found images follow in a single message, in the same
+// order. The client acknowledges them once
+pub struct ResultFindImages {
+	pub image_count: u32,
+	pub image_index: u32,
+	pub image_found: bool,
+	pub img_data: ImgData,
+}
+
+pub struct CommListImages {}
+
+// The server replies with one ResultListImages per image, ordered by image_index. If no images
+// exist, a single result with image_count 0 is sent
+pub struct ResultListImages {
+	pub image_count: u32,
+	pub image_index: u32,
+	pub image: ImgListEntry,
+}
+
+// Drop this connection's reference to an image. The image is destroyed once no connection
+// references it anymore
+pub struct CommReleaseImage {
+	pub image_name: String,
+}
+
+pub struct ResultReleaseImage {
+	pub image_released: bool,
+	pub image_destroyed: bool,
+}
+
+// Destroy an image, regardless of other connections still referencing it
+pub struct CommDeleteImage {
+	pub image_name: String,
+}
+
+pub struct ResultDeleteImage {
+	pub image_found: bool,
+}
+
+// Start or stop receiving ImageEvent messages for an image. The image doesn't have to exist yet
+pub struct CommSubscribe {
+	pub image_name: String,
+	pub subscribe: bool,
+}
+
+pub struct ResultSubscribe {
+	pub image_found: bool,
+}
+
+// Liveness check. A non-zero heartbeat_interval_millis announces that the client will keep
+// sending pings at this interval, the server drops the connection once several are missed
+pub struct CommPing {
+	pub sequence: u64,
+	pub heartbeat_interval_millis: u64,
+}
+
+pub struct ResultPong {
+	pub sequence: u64,
+}
+
+pub struct CommGetStats {}
+
+// The server replies with one ResultGetStats per image, followed by one per GPU device, ordered by
+// entry_index. Entries below image_count carry an image, the others a device. If neither exist, a
+// single result with both counts 0 is sent
+pub struct ResultGetStats {
+	pub connection_count: u32,
+	pub gpu_copy_count: u64,
+	pub image_count: u32,
+	pub device_count: u32,
+	pub entry_index: u32,
+	pub image: ImgStatsEntry,
+	pub device: DeviceStatsEntry,
+}
+
+// Server state, assembled from all ResultGetStats messages
+#[derive(Clone, Default)]
+pub struct ServerStats {
+	pub connection_count: u32,
+	// Number of images copied from one GPU to another
+	pub gpu_copy_count: u64,
+	pub images: Vec<ImgStatsEntry>,
+	pub devices: Vec<DeviceStatsEntry>,
+}
+
+// Set the entry with the given key, or remove it if remove is set. The server stores all entries
+// of an image in its shared memory segments
+pub struct CommSetImageMetadata {
+	pub image_name: String,
+	pub entry: ImgMetadataEntry,
+	pub remove: bool,
+}
+
+pub struct ResultSetImageMetadata {
+	pub image_found: bool,
+}
+
+pub struct CommGetImageMetadata {
+	pub image_name: String,
+}
+
+// The server replies with one ResultGetImageMetadata per entry, ordered by entry_index. If the
+// image has no entries or wasn't found, a single result with entry_count 0 is sent
+pub struct ResultGetImageMetadata {
+	pub image_found: bool,
+	pub entry_count: u32,
+	pub entry_index: u32,
+	pub entry: ImgMetadataEntry,
+}
+
+// The server answers once an image with this name is initialized, or after timeout_millis. Other
+// commands of the connection are processed in the meantime
+pub struct CommWaitForImage {
+	pub image_name: String,
+	pub timeout_millis: u64,
+}
+
+pub struct ResultWaitForImage {
+	pub image_found: bool,
+}
+
+// List all images whose name matches pattern, see img_name_matches. The server replies as to
+// ListImages, with ListImagesMatching results
+pub struct CommListImagesMatching {
+	pub pattern: String,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResultErrorCode {
+	Unknown,
+	InvalidCommand,
+	GpuNotFound,
+	FormatUnsupported,
+	OutOfMemory,
+	VulkanFailed,
+	MetadataTooLarge,
+}
+
+pub const RESULT_ERROR_MESSAGE_LEN: usize = 256;
+
+// Sent instead of the regular result if the server failed to process a command
+pub struct ResultError {
+	pub code: ResultErrorCode,
+	pub message: [u8; RESULT_ERROR_MESSAGE_LEN],
+}
+
+// Error reported by the server through a ResultError
+#[derive(Debug, Clone)]
+pub struct ServerError {
+	pub code: ResultErrorCode,
+	pub message: String,
+}
+
 pub struct CommCopyImage {
-	pub image_name: ImgName,
+	pub image_name: String,
 	pub gpu_device_uuid: u128,
 }
 
+impl TryFrom<u32> for CommandTag {
+	type Error = Error;
+
+	fn try_from(value: u32) -> Result<Self, Error> {
+		match value {
+			0 => Ok(CommandTag::Hello),
+			1 => Ok(CommandTag::InitImage),
+			2 => Ok(CommandTag::FindImage),
+			3 => Ok(CommandTag::CopyImage),
+			4 => Ok(CommandTag::RenameImage),
+			5 => Ok(CommandTag::ListImages),
+			6 => Ok(CommandTag::ReleaseImage),
+			7 => Ok(CommandTag::DeleteImage),
+			8 => Ok(CommandTag::Subscribe),
+			9 => Ok(CommandTag::ImageEvent),
+			10 => Ok(CommandTag::Error),
+			11 => Ok(CommandTag::Ping),
+			12 => Ok(CommandTag::GetStats),
+			13 => Ok(CommandTag::FindImages),
+			14 => Ok(CommandTag::SetImageMetadata),
+			15 => Ok(CommandTag::GetImageMetadata),
+			16 => Ok(CommandTag::WaitForImage),
+			17 => Ok(CommandTag::ListImagesMatching),
+			_ => Err(Error::Protocol(format!("Unknown command tag {}", value))),
+		}
+	}
+}
+
+// Map the variants of a message to their tags and encode or decode their data. Messages of the
+// remaining tags are not sent in this direction
+macro_rules! impl_message_data {
+	($msg:ident { $($tag:ident),* $(,)? }) => {
+		impl $msg {
+			pub fn tag(&self) -> CommandTag {
+				match self {
+					$($msg::$tag(_) => CommandTag::$tag,)*
+				}
+			}
+
+			pub(crate) fn encode_payload(&self) -> Vec<u8> {
+				let mut buf = Vec::new();
+				match self {
+					$($msg::$tag(data) => data.encode(&mut buf),)*
+				}
+				buf
+			}
+
+			// Decode the data of the given tag. The payload has to be consumed completely
+			fn decode_data(tag: CommandTag, payload: &[u8]) -> Result<$msg, Error> {
+				let mut buf = payload;
+				let msg = match tag {
+					$(CommandTag::$tag => $msg::$tag(WireFormat::decode(&mut buf)?),)*
+					_ => {
+						return Err(Error::Protocol(format!(
+							"Received {:?}, which is not sent in this direction",
+							tag
+						)))
+					}
+				};
+
+				if !buf.is_empty() {
+					return Err(Error::Protocol(format!(
+						"Received {} unexpected bytes after {:?} payload",
+						buf.len(),
+						tag
+					)));
+				}
+
+				Ok(msg)
+			}
+		}
+	};
+}
+
+impl_message_data!(CommandMsg {
+	Hello,
+	InitImage,
+	FindImage,
+	CopyImage,
+	RenameImage,
+	ListImages,
+	ReleaseImage,
+	DeleteImage,
+	Subscribe,
+	Ping,
+	GetStats,
+	FindImages,
+	SetImageMetadata,
+	GetImageMetadata,
+	WaitForImage,
+	ListImagesMatching,
+});
+
+impl_message_data!(ResultMsg {
+	Hello,
+	InitImage,
+	FindImage,
+	RenameImage,
+	ListImages,
+	ListImagesMatching,
+	ReleaseImage,
+	DeleteImage,
+	Subscribe,
+	ImageEvent,
+	Error,
+	Ping,
+	GetStats,
+	FindImages,
+	SetImageMetadata,
+	GetImageMetadata,
+	WaitForImage,
+});
+
+// Payload of FindImages with all names at their maximum length. The extra name leaves room for
+// the fixed size fields
+const FIND_IMAGES_MAX_PAYLOAD_LEN: usize =
+	(FIND_IMAGES_MAX_COUNT + 1) * (size_of::<u32>() + IMG_NAME_MAX_LEN);
+
+// Payload of SetImageMetadata with name, key and value at their maximum length, again with an
+// extra name for the fixed size fields
+const METADATA_MAX_PAYLOAD_LEN: usize =
+	3 * (size_of::<u32>() + IMG_NAME_MAX_LEN) + size_of::<u32>() + IMG_METADATA_MAX_LEN;
+
+// Upper bound for the payload of any message
+pub const MAX_PAYLOAD_LEN: usize = if FIND_IMAGES_MAX_PAYLOAD_LEN > METADATA_MAX_PAYLOAD_LEN {
+	FIND_IMAGES_MAX_PAYLOAD_LEN
+} else {
+	METADATA_MAX_PAYLOAD_LEN
+};
+
+impl CommandMsg {
+	// Decode and validate a received payload
+	pub(crate) fn decode_payload(tag: CommandTag, payload: &[u8]) -> Result<CommandMsg, Error> {
+		let msg = CommandMsg::decode_data(tag, payload)?;
+		msg.validate()?;
+		Ok(msg)
+	}
+
+	// Check the values of a received command. Bools, enums and the encoding of names are already
+	// checked while decoding
+	pub fn validate(&self) -> Result<(), Error> {
+		match self {
+			CommandMsg::InitImage(cmd) => {
+				check_name(&cmd.image_name)?;
+				check_dimension(cmd.width, "width")?;
+				check_dimension(cmd.height, "height")?;
+				check_buffer_count(cmd.buffer_count)
+			}
+			CommandMsg::FindImage(cmd) => check_name(&cmd.image_name),
+			CommandMsg::CopyImage(cmd) => check_name(&cmd.image_name),
+			CommandMsg::RenameImage(cmd) => {
+				check_name(&cmd.old_image_name)?;
+				check_name(&cmd.new_image_name)
+			}
+			CommandMsg::ReleaseImage(cmd) => check_name(&cmd.image_name),
+			CommandMsg::DeleteImage(cmd) => check_name(&cmd.image_name),
+			CommandMsg::Subscribe(cmd) => check_name(&cmd.image_name),
+			CommandMsg::FindImages(cmd) => {
+				if cmd.image_count == 0 || cmd.image_count as usize > FIND_IMAGES_MAX_COUNT {
+					return Err(Error::Protocol(format!(
+						"Image count {} out of range 1-{}",
+						cmd.image_count, FIND_IMAGES_MAX_COUNT
+					)));
+				}
+
+				cmd.image_names[0..cmd.image_count as usize]
+					.iter()
+					.try_for_each(|x| check_name(x))
+			}
+			CommandMsg::SetImageMetadata(cmd) => {
+				check_name(&cmd.image_name)?;
+				check_name(&cmd.entry.key)
+			}
+			CommandMsg::GetImageMetadata(cmd) => check_name(&cmd.image_name),
+			CommandMsg::WaitForImage(cmd) => check_name(&cmd.image_name),
+			_ => Ok(()),
+		}
+	}
+}
+
+// Image names and metadata keys must not be empty, namespaces and patterns may be
+fn check_name(name: &str) -> Result<(), Error> {
+	if name.is_empty() {
+		return Err(Error::Protocol("Received empty name".to_string()));
+	}
+
+	Ok(())
+}
+
+fn check_dimension(value: u32, field: &str) -> Result<(), Error> {
+	if value == 0 || value > IMG_MAX_DIMENSION {
+		return Err(Error::Protocol(format!(
+			"Image {} {} out of range 1-{}",
+			field, value, IMG_MAX_DIMENSION
+		)));
+	}
+
+	Ok(())
+}
+
+fn check_buffer_count(value: u32) -> Result<(), Error> {
+	if value == 0 || value > IMG_MAX_BUFFER_COUNT {
+		return Err(Error::Protocol(format!(
+			"Image buffer count {} out of range 1-{}",
+			value, IMG_MAX_BUFFER_COUNT
+		)));
+	}
+
+	Ok(())
+}
+
+impl ResultMsg {
+	// Decode and validate a received payload
+	pub(crate) fn decode_payload(tag: CommandTag, payload: &[u8]) -> Result<ResultMsg, Error> {
+		let msg = ResultMsg::decode_data(tag, payload)?;
+		msg.validate()?;
+		Ok(msg)
+	}
+
+	// Check the counts and indices of a received result. Bools, enums and names are already
+	// checked while decoding
+	pub fn validate(&self) -> Result<(), Error> {
+		match self {
+			ResultMsg::InitImage(res) => check_shmem_data(&res.img_data.data),
+			ResultMsg::FindImage(res) => check_shmem_data(&res.img_data.data),
+			ResultMsg::RenameImage(res) => check_shmem_data(&res.img_data.data),
+			ResultMsg::ImageEvent(event) => check_shmem_data(&event.data),
+			ResultMsg::FindImages(res) => {
+				if res.image_count == 0 || res.image_count as usize > FIND_IMAGES_MAX_COUNT {
+					return Err(Error::Protocol(format!(
+						"Image count {} out of range 1-{}",
+						res.image_count, FIND_IMAGES_MAX_COUNT
+					)));
+				}
+
+				check_index(res.image_index, res.image_count as u64)?;
+				check_shmem_data(&res.img_data.data)
+			}
+			ResultMsg::ListImages(res) | ResultMsg::ListImagesMatching(res) => {
+				check_index(res.image_index, res.image_count as u64)?;
+				check_gpu_device_count(res.image.gpu_device_count)
+			}
+			ResultMsg::GetStats(res) => {
+				check_index(res.entry_index, res.image_count as u64 + res.device_count as u64)?;
+				check_gpu_device_count(res.image.image.gpu_device_count)
+			}
+			ResultMsg::GetImageMetadata(res) => check_index(res.entry_index, res.entry_count as u64),
+			_ => Ok(()),
+		}
+	}
+}
+
+// Results of lists are numbered below their count. Empty lists are sent as a single result with
+// index and count 0
+fn check_index(index: u32, count: u64) -> Result<(), Error> {
+	if index as u64 >= count.max(1) {
+		return Err(Error::Protocol(format!(
+			"Result index {} out of range for count {}",
+			index, count
+		)));
+	}
+
+	Ok(())
+}
+
+fn check_gpu_device_count(value: u32) -> Result<(), Error> {
+	if value as usize > IMG_LIST_MAX_GPU_DEVICES {
+		return Err(Error::Protocol(format!(
+			"GPU device count {} larger than {}",
+			value, IMG_LIST_MAX_GPU_DEVICES
+		)));
+	}
+
+	Ok(())
+}
+
+fn check_shmem_data(data: &ShmemDataInternal) -> Result<(), Error> {
+	check_buffer_count(data.buffer_count)?;
+	if data.latest_buffer >= data.buffer_count {
+		return Err(Error::Protocol(format!(
+			"Latest buffer {} out of range for {} buffers",
+			data.latest_buffer, data.buffer_count
+		)));
+	}
+
+	Ok(())
+}
+
 impl Default for CommandMsg {
 	fn default() -> Self {
-		Self {
-			tag: CommandTag::FindImage,
-			data: CommandData {
-				find_img: ManuallyDrop::new(CommFindImage::default()),
-			},
-		}
+		CommandMsg::FindImage(CommFindImage::default())
 	}
 }
 
 impl Default for ResultMsg {
 	fn default() -> Self {
-		Self {
-			tag: CommandTag::FindImage,
-			data: ResultData {
-				find_img: ManuallyDrop::new(ResultFindImage {
-					image_found: false,
-					img_data: ImgData::default(),
-				}),
-			},
+		ResultMsg::FindImage(ResultFindImage {
+			image_found: false,
+			img_data: ImgData::default(),
+		})
+	}
+}
+
+impl ResultError {
+	pub fn new(code: ResultErrorCode, message: &str) -> ResultError {
+		// Truncate long messages, keeping the terminating NUL
+		let message_bytes = message.as_bytes();
+		let message_len = message_bytes.len().min(RESULT_ERROR_MESSAGE_LEN - 1);
+
+		let mut buf = [0u8; RESULT_ERROR_MESSAGE_LEN];
+		buf[0..message_len].copy_from_slice(&message_bytes[0..message_len]);
+		ResultError { code, message: buf }
+	}
+
+	pub fn to_server_error(&self) -> ServerError {
+		let end = self
+			.message
+			.iter()
+			.position(|it| *it == 0u8)
+			.unwrap_or(RESULT_ERROR_MESSAGE_LEN);
+		ServerError {
+			code: self.code,
+			message: String::from_utf8_lossy(&self.message[0..end]).to_string(),
 		}
 	}
 }
 
+impl ServerError {
+	pub fn new(code: ResultErrorCode, message: String) -> ServerError {
+		ServerError { code, message }
+	}
+}
+
+impl Display for ServerError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "Server error {:?}: {}", self.code, self.message)
+	}
+}
+
+impl std::error::Error for ServerError {}
+
 impl Default for CommInitImage {
 	fn default() -> Self {
 		CommInitImage {
-			image_name: [0 as u8; size_of::<ImgName>()],
-			shmem_name: [0 as u8; size_of::<ShmemName>()],
+			image_name: String::new(),
+			shmem_name: String::new(),
 			format: ImgFormat::default(),
 			width: 0,
 			height: 0,
 			overwrite_existing: false,
 			gpu_device_uuid: uuid::Uuid::nil().as_u128(),
+			buffer_count: 1,
 		}
 	}
 }
@@ -118,17 +662,145 @@ impl Default for CommInitImage {
 impl Default for CommFindImage {
 	fn default() -> Self {
 		Self {
-			image_name: [0 as u8; size_of::<ImgName>()],
+			image_name: String::new(),
 			gpu_device_uuid: uuid::Uuid::nil().as_u128(),
 		}
 	}
 }
 
+impl Default for CommFindImages {
+	fn default() -> Self {
+		Self {
+			image_count: 0,
+			gpu_device_uuid: uuid::Uuid::nil().as_u128(),
+			image_names: Default::default(),
+		}
+	}
+}
+
 impl Default for CommCopyImage {
 	fn default() -> Self {
 		Self {
-			image_name: [0 as u8; size_of::<ImgName>()],
+			image_name: String::new(),
 			gpu_device_uuid: uuid::Uuid::nil().as_u128(),
 		}
 	}
 }
+
+impl_wire_format_enum!(ResultErrorCode {
+	Unknown,
+	InvalidCommand,
+	GpuNotFound,
+	FormatUnsupported,
+	OutOfMemory,
+	VulkanFailed,
+	MetadataTooLarge,
+});
+impl_wire_format!(CommHello {
+	min_protocol_version,
+	max_protocol_version,
+	namespace,
+});
+impl_wire_format!(ResultHelloAck {
+	accepted,
+	protocol_version,
+	server_instance_id,
+});
+impl_wire_format!(CommInitImage {
+	image_name,
+	shmem_name,
+	width,
+	height,
+	format,
+	overwrite_existing,
+	gpu_device_uuid,
+	buffer_count,
+});
+impl_wire_format!(ResultInitImage {
+	image_created,
+	img_data,
+});
+impl_wire_format!(CommRenameImage {
+	old_image_name,
+	new_image_name,
+});
+impl_wire_format!(ResultRenameImage {
+	image_found,
+	img_data,
+});
+impl_wire_format!(CommFindImage {
+	image_name,
+	gpu_device_uuid,
+});
+impl_wire_format!(ResultFindImage {
+	image_found,
+	img_data,
+});
+impl_wire_format!(CommFindImages {
+	image_count,
+	gpu_device_uuid,
+	image_names,
+});
+impl_wire_format!(ResultFindImages {
+	image_count,
+	image_index,
+	image_found,
+	img_data,
+});
+impl_wire_format!(CommListImages {});
+impl_wire_format!(ResultListImages {
+	image_count,
+	image_index,
+	image,
+});
+impl_wire_format!(CommReleaseImage { image_name });
+impl_wire_format!(ResultReleaseImage {
+	image_released,
+	image_destroyed,
+});
+impl_wire_format!(CommDeleteImage { image_name });
+impl_wire_format!(ResultDeleteImage { image_found });
+impl_wire_format!(CommSubscribe {
+	image_name,
+	subscribe,
+});
+impl_wire_format!(ResultSubscribe { image_found });
+impl_wire_format!(CommPing {
+	sequence,
+	heartbeat_interval_millis,
+});
+impl_wire_format!(ResultPong { sequence });
+impl_wire_format!(CommGetStats {});
+impl_wire_format!(ResultGetStats {
+	connection_count,
+	gpu_copy_count,
+	image_count,
+	device_count,
+	entry_index,
+	image,
+	device,
+});
+impl_wire_format!(CommSetImageMetadata {
+	image_name,
+	entry,
+	remove,
+});
+impl_wire_format!(ResultSetImageMetadata { image_found });
+impl_wire_format!(CommGetImageMetadata { image_name });
+impl_wire_format!(ResultGetImageMetadata {
+	image_found,
+	entry_count,
+	entry_index,
+	entry,
+});
+impl_wire_format!(CommWaitForImage {
+	image_name,
+	timeout_millis,
+});
+impl_wire_format!(ResultWaitForImage { image_found });
+impl_wire_format!(CommListImagesMatching { pattern });
+impl_wire_format!(ResultError { code, message });
+impl_wire_format!(CommCopyImage {
+	image_name,
+	gpu_device_uuid,
+});
@@ -0,0 +1,315 @@
+use std::mem::size_of;
+
+use crate::platform::img_data::IMG_NAME_MAX_LEN;
+use crate::platform::ipc_commands::CommandTag;
+use crate::Error;
+
+// Every frame on the socket starts with a MsgHeader followed by payload_len bytes of payload.
+// The header layout must never change, so that peers of different versions can still detect
+// each other during the handshake.
+pub const PROTOCOL_MAGIC: u32 = u32::from_ne_bytes(*b"TSVK");
+
+// Bump PROTOCOL_VERSION whenever the payload of an existing command changes. Raise
+// PROTOCOL_VERSION_MIN once the old layout is no longer understood by this build.
+pub const PROTOCOL_VERSION: u32 = 4;
+pub const PROTOCOL_VERSION_MIN: u32 = 4;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MsgHeader {
+	pub magic: u32,
+	pub version: u32,
+	pub tag: u32,
+	pub payload_len: u32,
+}
+
+impl MsgHeader {
+	pub const SIZE: usize = size_of::<MsgHeader>();
+
+	pub fn new(version: u32, tag: u32, payload_len: usize) -> MsgHeader {
+		MsgHeader {
+			magic: PROTOCOL_MAGIC,
+			version,
+			tag,
+			payload_len: payload_len as u32,
+		}
+	}
+
+	pub fn to_bytes(&self) -> [u8; MsgHeader::SIZE] {
+		let mut buf = [0u8; MsgHeader::SIZE];
+		buf[0..4].copy_from_slice(&self.magic.to_ne_bytes());
+		buf[4..8].copy_from_slice(&self.version.to_ne_bytes());
+		buf[8..12].copy_from_slice(&self.tag.to_ne_bytes());
+		buf[12..16].copy_from_slice(&self.payload_len.to_ne_bytes());
+		buf
+	}
+
+	pub fn from_bytes(buf: &[u8; MsgHeader::SIZE]) -> MsgHeader {
+		let field = |i: usize| u32::from_ne_bytes(buf[i..i + 4].try_into().unwrap());
+		MsgHeader {
+			magic: field(0),
+			version: field(4),
+			tag: field(8),
+			payload_len: field(12),
+		}
+	}
+
+	// Check that the frame was sent by a texture-share peer speaking the expected version.
+	// During the handshake no version has been agreed on yet, so only the magic is checked.
+	pub fn validate(&self, expected_version: Option<u32>) -> Result<(), Error> {
+		if self.magic != PROTOCOL_MAGIC {
+			return Err(Error::Protocol(format!(
+				"Received frame with invalid magic {:#010x}. Peer uses an incompatible protocol",
+				self.magic
+			)));
+		}
+
+		if let Some(version) = expected_version {
+			if self.version != version {
+				return Err(Error::Protocol(format!(
+					"Received frame with protocol version {}, but version {} was negotiated",
+					self.version, version
+				)));
+			}
+		}
+
+		Ok(())
+	}
+
+	// Validate a received header and return the tag of the frame it announces
+	pub(crate) fn get_tag(&self, protocol_version: Option<u32>) -> Result<CommandTag, Error> {
+		self.validate(protocol_version)?;
+		let tag = CommandTag::try_from(self.tag)?;
+
+		// Only the handshake is allowed before a version was agreed on
+		if protocol_version.is_none() && tag != CommandTag::Hello {
+			return Err(Error::Protocol(format!(
+				"Received {:?} before handshake",
+				tag
+			)));
+		}
+
+		Ok(tag)
+	}
+
+	// Names make payloads variable in size. Checked before the payload is received, so that a
+	// peer can't make us allocate arbitrary amounts of memory
+	pub(crate) fn check_payload_len(&self, max_len: usize) -> Result<(), Error> {
+		if self.payload_len as usize > max_len {
+			return Err(Error::Protocol(format!(
+				"Received payload of {} bytes for tag {}, expected at most {} bytes",
+				self.payload_len, self.tag, max_len
+			)));
+		}
+
+		Ok(())
+	}
+}
+
+// Header and payload of a frame, so that both can be written in one go
+pub(crate) fn encode_frame(version: u32, tag: CommandTag, payload: &[u8]) -> Vec<u8> {
+	let header = MsgHeader::new(version, tag as u32, payload.len());
+
+	let mut buf = Vec::with_capacity(MsgHeader::SIZE + payload.len());
+	buf.extend_from_slice(&header.to_bytes());
+	buf.extend_from_slice(payload);
+	buf
+}
+
+// Field by field encoding of message payloads. Integers are sent in native byte order, bools as
+// a single byte and strings as a u32 byte length followed by the UTF-8 bytes. Decoding checks every
+// value, so that a malformed peer can't produce invalid bools, enums or names
+pub trait WireFormat: Sized {
+	fn encode(&self, buf: &mut Vec<u8>);
+
+	// Read a value from the start of buf and advance buf past it
+	fn decode(buf: &mut &[u8]) -> Result<Self, Error>;
+}
+
+// Implement WireFormat for a struct by encoding the listed fields in order. All fields have to be
+// listed, the wire layout is independent of the struct's memory layout
+macro_rules! impl_wire_format {
+	($name:ident { $($field:ident),* $(,)? }) => {
+		impl $crate::platform::ipc_protocol::WireFormat for $name {
+			fn encode(&self, buf: &mut Vec<u8>) {
+				$($crate::platform::ipc_protocol::WireFormat::encode(&self.$field, buf);)*
+				let _ = buf;
+			}
+
+			fn decode(buf: &mut &[u8]) -> Result<Self, $crate::Error> {
+				let _ = &buf;
+				Ok($name {
+					$($field: $crate::platform::ipc_protocol::WireFormat::decode(buf)?,)*
+				})
+			}
+		}
+	};
+}
+pub(crate) use impl_wire_format;
+
+// Implement WireFormat for a fieldless enum, sent as the u32 value of the variant. Values of
+// unlisted variants are rejected
+macro_rules! impl_wire_format_enum {
+	($name:ident { $($variant:ident),* $(,)? }) => {
+		impl $crate::platform::ipc_protocol::WireFormat for $name {
+			fn encode(&self, buf: &mut Vec<u8>) {
+				$crate::platform::ipc_protocol::WireFormat::encode(&(*self as u32), buf);
+			}
+
+			fn decode(buf: &mut &[u8]) -> Result<Self, $crate::Error> {
+				let value = <u32 as $crate::platform::ipc_protocol::WireFormat>::decode(buf)?;
+				$(if value == $name::$variant as u32 {
+					return Ok($name::$variant);
+				})*
+				Err($crate::Error::Protocol(format!(
+					"Invalid {} value {}",
+					stringify!($name),
+					value
+				)))
+			}
+		}
+	};
+}
+pub(crate) use impl_wire_format_enum;
+
+fn take_bytes<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+	if buf.len() < len {
+		return Err(Error::Protocol(format!(
+			"Payload too short, expected {} more bytes but only {} are left",
+			len,
+			buf.len()
+		)));
+	}
+
+	let (bytes, rest) = buf.split_at(len);
+	*buf = rest;
+	Ok(bytes)
+}
+
+macro_rules! impl_wire_format_int {
+	($($int:ty),*) => {
+		$(impl WireFormat for $int {
+			fn encode(&self, buf: &mut Vec<u8>) {
+				buf.extend_from_slice(&self.to_ne_bytes());
+			}
+
+			fn decode(buf: &mut &[u8]) -> Result<Self, Error> {
+				let bytes = take_bytes(buf, size_of::<$int>())?;
+				Ok(<$int>::from_ne_bytes(bytes.try_into().unwrap()))
+			}
+		})*
+	};
+}
+impl_wire_format_int!(u8, u32, u64, u128);
+
+impl WireFormat for bool {
+	fn encode(&self, buf: &mut Vec<u8>) {
+		buf.push(*self as u8);
+	}
+
+	fn decode(buf: &mut &[u8]) -> Result<Self, Error> {
+		match u8::decode(buf)? {
+			0 => Ok(false),
+			1 => Ok(true),
+			value => Err(Error::Protocol(format!("Invalid bool value {}", value))),
+		}
+	}
+}
+
+// Strings on the wire are image, shared memory or namespace names and metadata. Empty strings are
+// accepted here, commands that need a name check it after decoding
+impl WireFormat for String {
+	fn encode(&self, buf: &mut Vec<u8>) {
+		(self.len() as u32).encode(buf);
+		buf.extend_from_slice(self.as_bytes());
+	}
+
+	fn decode(buf: &mut &[u8]) -> Result<Self, Error> {
+		decode_string(buf, IMG_NAME_MAX_LEN)
+	}
+}
+
+// Decode a string of up to max_len bytes. Metadata values use a longer limit than names
+pub(crate) fn decode_string(buf: &mut &[u8], max_len: usize) -> Result<String, Error> {
+	let len = u32::decode(buf)? as usize;
+	if len > max_len {
+		return Err(Error::Protocol(format!(
+			"Received string of {} bytes, longer than {} bytes",
+			len, max_len
+		)));
+	}
+
+	let name = std::str::from_utf8(take_bytes(buf, len)?)
+		.map_err(|_| Error::Protocol("Received name that is not valid UTF-8".to_string()))?;
+	if name.contains('\0') {
+		return Err(Error::Protocol("Received name containing NUL".to_string()));
+	}
+
+	Ok(name.to_string())
+}
+
+impl<T: WireFormat, const N: usize> WireFormat for [T; N] {
+	fn encode(&self, buf: &mut Vec<u8>) {
+		self.iter().for_each(|x| x.encode(buf));
+	}
+
+	fn decode(buf: &mut &[u8]) -> Result<Self, Error> {
+		let values = (0..N)
+			.map(|_| T::decode(buf))
+			.collect::<Result<Vec<_>, _>>()?;
+		match values.try_into() {
+			Ok(values) => Ok(values),
+			Err(_) => unreachable!(),
+		}
+	}
+}
+
+// Pick the highest protocol version supported by both peers
+pub fn negotiate_version(peer_min_version: u32, peer_max_version: u32) -> Option<u32> {
+	let version = peer_max_version.min(PROTOCOL_VERSION);
+	if version < peer_min_version || version < PROTOCOL_VERSION_MIN {
+		None
+	} else {
+		Some(version)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn header_roundtrip() {
+		let header = MsgHeader::new(PROTOCOL_VERSION, 7, 1234);
+		let rec_header = MsgHeader::from_bytes(&header.to_bytes());
+
+		assert_eq!(header, rec_header);
+		assert!(rec_header.validate(Some(PROTOCOL_VERSION)).is_ok());
+	}
+
+	#[test]
+	fn header_invalid() {
+		let mut header = MsgHeader::new(PROTOCOL_VERSION, 0, 0);
+		assert!(header.validate(Some(PROTOCOL_VERSION + 1)).is_err());
+
+		header.magic = 0;
+		assert!(header.validate(None).is_err());
+	}
+
+	#[test]
+	fn version_negotiation() {
+		assert_eq!(
+			negotiate_version(PROTOCOL_VERSION_MIN, PROTOCOL_VERSION),
+			Some(PROTOCOL_VERSION)
+		);
+		assert_eq!(
+			negotiate_version(PROTOCOL_VERSION_MIN, PROTOCOL_VERSION + 10),
+			Some(PROTOCOL_VERSION)
+		);
+		assert_eq!(
+			negotiate_version(PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 10),
+			None
+		);
+		assert_eq!(negotiate_version(0, PROTOCOL_VERSION_MIN - 1), None);
+	}
+}
@@ -1,23 +1,32 @@
 use memoffset::offset_of;
-use raw_sync::locks::LockImpl;
 pub use raw_sync::Timeout;
 use shared_memory::Shmem;
 use shared_memory::ShmemConf;
 use shared_memory::ShmemError;
 use std::cell::UnsafeCell;
 
-use std::io::{Error, ErrorKind};
 use std::mem::size_of;
+use std::time::Duration;
 
-use crate::platform::img_data::ImgData;
 use crate::platform::img_data::ImgFormat;
-use crate::platform::img_data::ImgName;
-use crate::platform::RwLockInternalData;
+use crate::platform::img_data::ImgFrameInfo;
+use crate::platform::img_data::{IMG_METADATA_MAX_LEN, IMG_NAME_MAX_LEN};
+use crate::platform::ipc_protocol::impl_wire_format;
+use crate::platform::{RwLockInternalData, ShmemLock};
+use crate::Error;
 
 #[repr(C)]
 #[derive(Clone)]
 pub struct ShmemDataInternal {
-	pub name: ImgName,
+	// Location of the NUL-terminated image name in the shared memory segment, relative to the
+	// start of this struct. Only valid for data read from the segment, use
+	// IpcShmem::acquire_rname to read it
+	pub name_offset: u32,
+	pub name_len: u32,
+	// Location of the encoded metadata entries, relative to the start of this struct. Use
+	// IpcShmem::acquire_rmetadata to read them
+	pub metadata_offset: u32,
+	pub metadata_len: u32,
 	pub handle_id: u32,
 	pub width: u32,
 	pub height: u32,
@@ -25,16 +34,46 @@ pub struct ShmemDataInternal {
 	pub allocation_size: u64,
 	pub gpu_device_uuid_0: u64,
 	pub gpu_device_uuid_1: u64,
+	// Server that created this segment, see ShmemDataInternal::get_server_instance_id
+	pub server_instance_id_0: u64,
+	pub server_instance_id_1: u64,
+	// Incremented by every send_image. Unlike handle_id, it doesn't change on reallocation
+	pub frame_number: u64,
+	// CLOCK_MONOTONIC time at which frame_number was written, see get_monotonic_time
+	pub frame_timestamp_nanos: u64,
+	// Number of images allocated for this name, see IMG_MAX_BUFFER_COUNT
+	pub buffer_count: u32,
+	// Index of the buffer that holds frame_number
+	pub latest_buffer: u32,
+}
+
+// Marks segments created by this library, see ShmemHeader
+pub const SHMEM_MAGIC: [u8; 8] = *b"TXSHMEM\0";
+// Increment whenever the layout of ShmemData changes, e.g. a field of ShmemDataInternal is added
+pub const SHMEM_LAYOUT_VERSION: u32 = 1;
+
+// Start of every segment. Its own layout must never change, so that segments of other versions
+// are recognized as incompatible instead of being misread
+#[repr(C)]
+pub(super) struct ShmemHeader {
+	pub(super) magic: [u8; 8],
+	pub(super) layout_version: u32,
+	// size_of::<ShmemData>() of the creator
+	pub(super) data_size: u32,
 }
 
 #[repr(C)]
 pub(super) struct ShmemData {
+	pub(super) header: ShmemHeader,
 	pub(super) rwlock_data: RwLockInternalData,
 	pub(super) data: UnsafeCell<ShmemDataInternal>,
+	// Space for the longest name and its terminating NUL
+	pub(super) name: UnsafeCell<[u8; IMG_NAME_MAX_LEN + 1]>,
+	pub(super) metadata: UnsafeCell<[u8; IMG_METADATA_MAX_LEN]>,
 }
 
 pub struct IpcShmem {
-	pub(super) lock: Box<dyn LockImpl>,
+	pub(super) lock: ShmemLock,
 	shmem: Shmem,
 }
 
@@ -42,7 +81,10 @@ impl Default for ShmemDataInternal {
 	fn default() -> Self {
 		let nil = uuid::Uuid::nil().as_u64_pair();
 		ShmemDataInternal {
-			name: [0 as u8; size_of::<ImgName>()],
+			name_offset: 0,
+			name_len: 0,
+			metadata_offset: 0,
+			metadata_len: 0,
 			handle_id: 0,
 			width: 0,
 			height: 0,
@@ -50,16 +92,18 @@ impl Default for ShmemDataInternal {
 			allocation_size: 0,
 			gpu_device_uuid_0: nil.0,
 			gpu_device_uuid_1: nil.1,
+			server_instance_id_0: nil.0,
+			server_instance_id_1: nil.1,
+			frame_number: 0,
+			frame_timestamp_nanos: 0,
+			buffer_count: 1,
+			latest_buffer: 0,
 		}
 	}
 }
 
 impl<'a> IpcShmem {
-	pub fn new(
-		name: &str,
-		img_name: &str,
-		create: bool,
-	) -> Result<IpcShmem, Box<dyn std::error::Error>> {
+	pub fn new(name: &str, img_name: &str, create: bool) -> Result<IpcShmem, Error> {
 		let conf = ShmemConf::new().os_id(name).size(size_of::<ShmemData>());
 		let shmem = if create {
 			match conf.clone().create() {
@@ -73,7 +117,7 @@ impl<'a> IpcShmem {
 				Ok(s) => s,
 			}
 		} else {
-			conf.open().map_err(|e| Box::new(e))?
+			conf.open()?
 		};
 
 		// match create {
@@ -81,35 +125,86 @@ impl<'a> IpcShmem {
 		//     false => conf.open().map_err(|e| Box::new(e))?,
 		// };
 
+		// The header is written before the segment is handed to other processes
+		match create {
+			true => IpcShmem::init_header(&shmem),
+			false => IpcShmem::check_header(&shmem)?,
+		}
+
 		let lock = IpcShmem::init_rw_lock(&shmem, !create)?;
 
+		let ipc_shmem = IpcShmem { lock, shmem };
 		if create {
-			let _rw_lock = lock.lock()?;
-			unsafe {
-				let raw_data_ptr = shmem.as_ptr().add(offset_of!(ShmemData, data));
-				*(raw_data_ptr.cast::<UnsafeCell<ShmemDataInternal>>()) = UnsafeCell::new(
-					ShmemDataInternal::new_empty(img_name).map_err(|e| Box::new(e))?,
-				);
-			}
+			let rw_lock = ipc_shmem.acquire_lock(Timeout::Infinite)?;
+			*IpcShmem::acquire_data(&rw_lock) = ShmemDataInternal::default();
+			IpcShmem::set_name(&rw_lock, img_name)?;
 		}
 
-		Ok(IpcShmem { lock, shmem })
+		Ok(ipc_shmem)
 	}
 
 	// Get Id without acquiring lock. Should be sufficient for checking if surface image has changed
-	pub fn get_id_unchecked(&self) -> u32 {
-		unsafe {
-			(self.shmem.as_ptr().add(offset_of!(ShmemData, data)) as *const ShmemDataInternal)
-				.as_ref()
-				.unwrap()
-		}
-		.handle_id
+	pub fn get_id(&self, timeout: Timeout) -> Result<u32, Error> {
+		Ok(self.read_data(timeout)?.handle_id)
+	}
+
+	// Get the server instance without acquiring lock. Only written when the segment is created
+	pub fn get_server_instance_id(&self, timeout: Timeout) -> Result<u128, Error> {
+		Ok(self.read_data(timeout)?.get_server_instance_id())
 	}
 
 	pub fn get_name(&self) -> &str {
 		self.shmem.get_os_id()
 	}
 
+	fn init_header(shmem: &Shmem) {
+		let header = ShmemHeader {
+			magic: SHMEM_MAGIC,
+			layout_version: SHMEM_LAYOUT_VERSION,
+			data_size: size_of::<ShmemData>() as u32,
+		};
+		unsafe { (shmem.as_ptr() as *mut ShmemHeader).write(header) };
+	}
+
+	// Refuse segments that weren't created with the same ShmemData layout
+	fn check_header(shmem: &Shmem) -> Result<(), Error> {
+		let incompatible = |reason: String| Error::IncompatibleShmem {
+			name: shmem.get_os_id().to_string(),
+			reason,
+		};
+
+		if shmem.len() < size_of::<ShmemHeader>() {
+			return Err(incompatible(format!(
+				"Segment of {} bytes is too small for a header",
+				shmem.len()
+			)));
+		}
+
+		let header = unsafe { (shmem.as_ptr() as *const ShmemHeader).read() };
+		if header.magic != SHMEM_MAGIC {
+			return Err(incompatible("Magic bytes don't match".to_string()));
+		}
+
+		if header.layout_version != SHMEM_LAYOUT_VERSION {
+			return Err(incompatible(format!(
+				"Layout version {} differs from {}",
+				header.layout_version, SHMEM_LAYOUT_VERSION
+			)));
+		}
+
+		let data_size = size_of::<ShmemData>();
+		if header.data_size as usize != data_size || shmem.len() < data_size {
+			return Err(incompatible(format!(
+				"Data of {} bytes in a segment of {} bytes differs from {} bytes",
+				header.data_size,
+				shmem.len(),
+				data_size
+			)));
+		}
+
+		Ok(())
+	}
+
 	fn delete_shmem(shmem_name: &str) {
 		let conf = ShmemConf::new().os_id(shmem_name);
 
@@ -125,8 +220,13 @@ impl<'a> IpcShmem {
 }
 
 impl ShmemDataInternal {
+	// Offset of ShmemData::name relative to ShmemData::data
+	pub(crate) const NAME_OFFSET: usize = offset_of!(ShmemData, name) - offset_of!(ShmemData, data);
+	// Offset of ShmemData::metadata relative to ShmemData::data
+	pub(crate) const METADATA_OFFSET: usize =
+		offset_of!(ShmemData, metadata) - offset_of!(ShmemData, data);
+
 	pub(crate) fn new(
-		name: ImgName,
 		handle_id: u32,
 		width: u32,
 		height: u32,
@@ -135,8 +235,12 @@ impl ShmemDataInternal {
 		gpu_device_uuid: u128,
 	) -> ShmemDataInternal {
 		let gpu_uuid_pair = uuid::Uuid::from_u128(gpu_device_uuid).as_u64_pair();
+		let nil = uuid::Uuid::nil().as_u64_pair();
 		ShmemDataInternal {
-			name,
+			name_offset: 0,
+			name_len: 0,
+			metadata_offset: 0,
+			metadata_len: 0,
 			handle_id,
 			width,
 			height,
@@ -144,42 +248,97 @@ impl ShmemDataInternal {
 			allocation_size,
 			gpu_device_uuid_0: gpu_uuid_pair.0,
 			gpu_device_uuid_1: gpu_uuid_pair.1,
+			server_instance_id_0: nil.0,
+			server_instance_id_1: nil.1,
+			frame_number: 0,
+			frame_timestamp_nanos: 0,
+			buffer_count: 1,
+			latest_buffer: 0,
 		}
 	}
 
-	fn new_empty(img_name: &str) -> Result<ShmemDataInternal, Error> {
-		if img_name.as_bytes().len() > size_of::<ImgName>() {
-			Err(Error::new(
-				ErrorKind::OutOfMemory,
-				format!(
-					"Image name '{img_name:?}' too long. Should be less than {:?}bytes",
-					size_of::<ImgName>()
-				),
-			))
-		} else {
-			let shmem_internal = ShmemDataInternal {
-				name: ImgData::convert_shmem_str_to_array(img_name),
-				handle_id: 0,
-				width: 0,
-				height: 0,
-				format: ImgFormat::Undefined,
-				allocation_size: 0,
-				..Default::default()
-			};
-			Ok(shmem_internal)
+	// Id of the server instance that created the image. Images of an earlier instance are stale,
+	// even if their handle_id matches
+	pub fn get_server_instance_id(&self) -> u128 {
+		uuid::Uuid::from_u64_pair(self.server_instance_id_0, self.server_instance_id_1).as_u128()
+	}
+
+	pub fn set_server_instance_id(&mut self, server_instance_id: u128) {
+		let pair = uuid::Uuid::from_u128(server_instance_id).as_u64_pair();
+		self.server_instance_id_0 = pair.0;
+		self.server_instance_id_1 = pair.1;
+	}
+
+	pub fn get_frame_info(&self) -> ImgFrameInfo {
+		ImgFrameInfo {
+			frame_number: self.frame_number,
+			timestamp_nanos: self.frame_timestamp_nanos,
 		}
 	}
+
+	// Mark a new frame as written. Call while holding the write lock of the segment
+	pub fn next_frame(&mut self) -> ImgFrameInfo {
+		self.frame_number = self.frame_number.wrapping_add(1);
+		self.frame_timestamp_nanos = get_monotonic_time().as_nanos() as u64;
+		self.get_frame_info()
+	}
+
+	// Mark a new frame as written to the given buffer. Call while holding the write lock of the
+	// segment
+	pub fn next_frame_in_buffer(&mut self, buffer: u32) -> ImgFrameInfo {
+		self.latest_buffer = buffer;
+		self.next_frame()
+	}
+}
+
+impl_wire_format!(ShmemDataInternal {
+	name_offset,
+	name_len,
+	metadata_offset,
+	metadata_len,
+	handle_id,
+	width,
+	height,
+	format,
+	allocation_size,
+	gpu_device_uuid_0,
+	gpu_device_uuid_1,
+	server_instance_id_0,
+	server_instance_id_1,
+	frame_number,
+	frame_timestamp_nanos,
+	buffer_count,
+	latest_buffer,
+});
+
+// CLOCK_MONOTONIC is shared by all processes of a machine, so timestamps of other processes can
+// be compared against it
+pub fn get_monotonic_time() -> Duration {
+	let mut ts = libc::timespec {
+		tv_sec: 0,
+		tv_nsec: 0,
+	};
+	// Can't fail for CLOCK_MONOTONIC with a valid pointer
+	unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+	Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
 }
 
 #[cfg(test)]
 mod tests {
-	use std::time::Duration;
+	use std::io::{BufRead, BufReader, Write};
+	use std::process::{Child, Command, Stdio};
+	use std::sync::atomic::{AtomicBool, Ordering};
+	use std::sync::Arc;
+	use std::thread::JoinHandle;
+	use std::time::{Duration, Instant};
 
 	use raw_sync::Timeout;
+	use shared_memory::ShmemConf;
 
-	use crate::platform::img_data::ImgData;
+	use crate::platform::img_data::{ImgMetadataEntry, IMG_METADATA_MAX_LEN, IMG_NAME_MAX_LEN};
+	use crate::Error;
 
-	use super::IpcShmem;
+	use super::{get_monotonic_time, IpcShmem, ShmemHeader, SHMEM_MAGIC};
 
 	const SHMEM_NAME: &str = "shmem_name";
 	const TIMEOUT: Timeout = Timeout::Val(Duration::from_secs(10));
@@ -190,13 +349,14 @@ mod tests {
 
 	#[test]
 	fn shmem_create() {
-		IpcShmem::new(SHMEM_NAME, &img_name(), true).expect("Failed to create shmem");
+		IpcShmem::new("shmem_create", &img_name(), true).expect("Failed to create shmem");
 	}
 
-	fn _shmem_share() -> (IpcShmem, IpcShmem) {
-		let created_shmem = IpcShmem::new(SHMEM_NAME, &img_name(), true).unwrap();
+	// Tests run in parallel, so each one shares its own segment
+	fn _shmem_share(shmem_name: &str) -> (IpcShmem, IpcShmem) {
+		let created_shmem = IpcShmem::new(shmem_name, &img_name(), true).unwrap();
 		let shared_shmem =
-			IpcShmem::new(SHMEM_NAME, &img_name(), false).expect("Failed to share shmem");
+			IpcShmem::new(shmem_name, &img_name(), false).expect("Failed to share shmem");
 
 		(created_shmem, shared_shmem)
 	}
@@ -208,12 +368,276 @@ mod tests {
 		let rlock = shmem.acquire_rlock(TIMEOUT).unwrap();
 		let rdata = IpcShmem::acquire_rdata(&rlock);
 
-		assert_eq!(ImgData::convert_shmem_array_to_str(&rdata.name), img_name());
+		assert_eq!(IpcShmem::acquire_rname(&rlock).unwrap(), img_name());
+		assert_eq!(rdata.name_len as usize, img_name().len());
+	}
+
+	#[test]
+	fn shmem_set_name() {
+		let (created_shmem, shared_shmem) = _shmem_share("shmem_set_name");
+		{
+			let lock = created_shmem.acquire_lock(TIMEOUT).unwrap();
+			IpcShmem::set_name(&lock, "new_img_name").unwrap();
+			assert!(IpcShmem::set_name(&lock, &"a".repeat(IMG_NAME_MAX_LEN + 1)).is_err());
+		}
+
+		let rlock = shared_shmem.acquire_rlock(TIMEOUT).unwrap();
+		assert_eq!(IpcShmem::acquire_rname(&rlock).unwrap(), "new_img_name");
+	}
+
+	#[test]
+	fn shmem_incompatible_header() {
+		let shmem_name = "shmem_incompatible_header";
+		let (created_shmem, _) = _shmem_share(shmem_name);
+		let header = created_shmem.shmem.as_ptr() as *mut ShmemHeader;
+		let is_incompatible =
+			|res: Result<IpcShmem, Error>| matches!(res, Err(Error::IncompatibleShmem { .. }));
+		let open = || IpcShmem::new(shmem_name, &img_name(), false);
+
+		// Segments of another layout are refused
+		unsafe { (*header).layout_version += 1 };
+		assert!(is_incompatible(open()));
+		unsafe { (*header).layout_version -= 1 };
+
+		unsafe { (*header).data_size += 8 };
+		assert!(is_incompatible(open()));
+		unsafe { (*header).data_size -= 8 };
+
+		unsafe { (*header).magic[0] = 0 };
+		assert!(is_incompatible(open()));
+		unsafe { (*header).magic = SHMEM_MAGIC };
+		assert!(open().is_ok());
+
+		// Segments that can't even hold a header
+		let small_name = "shmem_incompatible_header_small";
+		let _small_shmem = ShmemConf::new().os_id(small_name).size(4).create().unwrap();
+		let res = IpcShmem::new(small_name, &img_name(), false);
+		assert!(is_incompatible(res));
+	}
+
+	#[test]
+	fn shmem_set_metadata() {
+		let (created_shmem, shared_shmem) = _shmem_share("shmem_set_metadata");
+		{
+			let rlock = shared_shmem.acquire_rlock(TIMEOUT).unwrap();
+			assert!(IpcShmem::acquire_rmetadata(&rlock).unwrap().is_empty());
+		}
+
+		let entries = vec![
+			ImgMetadataEntry::new("color_space", "srgb").unwrap(),
+			ImgMetadataEntry::new("label", "").unwrap(),
+		];
+		{
+			let lock = created_shmem.acquire_lock(TIMEOUT).unwrap();
+			IpcShmem::set_metadata(&lock, &entries).unwrap();
+
+			let too_large =
+				vec![ImgMetadataEntry::new("key", &"a".repeat(IMG_METADATA_MAX_LEN)).unwrap()];
+			assert!(IpcShmem::set_metadata(&lock, &too_large).is_err());
+		}
+
+		let rlock = shared_shmem.acquire_rlock(TIMEOUT).unwrap();
+		let metadata = IpcShmem::acquire_rmetadata(&rlock).unwrap();
+		assert!(metadata == entries);
+		assert_eq!(metadata[0].get_value(), "srgb");
+		assert_eq!(IpcShmem::acquire_rname(&rlock).unwrap(), img_name());
+	}
+
+	#[test]
+	fn shmem_next_frame() {
+		let (created_shmem, shared_shmem) = _shmem_share("shmem_next_frame");
+		let start_time = get_monotonic_time();
+		{
+			let lock = created_shmem.acquire_lock(TIMEOUT).unwrap();
+			let data = IpcShmem::acquire_data(&lock);
+			assert_eq!(data.get_frame_info().frame_number, 0);
+
+			data.next_frame();
+			data.next_frame();
+		}
+
+		let rlock = shared_shmem.acquire_rlock(TIMEOUT).unwrap();
+		let frame_info = IpcShmem::acquire_rdata(&rlock).get_frame_info();
+		assert_eq!(frame_info.frame_number, 2);
+		assert!(Duration::from_nanos(frame_info.timestamp_nanos) >= start_time);
+		assert!(frame_info.get_age() < Duration::from_secs(10));
+	}
+
+	// Passed to shmem_lock_in_child, which only runs if they are set
+	const CHILD_SHMEM_NAME_ENV: &str = "SHMEM_TEST_CHILD_NAME";
+	const CHILD_WRITE_ENV: &str = "SHMEM_TEST_CHILD_WRITE";
+	const CHILD_LOCKED_MSG: &str = "shmem child locked";
+
+	// Started as a separate process by _lock_in_child. Takes a lock on the segment and keeps it
+	// until the process is killed
+	#[test]
+	#[ignore]
+	fn shmem_lock_in_child() {
+		let shmem_name = match std::env::var(CHILD_SHMEM_NAME_ENV) {
+			Ok(name) => name,
+			Err(_) => return,
+		};
+
+		// The guard is never dropped, the lock is held until the child dies
+		let shmem = IpcShmem::new(&shmem_name, &img_name(), false).unwrap();
+		if std::env::var(CHILD_WRITE_ENV).is_ok() {
+			std::mem::forget(shmem.acquire_lock(TIMEOUT).unwrap());
+		} else {
+			std::mem::forget(shmem.acquire_rlock(TIMEOUT).unwrap());
+		}
+
+		println!("{}", CHILD_LOCKED_MSG);
+		std::io::stdout().flush().unwrap();
+		loop {
+			std::thread::sleep(Duration::from_secs(1));
+		}
+	}
+
+	// Run shmem_lock_in_child in a new process of the test binary. Forking the multi-threaded test
+	// process instead could deadlock the child
+	fn _lock_in_child(shmem_name: &str, write: bool) -> Child {
+		let test_name = module_path!().split_once("::").unwrap().1;
+		let mut command = Command::new(std::env::current_exe().unwrap());
+		command
+			.arg(format!("{}::shmem_lock_in_child", test_name))
+			.args(["--exact", "--ignored", "--nocapture", "--test-threads=1"])
+			.env(CHILD_SHMEM_NAME_ENV, shmem_name)
+			.stdout(Stdio::piped());
+		if write {
+			command.env(CHILD_WRITE_ENV, "1");
+		}
+
+		let mut child = command.spawn().unwrap();
+		let locked = BufReader::new(child.stdout.take().unwrap())
+			.lines()
+			.any(|line| line.unwrap().contains(CHILD_LOCKED_MSG));
+		assert!(locked, "Child failed to acquire lock");
+		child
+	}
+
+	fn _kill_child(mut child: Child) {
+		child.kill().unwrap();
+		child.wait().unwrap();
+	}
+
+	#[test]
+	fn shmem_lock_owner_crash() {
+		const SHORT_TIMEOUT: Timeout = Timeout::Val(Duration::from_millis(100));
+
+		let shmem_name = "shmem_lock_owner_crash";
+		let (created_shmem, _) = _shmem_share(shmem_name);
+
+		// Writer crashes while holding the lock
+		let child = _lock_in_child(shmem_name, true);
+		assert!(created_shmem.acquire_rlock(SHORT_TIMEOUT).is_err());
+		_kill_child(child);
+		assert_eq!(created_shmem.read_data(SHORT_TIMEOUT).unwrap().width, 0);
+		{
+			let lock = created_shmem.acquire_lock(SHORT_TIMEOUT).unwrap();
+			IpcShmem::acquire_data(&lock).width = 12345;
+		}
+
+		// Reader crashes while holding the lock. Other readers can still share it
+		let child = _lock_in_child(shmem_name, false);
+		assert!(created_shmem.acquire_lock(SHORT_TIMEOUT).is_err());
+		{
+			let rlock = created_shmem.acquire_rlock(SHORT_TIMEOUT).unwrap();
+			assert_eq!(IpcShmem::acquire_rdata(&rlock).width, 12345);
+		}
+		_kill_child(child);
+		created_shmem.acquire_lock(SHORT_TIMEOUT).unwrap();
+	}
+
+	// Writes width and height with the same value, so that torn reads can be detected
+	fn _write_in_thread(shmem_name: &'static str, stop_bit: Arc<AtomicBool>) -> JoinHandle<u32> {
+		std::thread::spawn(move || {
+			let shmem = IpcShmem::new(shmem_name, &img_name(), false).unwrap();
+			let mut count = 0;
+			while !stop_bit.load(Ordering::Relaxed) {
+				count += 1;
+				let lock = shmem.acquire_lock(TIMEOUT).unwrap();
+				let data = IpcShmem::acquire_data(&lock);
+				data.width = count;
+				data.height = count;
+			}
+			count
+		})
+	}
+
+	#[test]
+	fn shmem_read_data() {
+		let shmem_name = "shmem_read_data";
+		let shmem = IpcShmem::new(shmem_name, &img_name(), true).unwrap();
+		{
+			let lock = shmem.acquire_lock(TIMEOUT).unwrap();
+			IpcShmem::acquire_data(&lock).width = 12345;
+		}
+		assert_eq!(shmem.read_data(TIMEOUT).unwrap().width, 12345);
+
+		// A thread holding the write lock can't wait for itself
+		{
+			let lock = shmem.acquire_lock(TIMEOUT).unwrap();
+			assert!(shmem.read_data(TIMEOUT).is_err());
+			IpcShmem::acquire_data(&lock).width = 0;
+		}
+
+		let stop_bit = Arc::new(AtomicBool::new(false));
+		let writer = _write_in_thread(shmem_name, stop_bit.clone());
+		let mut last_width = 0;
+		for _ in 0..100000 {
+			let data = shmem.read_data(TIMEOUT).unwrap();
+			assert_eq!(data.width, data.height);
+			assert!(data.width >= last_width);
+			last_width = data.width;
+		}
+		stop_bit.store(true, Ordering::Relaxed);
+		assert!(writer.join().unwrap() > 0);
+	}
+
+	// Compare lock-free reads with read locks. Run with
+	// cargo test --release shmem_read_benchmark -- --ignored --nocapture
+	#[test]
+	#[ignore]
+	fn shmem_read_benchmark() {
+		const READ_COUNT: u32 = 1000000;
+
+		let shmem_name = "shmem_read_benchmark";
+		let shmem = IpcShmem::new(shmem_name, &img_name(), true).unwrap();
+		let bench = |name: &str, read: &dyn Fn() -> u32| {
+			let start = Instant::now();
+			let mut sum = 0u64;
+			for _ in 0..READ_COUNT {
+				sum += read() as u64;
+			}
+			println!(
+				"{}: {:?} per read (checksum {})",
+				name,
+				start.elapsed() / READ_COUNT,
+				sum
+			);
+		};
+		let bench_all = |suffix: &str| {
+			bench(&format!("acquire_rlock{}", suffix), &|| {
+				let rlock = shmem.acquire_rlock(TIMEOUT).unwrap();
+				IpcShmem::acquire_rdata(&rlock).width
+			});
+			bench(&format!("read_data{}", suffix), &|| {
+				shmem.read_data(TIMEOUT).unwrap().width
+			});
+		};
+
+		bench_all("");
+
+		let stop_bit = Arc::new(AtomicBool::new(false));
+		let writer = _write_in_thread(shmem_name, stop_bit.clone());
+		bench_all(" with concurrent writer");
+		stop_bit.store(true, Ordering::Relaxed);
+		println!("Writer wrote {} times", writer.join().unwrap());
 	}
 
 	#[test]
 	fn shmem_share() {
-		let _ = shmem_create();
+		let _ = _shmem_share("shmem_share");
 	}
 
 	#[test]
@@ -223,7 +647,7 @@ mod tests {
 
 		//let shared_shmem = IpcShmem::new(SHMEM_NAME, &img_name(), true).unwrap();
 
-		let (created_shmem, shared_shmem) = _shmem_share();
+		let (created_shmem, shared_shmem) = _shmem_share("shmem_set_width");
 		{
 			let lock = shared_shmem.acquire_lock(TIMEOUT).unwrap();
 			let data = IpcShmem::acquire_data(&lock);
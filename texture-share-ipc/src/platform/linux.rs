@@ -1,2 +1,3 @@
 pub(super) mod ipc_shmem_lock;
 pub mod ipc_unix_socket;
+pub mod ipc_wait;
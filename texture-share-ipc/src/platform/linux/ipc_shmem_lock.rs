@@ -1,46 +1,310 @@
 use memoffset::offset_of;
-use raw_sync::{
-	locks::{LockImpl, LockInit, RwLock},
-	Timeout,
-};
+use raw_sync::Timeout;
 use shared_memory::Shmem;
 use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::ops::Deref;
+use std::ptr::addr_of_mut;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 use crate::{
-	platform::{ipc_shmem::ShmemData, ShmemDataInternal},
-	IpcShmem,
+	platform::{
+		img_data::{ImgMetadataEntry, IMG_METADATA_MAX_LEN, IMG_NAME_MAX_LEN},
+		ipc_protocol::WireFormat,
+		ipc_shmem::ShmemData,
+		ShmemDataInternal,
+	},
+	Error, IpcShmem,
 };
 
-pub type RwLockInternalData = libc::pthread_rwlock_t;
+// Number of read locks that can be held at the same time
+pub const MAX_READ_LOCKS: usize = 32;
 
-pub type ReadLockGuard<'a> = raw_sync::locks::ReadLockGuard<'a>;
-pub type LockGuard<'a> = raw_sync::locks::LockGuard<'a>;
+// Number of attempts to read a consistent copy of the data before waiting for the writer
+const SEQ_READ_SPIN_COUNT: u32 = 100;
 
-impl IpcShmem {
-	pub(crate) fn init_rw_lock(
-		shmem: &Shmem,
+// Lock state stored in shared memory. All mutexes are robust, so a mutex held by a crashed
+// process is released by the kernel and recovered by the next process that acquires it
+#[repr(C)]
+pub struct RwLockInternalData {
+	// Held by the writer, and briefly by readers while they take a read slot
+	write_mutex: libc::pthread_mutex_t,
+	// Every read lock holds one of these slots
+	read_mutexes: [libc::pthread_mutex_t; MAX_READ_LOCKS],
+	// Odd while a writer may modify the data. Readers that don't take a lock compare it before
+	// and after copying the data, see ShmemLock::read_data
+	data_seq: AtomicU64,
+}
+
+pub(crate) struct ShmemLock {
+	raw: *mut RwLockInternalData,
+	data: *mut u8,
+}
+
+// Unlocks a mutex of RwLockInternalData on drop
+struct MutexGuard {
+	mutex: *mut libc::pthread_mutex_t,
+}
+
+pub struct ReadLockGuard<'a> {
+	lock: &'a ShmemLock,
+	_slot_mutex: MutexGuard,
+}
+
+pub struct LockGuard<'a> {
+	lock: &'a ShmemLock,
+	_write_mutex: MutexGuard,
+}
+
+impl Drop for MutexGuard {
+	fn drop(&mut self) {
+		unsafe { libc::pthread_mutex_unlock(self.mutex) };
+	}
+}
+
+impl<'a> Deref for ReadLockGuard<'a> {
+	type Target = *mut u8;
+
+	fn deref(&self) -> &*mut u8 {
+		&self.lock.data
+	}
+}
+
+impl<'a> Deref for LockGuard<'a> {
+	type Target = *mut u8;
+
+	fn deref(&self) -> &*mut u8 {
+		&self.lock.data
+	}
+}
+
+impl<'a> Drop for LockGuard<'a> {
+	fn drop(&mut self) {
+		// Publish the written data. The write mutex is unlocked afterwards
+		self.lock.data_seq().fetch_add(1, Ordering::Release);
+	}
+}
+
+impl ShmemLock {
+	unsafe fn new(
+		raw: *mut RwLockInternalData,
+		data: *mut u8,
 		from_existing: bool,
-	) -> Result<Box<dyn LockImpl>, Box<dyn std::error::Error>> {
+	) -> Result<ShmemLock, Error> {
+		let lock = ShmemLock { raw, data };
+		if from_existing {
+			return Ok(lock);
+		}
+
+		let mut attr: libc::pthread_mutexattr_t = std::mem::zeroed();
+		check_res(libc::pthread_mutexattr_init(&mut attr))?;
+		// Error checking mutexes fail instead of deadlocking if a thread locks one twice
+		let res = check_res(libc::pthread_mutexattr_settype(
+			&mut attr,
+			libc::PTHREAD_MUTEX_ERRORCHECK,
+		))
+		.and_then(|_| {
+			check_res(libc::pthread_mutexattr_setpshared(
+				&mut attr,
+				libc::PTHREAD_PROCESS_SHARED,
+			))
+		})
+		.and_then(|_| {
+			check_res(libc::pthread_mutexattr_setrobust(
+				&mut attr,
+				libc::PTHREAD_MUTEX_ROBUST,
+			))
+		})
+		.and_then(|_| check_res(libc::pthread_mutex_init(lock.write_mutex(), &attr)))
+		.and_then(|_| {
+			(0..MAX_READ_LOCKS).try_for_each(|slot| {
+				check_res(libc::pthread_mutex_init(lock.read_mutex(slot), &attr))
+			})
+		});
+		libc::pthread_mutexattr_destroy(&mut attr);
+		lock.data_seq().store(0, Ordering::Relaxed);
+		res.map(|_| lock)
+	}
+
+	fn write_mutex(&self) -> *mut libc::pthread_mutex_t {
+		unsafe { addr_of_mut!((*self.raw).write_mutex) }
+	}
+
+	fn read_mutex(&self, slot: usize) -> *mut libc::pthread_mutex_t {
+		unsafe { addr_of_mut!((*self.raw).read_mutexes[slot]) }
+	}
+
+	fn data_seq(&self) -> &AtomicU64 {
+		unsafe { &(*self.raw).data_seq }
+	}
+
+	fn write(&self, timeout: Timeout) -> Result<LockGuard<'_>, Error> {
+		let deadline = get_deadline(timeout);
+		let write_mutex = lock_mutex(self.write_mutex(), deadline)?;
+
+		// Wait until all readers released their slots. No new reader can take a slot while the
+		// write mutex is held
+		for slot in 0..MAX_READ_LOCKS {
+			lock_mutex(self.read_mutex(slot), deadline)?;
+		}
+
+		// Readers without a lock retry until the guard is dropped. The sequence is already odd if
+		// the previous writer crashed
+		let data_seq = self.data_seq();
+		data_seq.store(data_seq.load(Ordering::Relaxed) | 1, Ordering::Relaxed);
+		fence(Ordering::Release);
+
+		Ok(LockGuard {
+			lock: self,
+			_write_mutex: write_mutex,
+		})
+	}
+
+	fn read(&self, timeout: Timeout) -> Result<ReadLockGuard<'_>, Error> {
+		let deadline = get_deadline(timeout);
+		loop {
+			{
+				let _write_mutex = lock_mutex(self.write_mutex(), deadline)?;
+
+				// No writer is active, an odd sequence was left behind by a crashed writer
+				let data_seq = self.data_seq();
+				if !data_seq.load(Ordering::Relaxed).is_multiple_of(2) {
+					data_seq.fetch_add(1, Ordering::Release);
+				}
+
+				for slot in 0..MAX_READ_LOCKS {
+					if let Some(slot_mutex) = try_lock_mutex(self.read_mutex(slot))? {
+						return Ok(ReadLockGuard {
+							lock: self,
+							_slot_mutex: slot_mutex,
+						});
+					}
+				}
+			}
+
+			// All slots are taken, wait for a reader to release one
+			if deadline.is_some_and(|d| Instant::now() >= d) {
+				return Err(Error::Lock(format!(
+					"All {} read locks are held",
+					MAX_READ_LOCKS
+				)));
+			}
+			std::thread::sleep(Duration::from_millis(1));
+		}
+	}
+
+	// Copy the data without taking a lock. Only waits for the read lock if a writer holds the
+	// write lock for longer
+	fn read_data(&self, timeout: Timeout) -> Result<ShmemDataInternal, Error> {
+		let data_seq = self.data_seq();
+		for _ in 0..SEQ_READ_SPIN_COUNT {
+			let seq = data_seq.load(Ordering::Acquire);
+			if seq.is_multiple_of(2) {
+				// The copy may be torn, it's only used if the sequence didn't change
+				let data = unsafe {
+					std::ptr::read_volatile(self.data as *const MaybeUninit<ShmemDataInternal>)
+				};
+				fence(Ordering::Acquire);
+				if data_seq.load(Ordering::Relaxed) == seq {
+					return Ok(unsafe { data.assume_init() });
+				}
+			}
+			std::hint::spin_loop();
+		}
+
+		// Also recovers the lock if the writer crashed
+		let rlock = self.read(timeout)?;
+		Ok(IpcShmem::acquire_rdata(&rlock).clone())
+	}
+}
+
+fn check_res(res: libc::c_int) -> Result<(), Error> {
+	match res {
+		0 => Ok(()),
+		e => Err(Error::Lock(
+			std::io::Error::from_raw_os_error(e).to_string(),
+		)),
+	}
+}
+
+fn get_deadline(timeout: Timeout) -> Option<Instant> {
+	match timeout {
+		Timeout::Infinite => None,
+		Timeout::Val(duration) => Some(Instant::now() + duration),
+	}
+}
+
+// pthread_mutex_timedlock expects an absolute CLOCK_REALTIME time
+fn get_realtime_deadline(deadline: Instant) -> libc::timespec {
+	let mut ts = libc::timespec {
+		tv_sec: 0,
+		tv_nsec: 0,
+	};
+	unsafe { libc::clock_gettime(libc::CLOCK_REALTIME, &mut ts) };
+	let time = Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+		+ deadline.saturating_duration_since(Instant::now());
+	libc::timespec {
+		tv_sec: time.as_secs() as libc::time_t,
+		tv_nsec: time.subsec_nanos() as libc::c_long,
+	}
+}
+
+fn lock_mutex(
+	mutex: *mut libc::pthread_mutex_t,
+	deadline: Option<Instant>,
+) -> Result<MutexGuard, Error> {
+	let res = match deadline {
+		None => unsafe { libc::pthread_mutex_lock(mutex) },
+		Some(deadline) => unsafe {
+			libc::pthread_mutex_timedlock(mutex, &get_realtime_deadline(deadline))
+		},
+	};
+	match res {
+		libc::ETIMEDOUT => Err(Error::Lock("Timed out waiting for lock".to_string())),
+		res => recover_mutex(mutex, res).map(|_| MutexGuard { mutex }),
+	}
+}
+
+// Returns None if the mutex is held by another thread
+fn try_lock_mutex(mutex: *mut libc::pthread_mutex_t) -> Result<Option<MutexGuard>, Error> {
+	match unsafe { libc::pthread_mutex_trylock(mutex) } {
+		libc::EBUSY => Ok(None),
+		res => recover_mutex(mutex, res).map(|_| Some(MutexGuard { mutex })),
+	}
+}
+
+// The previous owner of the mutex died while holding it. The lock only guards plain data, so it
+// can be used again right away
+fn recover_mutex(mutex: *mut libc::pthread_mutex_t, res: libc::c_int) -> Result<(), Error> {
+	match res {
+		libc::EOWNERDEAD => check_res(unsafe { libc::pthread_mutex_consistent(mutex) }),
+		res => check_res(res),
+	}
+}
+
+impl IpcShmem {
+	pub(crate) fn init_rw_lock(shmem: &Shmem, from_existing: bool) -> Result<ShmemLock, Error> {
 		let raw_rwlock_ptr = unsafe { shmem.as_ptr().add(offset_of!(ShmemData, rwlock_data)) };
 		let raw_data_ptr = unsafe { shmem.as_ptr().add(offset_of!(ShmemData, data)) };
 
-		let res = unsafe {
-			if !from_existing {
-				RwLock::new(raw_rwlock_ptr, raw_data_ptr)
-			} else {
-				RwLock::from_existing(raw_rwlock_ptr, raw_data_ptr)
-			}
-		}?;
-		assert!(res.1 <= offset_of!(ShmemData, data) - offset_of!(ShmemData, rwlock_data));
+		unsafe {
+			ShmemLock::new(
+				raw_rwlock_ptr as *mut RwLockInternalData,
+				raw_data_ptr,
+				from_existing,
+			)
+		}
+	}
 
-		Ok(res.0)
+	pub fn acquire_rlock<'a>(&'a self, timeout: Timeout) -> Result<ReadLockGuard<'a>, Error> {
+		self.lock.read(timeout)
 	}
 
-	pub fn acquire_rlock<'a>(
-		&'a self,
-		timeout: Timeout,
-	) -> Result<ReadLockGuard<'a>, Box<dyn std::error::Error>> {
-		self.lock.try_rlock(timeout)
+	// Consistent copy of the segment data. Doesn't take a lock unless a writer holds the write
+	// lock, so it is cheaper than acquire_rlock for reading a few fields
+	pub fn read_data(&self, timeout: Timeout) -> Result<ShmemDataInternal, Error> {
+		self.lock.read_data(timeout)
 	}
 
 	pub fn acquire_rdata<'a>(lock: &ReadLockGuard<'a>) -> &'a ShmemDataInternal {
@@ -54,6 +318,107 @@ impl IpcShmem {
 		}
 	}
 
+	// Image name stored in the segment. The segment is shared with other processes, so its
+	// location is checked before reading
+	pub fn acquire_rname<'a>(lock: &ReadLockGuard<'a>) -> Result<&'a str, Error> {
+		let data = IpcShmem::acquire_rdata(lock);
+		if data.name_offset as usize != ShmemDataInternal::NAME_OFFSET
+			|| data.name_len as usize > IMG_NAME_MAX_LEN
+		{
+			return Err(Error::Protocol(format!(
+				"Shared memory name at {} with {} bytes is out of bounds",
+				data.name_offset, data.name_len
+			)));
+		}
+
+		let name = unsafe {
+			std::slice::from_raw_parts(
+				lock.cast::<u8>().add(ShmemDataInternal::NAME_OFFSET),
+				data.name_len as usize,
+			)
+		};
+		std::str::from_utf8(name)
+			.map_err(|_| Error::Protocol("Shared memory name is not valid UTF-8".to_string()))
+	}
+
+	// Decode the metadata entries stored in the segment
+	pub fn acquire_rmetadata(lock: &ReadLockGuard) -> Result<Vec<ImgMetadataEntry>, Error> {
+		let data = IpcShmem::acquire_rdata(lock);
+		if data.metadata_len == 0 {
+			return Ok(Vec::new());
+		}
+
+		if data.metadata_offset as usize != ShmemDataInternal::METADATA_OFFSET
+			|| data.metadata_len as usize > IMG_METADATA_MAX_LEN
+		{
+			return Err(Error::Protocol(format!(
+				"Shared memory metadata at {} with {} bytes is out of bounds",
+				data.metadata_offset, data.metadata_len
+			)));
+		}
+
+		let mut buf = unsafe {
+			std::slice::from_raw_parts(
+				lock.cast::<u8>().add(ShmemDataInternal::METADATA_OFFSET),
+				data.metadata_len as usize,
+			)
+		};
+		let mut entries = Vec::new();
+		while !buf.is_empty() {
+			entries.push(ImgMetadataEntry::decode(&mut buf)?);
+		}
+
+		Ok(entries)
+	}
+
+	// Replace the metadata entries stored in the segment
+	pub fn set_metadata(lock: &LockGuard, entries: &[ImgMetadataEntry]) -> Result<(), Error> {
+		let mut buf = Vec::new();
+		entries.iter().for_each(|entry| entry.encode(&mut buf));
+		if buf.len() > IMG_METADATA_MAX_LEN {
+			return Err(Error::MetadataTooLarge {
+				len: buf.len(),
+				max_len: IMG_METADATA_MAX_LEN,
+			});
+		}
+
+		let metadata_buf = unsafe {
+			std::slice::from_raw_parts_mut(
+				lock.cast::<u8>().add(ShmemDataInternal::METADATA_OFFSET),
+				IMG_METADATA_MAX_LEN,
+			)
+		};
+		metadata_buf[..buf.len()].copy_from_slice(&buf);
+
+		let data = IpcShmem::acquire_data(lock);
+		data.metadata_offset = ShmemDataInternal::METADATA_OFFSET as u32;
+		data.metadata_len = buf.len() as u32;
+		Ok(())
+	}
+
+	pub fn set_name(lock: &LockGuard, name: &str) -> Result<(), Error> {
+		if name.len() > IMG_NAME_MAX_LEN {
+			return Err(Error::NameTooLong {
+				name: name.to_string(),
+				max_len: IMG_NAME_MAX_LEN,
+			});
+		}
+
+		let name_buf = unsafe {
+			std::slice::from_raw_parts_mut(
+				lock.cast::<u8>().add(ShmemDataInternal::NAME_OFFSET),
+				IMG_NAME_MAX_LEN + 1,
+			)
+		};
+		name_buf[..name.len()].copy_from_slice(name.as_bytes());
+		name_buf[name.len()] = 0u8;
+
+		let data = IpcShmem::acquire_data(lock);
+		data.name_offset = ShmemDataInternal::NAME_OFFSET as u32;
+		data.name_len = name.len() as u32;
+		Ok(())
+	}
+
 	// fn read_lock(
 	// 	&self,
 	// 	timeout: Timeout,
@@ -66,11 +431,8 @@ impl IpcShmem {
 	// 	Ok((data, lock))
 	// }
 
-	pub fn acquire_lock<'a>(
-		&'a self,
-		timeout: Timeout,
-	) -> Result<LockGuard<'a>, Box<dyn std::error::Error>> {
-		self.lock.try_lock(timeout)
+	pub fn acquire_lock<'a>(&'a self, timeout: Timeout) -> Result<LockGuard<'a>, Error> {
+		self.lock.write(timeout)
 	}
 
 	pub fn acquire_data<'a>(lock: &'a LockGuard<'a>) -> &'a mut ShmemDataInternal {
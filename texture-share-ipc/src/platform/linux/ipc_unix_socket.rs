@@ -1,65 +1,154 @@
-use std::cell::RefCell;
-use std::io::{Error, ErrorKind, IoSlice, IoSliceMut, Read, Write};
-use std::mem::size_of;
-use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, IoSlice, IoSliceMut, Read};
+use std::mem::{size_of, size_of_val};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::os::unix::net::{AncillaryData, SocketAncillary, UnixListener, UnixStream};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
-
-use crate::platform::ipc_commands::{CommandMsg, ResultMsg};
-
+use std::time::{Duration, Instant};
+
+use crate::platform::img_data::{
+	img_name, img_name_or_empty, ImgData, ImgEvent, ImgMetadataEntry, IMG_MAX_BUFFER_COUNT,
+};
+use crate::platform::ipc_commands::{
+	CommFindImages, CommGetImageMetadata, CommGetStats, CommHello, CommPing, CommSetImageMetadata,
+	CommWaitForImage, CommandMsg, CommandTag, ResultHelloAck, ResultMsg,
+	ServerStats, DEFAULT_NAMESPACE, FIND_IMAGES_MAX_COUNT, MAX_PAYLOAD_LEN,
+};
+use crate::platform::ipc_protocol::{
+	encode_frame, negotiate_version, MsgHeader, PROTOCOL_VERSION, PROTOCOL_VERSION_MIN,
+};
+use crate::platform::ipc_wait::{BlockingWait, IpcWait};
+use crate::Error;
+
+// Used to hand out unique connection ids
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+// Delay between attempts of operations that can't be polled, e.g. connecting to a socket that
+// doesn't exist yet
+const RETRY_INTERVAL: Duration = Duration::from_millis(5);
+
+// Image found by the server, with one memory handle per buffer
+pub type FoundImage = (ImgData, Vec<OwnedFd>);
+
+// Connection to a client or the server. Methods ending in _with wait with the given IpcWait, so
+// that async code can use the connection, e.g. with TokioWait. Their futures are not Send, as
+// the connection is not Sync
 pub struct IpcConnection {
 	conn: RefCell<UnixStream>,
 	//proc_id: i32,
-	timeout: Duration,
+	id: u64,
+	timeout: Cell<Duration>,
+	protocol_version: Cell<Option<u32>>,
+	// Events pushed by the server while waiting for a result
+	events: RefCell<VecDeque<ImgEvent>>,
+	// Time the last frame was received, used to detect stalled peers
+	last_recv: Cell<Instant>,
+	// Ping interval announced by the peer, if it sends heartbeats
+	heartbeat_interval: Cell<Option<Duration>>,
+	// Server instance this connection was accepted by, set by the handshake
+	server_instance_id: Cell<Option<u128>>,
+	// Namespace requested by the client during the handshake
+	namespace: RefCell<String>,
 }
 
 pub struct IpcSocket {
 	listener_socket: UnixListener,
 	pub connections: Arc<Mutex<Vec<RefCell<IpcConnection>>>>,
 	pub timeout: Duration,
+	// Random id sent to clients during the handshake, lets them detect a restarted server
+	pub server_instance_id: u128,
 }
 
 impl IpcConnection {
-	pub fn new(conn: UnixStream, timeout: Duration) -> IpcConnection {
-		conn.set_nonblocking(false).unwrap();
+	pub fn new(conn: UnixStream, timeout: Duration) -> Result<IpcConnection, Error> {
+		conn.set_nonblocking(false)?;
 
-		// TODO: Use socket timeout instead of own implementation
-		conn.set_read_timeout(Some(timeout)).unwrap();
-		conn.set_write_timeout(Some(timeout)).unwrap();
+		// Reads are polled with a deadline. The socket timeouts only prevent writes and reads
+		// from blocking forever
+		conn.set_read_timeout(Some(timeout))?;
+		conn.set_write_timeout(Some(timeout))?;
 
-		IpcConnection {
+		Ok(IpcConnection {
 			conn: RefCell::new(conn),
 			//proc_id,
-			timeout,
-		}
+			id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+			timeout: Cell::new(timeout),
+			protocol_version: Cell::new(None),
+			events: RefCell::new(VecDeque::new()),
+			last_recv: Cell::new(Instant::now()),
+			heartbeat_interval: Cell::new(None),
+			server_instance_id: Cell::new(None),
+			namespace: RefCell::new(DEFAULT_NAMESPACE.to_string()),
+		})
 	}
 
 	pub fn get_socket(&self) -> std::cell::Ref<'_, UnixStream> {
-		return self.conn.borrow();
+		self.conn.borrow()
+	}
+
+	// Unique for the lifetime of the process, unlike the socket's fd
+	pub fn get_id(&self) -> u64 {
+		self.id
 	}
 
 	pub fn try_connect(
 		socket_path: &str,
 		timeout: Duration,
 	) -> Result<Option<IpcConnection>, Error> {
-		IpcConnection::try_fcn_timeout(
+		IpcConnection::try_connect_namespace(socket_path, DEFAULT_NAMESPACE, timeout)
+	}
+
+	// Connect to the server and join the given namespace. Images of other namespaces are invisible
+	// to the connection
+	pub fn try_connect_namespace(
+		socket_path: &str,
+		namespace: &str,
+		timeout: Duration,
+	) -> Result<Option<IpcConnection>, Error> {
+		BlockingWait::block_on(IpcConnection::try_connect_namespace_with(
+			&BlockingWait,
+			socket_path,
+			namespace,
+			timeout,
+		))
+	}
+
+	pub async fn try_connect_namespace_with<W: IpcWait>(
+		wait: &W,
+		socket_path: &str,
+		namespace: &str,
+		timeout: Duration,
+	) -> Result<Option<IpcConnection>, Error> {
+		let conn = IpcConnection::try_fcn_timeout_with(
+			wait,
 			|| {
 				//let sock = UnixStream::unbound()?;
 				match UnixStream::connect(socket_path) {
-					Ok(c) => Ok(Some(IpcConnection::new(c, timeout))),
+					Ok(c) => IpcConnection::new(c, timeout).map(Some),
 					Err(e) => match e.kind() {
 						ErrorKind::AddrNotAvailable
 						| ErrorKind::NotFound
 						| ErrorKind::Interrupted
 						| ErrorKind::ConnectionRefused
 						| ErrorKind::ConnectionAborted => Ok(None),
-						_ => Err(e),
+						_ => Err(e.into()),
 					},
 				}
 			},
+			None,
 			&timeout,
 		)
+		.await?;
+
+		// Agree on a protocol version before handing out the connection
+		if let Some(conn) = &conn {
+			conn.handshake_with(wait, PROTOCOL_VERSION_MIN, PROTOCOL_VERSION, namespace)
+				.await?;
+		}
+
+		Ok(conn)
 	}
 
 	fn compute_cmsg_header_size() -> usize {
@@ -70,213 +159,861 @@ impl IpcConnection {
 	}
 
 	pub fn send_anillary_handles(&self, handles: &[RawFd]) -> Result<usize, Error> {
-		if handles.len() == 0 {
+		IpcConnection::send_handles(&self.conn.borrow(), handles)
+	}
+
+	// Send handles in a single message
+	fn send_handles(conn: &UnixStream, handles: &[RawFd]) -> Result<usize, Error> {
+		if handles.is_empty() {
 			Ok(0)
 		} else {
-			let buf = [0 as u8; 4];
+			let buf = [0u8; 4];
 
-			let abuf_len =
-				IpcConnection::compute_cmsg_header_size() + handles.len() * size_of::<RawFd>();
-			let mut abuf = vec![0 as u8; abuf_len];
+			let abuf_len = IpcConnection::compute_cmsg_header_size() + size_of_val(handles);
+			let mut abuf = vec![0u8; abuf_len];
 			let mut ancillary = SocketAncillary::new(&mut abuf);
 			if !ancillary.add_fds(handles) {
 				// This means that the compute_cmsg_header_size() fcn is incorrect
-				panic!("Failed to add file descriptors to ancillary data");
+				return Err(Error::Io(io::Error::new(
+					ErrorKind::InvalidInput,
+					"Failed to add file descriptors to ancillary data",
+				)));
 			}
-			self.conn
-				.borrow()
-				.send_vectored_with_ancillary(&[IoSlice::new(&buf)], &mut ancillary)
+			let sent = conn.send_vectored_with_ancillary(&[IoSlice::new(&buf)], &mut ancillary)?;
+			Ok(sent)
 			// let ancillary =
 			//     [AncillaryData::FileDescriptors(std::borrow::Cow::Borrowed(handles)); 1];
 			// self.conn
-			//     .send_ancillary(&[1 as u8; 4], ancillary)
+			//     .send_ancillary(&[1u8; 4], ancillary)
 			//     .map(|r| r.1)
 		}
 	}
 
+	// Receive a single message and append its handles to fds. Returns the number of bytes read
+	fn recv_handles(
+		conn: &UnixStream,
+		handle_count: usize,
+		fds: &mut Vec<OwnedFd>,
+	) -> Result<usize, Error> {
+		let mut buf = [0u8; 4];
+		let abuf_len = IpcConnection::compute_cmsg_header_size()
+			+ handle_count.saturating_sub(fds.len()) * size_of::<RawFd>();
+		let mut abuf = vec![0u8; abuf_len];
+		let mut adat = SocketAncillary::new(&mut abuf);
+		let rec = conn.recv_vectored_with_ancillary(&mut [IoSliceMut::new(&mut buf)], &mut adat)?;
+
+		for ares in adat.messages() {
+			let ares = ares.map_err(|e| {
+				Error::Protocol(format!("Received invalid ancillary data: {:?}", e))
+			})?;
+			if let AncillaryData::ScmRights(afds) = ares {
+				for fd in afds {
+					fds.push(unsafe { OwnedFd::from_raw_fd(fd) })
+				}
+			}
+		}
+
+		Ok(rec)
+	}
+
 	pub fn recv_ancillary(&self, handle_count: usize) -> Result<Vec<OwnedFd>, Error> {
-		let mut buf = [0 as u8; 4];
+		BlockingWait::block_on(self.recv_ancillary_with(&BlockingWait, handle_count))
+	}
+
+	pub async fn recv_ancillary_with<W: IpcWait>(
+		&self,
+		wait: &W,
+		handle_count: usize,
+	) -> Result<Vec<OwnedFd>, Error> {
 		let mut fds = Vec::<OwnedFd>::new();
-		let _ = IpcConnection::try_fcn_timeout(
+		let res = IpcConnection::try_fcn_timeout_with(
+			wait,
 			|| {
-				let abuf_len = IpcConnection::compute_cmsg_header_size()
-					+ (handle_count - fds.len()) * size_of::<RawFd>();
-				let mut abuf = vec![0 as u8; abuf_len];
-				let mut adat = SocketAncillary::new(&mut abuf);
-				let rec = self
-					.conn
-					.borrow()
-					.recv_vectored_with_ancillary(&mut [IoSliceMut::new(&mut buf)], &mut adat)?;
-
-				if rec > 0 {
-					for ares in adat.messages() {
-						if let AncillaryData::ScmRights(afds) = ares.unwrap() {
-							for fd in afds {
-								fds.push(unsafe { OwnedFd::from_raw_fd(fd) })
-							}
-						}
-					}
-
-					if fds.len() >= handle_count {
-						Ok(Some(fds.len()))
-					} else {
-						Ok(None)
-					}
+				let rec = IpcConnection::recv_handles(&self.conn.borrow(), handle_count, &mut fds)?;
+				if rec > 0 && fds.len() >= handle_count {
+					Ok(Some(fds.len()))
 				} else {
 					Ok(None)
 				}
 			},
-			&self.timeout,
-		)?;
+			Some(self.fd()),
+			&self.timeout.get(),
+		)
+		.await?;
+		if res.is_none() {
+			return Err(Error::Timeout(format!(
+				"Received {} of {} handles",
+				fds.len(),
+				handle_count
+			)));
+		}
 
 		Ok(fds)
 	}
 
-	pub fn send_command(&self, command_msg: CommandMsg) -> Result<(), Error> {
-		const MSG_LEN: usize = size_of::<CommandMsg>();
-		let raw_ptr: *const CommandMsg = &(command_msg);
-		let msg: &[u8; MSG_LEN] = unsafe { raw_ptr.cast::<[u8; MSG_LEN]>().as_ref().unwrap() };
-		self.conn.borrow_mut().write_all(msg)
+	// Negotiate a protocol version with the server. Must be called before any other command is
+	// sent over this connection
+	pub fn handshake(
+		&self,
+		min_version: u32,
+		max_version: u32,
+		namespace: &str,
+	) -> Result<u32, Error> {
+		BlockingWait::block_on(self.handshake_with(
+			&BlockingWait,
+			min_version,
+			max_version,
+			namespace,
+		))
 	}
 
-	pub fn send_result(&self, result_msg: ResultMsg) -> Result<(), Error> {
-		const MSG_LEN: usize = size_of::<ResultMsg>();
-		let raw_ptr: *const ResultMsg = &(result_msg);
-		let msg: &[u8; MSG_LEN] = unsafe { raw_ptr.cast::<[u8; MSG_LEN]>().as_ref().unwrap() };
-		self.conn.borrow_mut().write_all(msg)
+	pub async fn handshake_with<W: IpcWait>(
+		&self,
+		wait: &W,
+		min_version: u32,
+		max_version: u32,
+		namespace: &str,
+	) -> Result<u32, Error> {
+		self.send_command_with(
+			wait,
+			CommandMsg::Hello(CommHello {
+				min_protocol_version: min_version,
+				max_protocol_version: max_version,
+				namespace: img_name_or_empty(namespace)?,
+			}),
+		)
+		.await?;
+
+		let res_msg = self
+			.recv_result_with(wait)
+			.await?
+			.ok_or_else(|| Error::Timeout("Waiting for handshake reply".to_string()))?;
+		let ack = match &res_msg {
+			ResultMsg::Hello(ack) => ack,
+			msg => {
+				return Err(Error::Protocol(format!(
+					"Expected handshake reply, received {:?}",
+					msg.tag()
+				)))
+			}
+		};
+		if !ack.accepted {
+			return Err(Error::Protocol(format!(
+				"Server rejected protocol versions {}-{}. Server supports version {}",
+				min_version, max_version, ack.protocol_version
+			)));
+		}
+
+		self.protocol_version.set(Some(ack.protocol_version));
+		self.server_instance_id.set(Some(ack.server_instance_id));
+		*self.namespace.borrow_mut() = namespace.to_string();
+		Ok(ack.protocol_version)
 	}
 
-	pub fn recv_command_if_available(&self) -> Result<Option<CommandMsg>, Error> {
-		let mut msg = CommandMsg::default();
+	// Wait for the client's handshake and reply with the negotiated version and
+	// server_instance_id. Returns false if the client's protocol versions are not supported
+	pub fn accept_handshake(&self, server_instance_id: u128) -> Result<bool, Error> {
+		let cmd_msg = self
+			.recv_command()?
+			.ok_or_else(|| Error::Timeout("Waiting for handshake".to_string()))?;
+		self.process_handshake(&cmd_msg, server_instance_id)
+	}
 
-		const MSG_LEN: usize = size_of::<CommandMsg>();
-		let buf: &mut [u8; MSG_LEN] = unsafe {
-			(&mut msg as *mut CommandMsg)
-				.cast::<[u8; MSG_LEN]>()
-				.as_mut()
-				.unwrap()
+	// Reply to the first command received from a client, which has to be its handshake. Servers
+	// call this from their receive loop, so that waiting for the handshake doesn't block them
+	pub fn process_handshake(
+		&self,
+		cmd_msg: &CommandMsg,
+		server_instance_id: u128,
+	) -> Result<bool, Error> {
+		let hello = match cmd_msg {
+			CommandMsg::Hello(hello) => hello,
+			msg => {
+				return Err(Error::Protocol(format!(
+					"Expected handshake, received {:?}",
+					msg.tag()
+				)))
+			}
 		};
+		let version = negotiate_version(hello.min_protocol_version, hello.max_protocol_version);
+
+		self.protocol_version.set(version);
+		self.server_instance_id.set(Some(server_instance_id));
+		*self.namespace.borrow_mut() = hello.namespace.to_string();
+		self.send_result(ResultMsg::Hello(ResultHelloAck {
+			accepted: version.is_some(),
+			protocol_version: version.unwrap_or(PROTOCOL_VERSION),
+			server_instance_id,
+		}))?;
+
+		Ok(version.is_some())
+	}
 
-		// Check if a message is waiting
-		let first_read = match self.conn.borrow_mut().read(buf) {
-			Err(e) => match e.kind() {
-				ErrorKind::WouldBlock => Ok(0 as usize),
-				_ => Err(e),
+	// False until a handshake with a compatible protocol version completed
+	pub fn is_handshake_complete(&self) -> bool {
+		self.protocol_version.get().is_some()
+	}
+
+	pub fn get_protocol_version(&self) -> Option<u32> {
+		self.protocol_version.get()
+	}
+
+	// None until the handshake completed
+	pub fn get_server_instance_id(&self) -> Option<u128> {
+		self.server_instance_id.get()
+	}
+
+	pub fn get_namespace(&self) -> String {
+		self.namespace.borrow().clone()
+	}
+
+	pub fn get_last_recv(&self) -> Instant {
+		self.last_recv.get()
+	}
+
+	pub fn get_heartbeat_interval(&self) -> Option<Duration> {
+		self.heartbeat_interval.get()
+	}
+
+	pub fn set_heartbeat_interval(&self, heartbeat_interval: Option<Duration>) {
+		self.heartbeat_interval.set(heartbeat_interval);
+	}
+
+	// Send a Ping and wait up to timeout for the matching Pong. Returns the round trip time, or
+	// None if the peer did not answer in time
+	pub fn ping(
+		&self,
+		sequence: u64,
+		heartbeat_interval: Option<Duration>,
+		timeout: Duration,
+	) -> Result<Option<Duration>, Error> {
+		let start_time = Instant::now();
+		self.send_command(CommandMsg::Ping(CommPing {
+			sequence,
+			heartbeat_interval_millis: heartbeat_interval
+				.map_or(0, |i| i.as_millis().max(1) as u64),
+		}))?;
+
+		// Use the ping timeout instead of the connection's timeout while waiting
+		let timeout = timeout.max(Duration::from_millis(1));
+		let conn_timeout = self.timeout.replace(timeout);
+		self.conn.borrow().set_read_timeout(Some(timeout))?;
+		let res = self.recv_pong(sequence, start_time + timeout);
+		self.timeout.set(conn_timeout);
+		self.conn.borrow().set_read_timeout(Some(conn_timeout))?;
+
+		Ok(res?.map(|_| start_time.elapsed()))
+	}
+
+	fn recv_pong(&self, sequence: u64, deadline: Instant) -> Result<Option<()>, Error> {
+		loop {
+			let msg = match BlockingWait::block_on(self.recv_next_result_with(&BlockingWait))? {
+				Some(msg) => msg,
+				None => return Ok(None),
+			};
+			let pong = match &msg {
+				ResultMsg::Ping(pong) => pong,
+				msg => {
+					return Err(Error::Protocol(format!(
+						"Expected pong, received {:?}",
+						msg.tag()
+					)))
+				}
+			};
+
+			// Pongs of earlier pings that timed out are skipped
+			if pong.sequence == sequence {
+				return Ok(Some(()));
+			}
+
+			if Instant::now() > deadline {
+				return Ok(None);
+			}
+		}
+	}
+
+	// Request the server's state. Returns None if the server did not answer in time
+	pub fn get_stats(&self) -> Result<Option<ServerStats>, Error> {
+		self.send_command(CommandMsg::GetStats(CommGetStats {}))?;
+
+		// The server sends one result per image and device
+		let mut stats = ServerStats::default();
+		let mut entry_count = 0;
+		loop {
+			let res_msg = self.recv_result()?;
+			let res_data = match &res_msg {
+				None if entry_count == 0 => return Ok(None),
+				None => Err(Error::Timeout("Receiving server stats".to_string())),
+				Some(ResultMsg::GetStats(res)) => Ok(res),
+				Some(msg) => Err(Error::Protocol(format!(
+					"Expected stats, received {:?}",
+					msg.tag()
+				))),
+			}?;
+
+			if res_data.entry_index != entry_count {
+				return Err(Error::Protocol(
+					"Received server stats out of order".to_string(),
+				));
+			}
+
+			stats.connection_count = res_data.connection_count;
+			stats.gpu_copy_count = res_data.gpu_copy_count;
+			if entry_count < res_data.image_count {
+				stats.images.push(res_data.image.clone());
+			} else if entry_count - res_data.image_count < res_data.device_count {
+				stats.devices.push(res_data.device.clone());
+			}
+			entry_count += 1;
+
+			if entry_count >= res_data.image_count.saturating_add(res_data.device_count) {
+				break;
+			}
+		}
+
+		Ok(Some(stats))
+	}
+
+	// Set the metadata entry key of an image, or remove it if value is None. Returns None if the
+	// image was not found
+	pub fn set_image_metadata(
+		&self,
+		image_name: &str,
+		key: &str,
+		value: Option<&str>,
+	) -> Result<Option<()>, Error> {
+		self.send_command(CommandMsg::SetImageMetadata(CommSetImageMetadata {
+			image_name: img_name(image_name)?,
+			entry: ImgMetadataEntry::new(key, value.unwrap_or_default())?,
+			remove: value.is_none(),
+		}))?;
+
+		let res_msg = self
+			.recv_result()?
+			.ok_or_else(|| Error::Timeout("Setting image metadata".to_string()))?;
+		match res_msg {
+			ResultMsg::SetImageMetadata(res) => match res.image_found {
+				true => Ok(Some(())),
+				false => Ok(None),
 			},
-			s => s,
-		}?;
-		if first_read == 0 {
-			return Ok(None);
+			msg => Err(Error::Protocol(format!(
+				"Expected image metadata, received {:?}",
+				msg.tag()
+			))),
 		}
+	}
 
-		let mut rec_bytes: usize = first_read;
-		let recv_res = IpcConnection::try_fcn_timeout(
-			|| {
-				let rec_buf: &mut [u8] = buf.split_at_mut(rec_bytes).1;
-				self.conn.borrow_mut().read_exact(rec_buf)?;
-				rec_bytes += rec_buf.len();
+	// Request all metadata entries of an image. Returns None if the image was not found or the
+	// server did not answer in time
+	pub fn get_image_metadata(
+		&self,
+		image_name: &str,
+	) -> Result<Option<Vec<ImgMetadataEntry>>, Error> {
+		self.send_command(CommandMsg::GetImageMetadata(CommGetImageMetadata {
+			image_name: img_name(image_name)?,
+		}))?;
+
+		// The server sends one result per entry
+		let mut entries = Vec::new();
+		loop {
+			let res_msg = self.recv_result()?;
+			let res_data = match &res_msg {
+				None if entries.is_empty() => return Ok(None),
+				None => Err(Error::Timeout("Receiving image metadata".to_string())),
+				Some(ResultMsg::GetImageMetadata(res)) => Ok(res),
+				Some(msg) => Err(Error::Protocol(format!(
+					"Expected image metadata, received {:?}",
+					msg.tag()
+				))),
+			}?;
+
+			if !res_data.image_found {
+				return Ok(None);
+			}
 
-				if rec_bytes >= MSG_LEN {
-					Ok::<Option<()>, Error>(Some(()))
-				} else {
-					Ok(None)
+			if res_data.entry_index as usize != entries.len() {
+				return Err(Error::Protocol(
+					"Received image metadata out of order".to_string(),
+				));
+			}
+
+			if res_data.entry_count > 0 {
+				entries.push(res_data.entry.clone());
+			}
+
+			if entries.len() >= res_data.entry_count as usize {
+				break;
+			}
+		}
+
+		Ok(Some(entries))
+	}
+
+	// Wait until the server reports that an image with this name is initialized. Returns false if
+	// it wasn't within timeout. The image still has to be imported with FindImage
+	pub fn wait_for_image(&self, image_name: &str, timeout: Duration) -> Result<bool, Error> {
+		self.send_command(CommandMsg::WaitForImage(CommWaitForImage {
+			image_name: img_name(image_name)?,
+			timeout_millis: timeout.as_millis().min(u64::MAX as u128) as u64,
+		}))?;
+
+		// The server answers after up to timeout, allow for the usual delay on top
+		let conn_timeout = self.timeout.get();
+		let wait_timeout = timeout.saturating_add(conn_timeout);
+		self.timeout.set(wait_timeout);
+		self.conn.borrow().set_read_timeout(Some(wait_timeout))?;
+		let res = self.recv_result();
+		self.timeout.set(conn_timeout);
+		self.conn.borrow().set_read_timeout(Some(conn_timeout))?;
+
+		let res_msg = res?.ok_or_else(|| Error::Timeout("Waiting for image".to_string()))?;
+		match res_msg {
+			ResultMsg::WaitForImage(res) => Ok(res.image_found),
+			msg => Err(Error::Protocol(format!(
+				"Expected image wait result, received {:?}",
+				msg.tag()
+			))),
+		}
+	}
+
+	// Look up several images, with one round trip per FIND_IMAGES_MAX_COUNT names. Found images
+	// are returned with one memory handle per buffer, ordered like image_names. Returns None if
+	// the server did not answer in time
+	pub fn find_images(
+		&self,
+		image_names: &[&str],
+		gpu_device_uuid: u128,
+	) -> Result<Option<Vec<Option<FoundImage>>>, Error> {
+		// Validate all names before anything is sent
+		let image_names = image_names
+			.iter()
+			.map(|name| img_name(name))
+			.collect::<Result<Vec<_>, _>>()?;
+
+		let mut images = Vec::with_capacity(image_names.len());
+		for chunk in image_names.chunks(FIND_IMAGES_MAX_COUNT) {
+			let mut cmd = CommFindImages {
+				image_count: chunk.len() as u32,
+				gpu_device_uuid,
+				..Default::default()
+			};
+			cmd.image_names[..chunk.len()].clone_from_slice(chunk);
+			self.send_command(CommandMsg::FindImages(cmd))?;
+
+			// The server sends one result per name
+			let mut img_datas = Vec::with_capacity(chunk.len());
+			while img_datas.len() < chunk.len() {
+				let res_msg = self.recv_result()?;
+				let res_data = match &res_msg {
+					None if images.is_empty() && img_datas.is_empty() => return Ok(None),
+					None => Err(Error::Timeout("Receiving found images".to_string())),
+					Some(ResultMsg::FindImages(res)) => Ok(res),
+					Some(msg) => Err(Error::Protocol(format!(
+						"Expected found images, received {:?}",
+						msg.tag()
+					))),
+				}?;
+
+				if res_data.image_index as usize != img_datas.len()
+					|| res_data.image_count as usize != chunk.len()
+				{
+					return Err(Error::Protocol(
+						"Received found images out of order".to_string(),
+					));
 				}
-			},
-			&self.timeout,
-		)?;
 
-		Ok(recv_res.and_then(|_| Some(msg)))
+				img_datas.push(match res_data.image_found {
+					true => Some(res_data.img_data.clone()),
+					false => None,
+				});
+			}
+
+			// Handles of all buffers of all found images arrive in a single message
+			let mut handle_count = 0;
+			for img_data in img_datas.iter().flatten() {
+				let buffer_count = img_data.data.buffer_count;
+				if buffer_count == 0 || buffer_count > IMG_MAX_BUFFER_COUNT {
+					return Err(Error::Protocol(format!(
+						"Image buffer count {} out of range 1-{}",
+						buffer_count, IMG_MAX_BUFFER_COUNT
+					)));
+				}
+				handle_count += buffer_count as usize;
+			}
+
+			let mut handles = Vec::new();
+			if handle_count > 0 {
+				handles = self.recv_ancillary(handle_count)?;
+				self.send_ack()?;
+			}
+
+			if handles.len() != handle_count {
+				return Err(Error::Protocol(format!(
+					"Received {} handles for {} image buffers",
+					handles.len(),
+					handle_count
+				)));
+			}
+
+			let mut handles = handles.into_iter();
+			images.extend(img_datas.into_iter().map(|img_data| {
+				img_data.map(|img_data| {
+					let buffer_handles = handles
+						.by_ref()
+						.take(img_data.data.buffer_count as usize)
+						.collect();
+					(img_data, buffer_handles)
+				})
+			}));
+		}
+
+		Ok(Some(images))
 	}
 
-	#[allow(dead_code)]
-	pub fn recv_command(&self) -> Result<Option<CommandMsg>, Error> {
-		let mut msg = CommandMsg::default();
+	fn fd(&self) -> RawFd {
+		self.conn.borrow().as_raw_fd()
+	}
 
-		const MSG_LEN: usize = size_of::<CommandMsg>();
-		let buf: &mut [u8; MSG_LEN] = unsafe {
-			(&mut msg as *mut CommandMsg)
-				.cast::<[u8; MSG_LEN]>()
-				.as_mut()
-				.unwrap()
-		};
+	async fn send_frame_with<W: IpcWait>(
+		&self,
+		wait: &W,
+		tag: CommandTag,
+		payload: &[u8],
+	) -> Result<(), Error> {
+		let buf = encode_frame(
+			self.protocol_version.get().unwrap_or(PROTOCOL_VERSION),
+			tag,
+			payload,
+		);
+		self.write_all_with(wait, &buf).await
+	}
+
+	// Writes don't block, so that async waits aren't stalled by a full socket buffer
+	async fn write_all_with<W: IpcWait>(&self, wait: &W, buf: &[u8]) -> Result<(), Error> {
+		let deadline = Instant::now() + self.timeout.get();
+		let mut sent: usize = 0;
+		while sent < buf.len() {
+			if !wait.wait_fd(self.fd(), libc::POLLOUT, deadline).await? {
+				return Err(Error::Timeout("Sending message".to_string()));
+			}
+
+			// Closed sockets are reported as errors instead of raising SIGPIPE
+			let res = unsafe {
+				libc::send(
+					self.fd(),
+					buf[sent..].as_ptr().cast(),
+					buf.len() - sent,
+					libc::MSG_DONTWAIT | libc::MSG_NOSIGNAL,
+				)
+			};
+			if res < 0 {
+				let e = io::Error::last_os_error();
+				match e.kind() {
+					ErrorKind::WouldBlock | ErrorKind::Interrupted => continue,
+					_ => return Err(e.into()),
+				}
+			}
+
+			sent += res as usize;
+		}
+
+		Ok(())
+	}
+
+	// Read until buf is full. Returns None if no data arrived before the timeout
+	async fn recv_exact_with<W: IpcWait>(
+		&self,
+		wait: &W,
+		buf: &mut [u8],
+	) -> Result<Option<()>, Error> {
+		// Reading into an empty buffer would block until the next frame arrives
+		if buf.is_empty() {
+			return Ok(Some(()));
+		}
 
 		let mut rec_bytes: usize = 0;
-		let recv_res = IpcConnection::try_fcn_timeout(
+		IpcConnection::try_fcn_timeout_with(
+			wait,
 			|| {
-				let rec_buf: &mut [u8] = buf.split_at_mut(rec_bytes).1;
-				self.conn.borrow_mut().read_exact(rec_buf)?;
-				rec_bytes += rec_buf.len();
+				let rec = self.conn.borrow_mut().read(&mut buf[rec_bytes..])?;
+				if rec == 0 && rec_bytes < buf.len() {
+					return Err(Error::Io(io::Error::new(
+						ErrorKind::BrokenPipe,
+						"Connection closed by peer",
+					)));
+				}
 
-				if rec_bytes >= MSG_LEN {
-					Ok::<Option<()>, Error>(Some(()))
+				rec_bytes += rec;
+				if rec_bytes >= buf.len() {
+					Ok(Some(()))
 				} else {
 					Ok(None)
 				}
 			},
-			&self.timeout,
+			Some(self.fd()),
+			&self.timeout.get(),
+		)
+		.await
+		.and_then(|r| match r {
+			// Once a frame has been started, the stream is unusable if it is not completed
+			None if rec_bytes > 0 => Err(Error::Timeout("Receiving message".to_string())),
+			r => Ok(r),
+		})
+	}
+
+	async fn recv_header_with<W: IpcWait>(
+		&self,
+		wait: &W,
+		first_bytes: &[u8],
+	) -> Result<Option<MsgHeader>, Error> {
+		let mut header_buf = [0u8; MsgHeader::SIZE];
+		header_buf[..first_bytes.len()].copy_from_slice(first_bytes);
+
+		let res = self
+			.recv_exact_with(wait, &mut header_buf[first_bytes.len()..])
+			.await;
+		let res = match res {
+			Ok(None) if !first_bytes.is_empty() => {
+				Err(Error::Timeout("Receiving message header".to_string()))
+			}
+			r => r,
+		}?;
+		if res.is_some() {
+			self.last_recv.set(Instant::now());
+		}
+
+		Ok(res.map(|_| MsgHeader::from_bytes(&header_buf)))
+	}
+
+	fn recv_command_frame(&self, header: MsgHeader) -> Result<CommandMsg, Error> {
+		let tag = header.get_tag(self.protocol_version.get())?;
+		header.check_payload_len(MAX_PAYLOAD_LEN)?;
+
+		let mut buf = vec![0u8; header.payload_len as usize];
+		BlockingWait::block_on(self.recv_exact_with(&BlockingWait, &mut buf))?
+			.ok_or_else(|| Error::Timeout("Receiving command".to_string()))?;
+
+		CommandMsg::decode_payload(tag, &buf)
+	}
+
+	pub fn send_command(&self, command_msg: CommandMsg) -> Result<(), Error> {
+		BlockingWait::block_on(self.send_command_with(&BlockingWait, command_msg))
+	}
+
+	pub async fn send_command_with<W: IpcWait>(
+		&self,
+		wait: &W,
+		command_msg: CommandMsg,
+	) -> Result<(), Error> {
+		self.send_frame_with(wait, command_msg.tag(), &command_msg.encode_payload())
+			.await
+	}
+
+	pub fn send_result(&self, result_msg: ResultMsg) -> Result<(), Error> {
+		BlockingWait::block_on(self.send_frame_with(
+			&BlockingWait,
+			result_msg.tag(),
+			&result_msg.encode_payload(),
+		))
+	}
+
+	pub fn recv_command_if_available(&self) -> Result<Option<CommandMsg>, Error> {
+		let mut first_bytes = [0u8; MsgHeader::SIZE];
+
+		// Check if a message is waiting
+		let first_read = match self.conn.borrow_mut().read(&mut first_bytes) {
+			Err(e) => match e.kind() {
+				ErrorKind::WouldBlock => return Ok(None),
+				_ => Err(e),
+			},
+			s => s,
+		}?;
+		if first_read == 0 {
+			// A blocking read only returns no data once the peer closed the socket
+			return Err(Error::Io(io::Error::new(
+				ErrorKind::BrokenPipe,
+				"Connection closed by peer",
+			)));
+		}
+
+		let header = BlockingWait::block_on(
+			self.recv_header_with(&BlockingWait, &first_bytes[..first_read]),
 		)?;
+		match header {
+			Some(header) => self.recv_command_frame(header).map(Some),
+			None => Ok(None),
+		}
+	}
 
-		Ok(recv_res.and_then(|_| Some(msg)))
+	pub fn recv_command(&self) -> Result<Option<CommandMsg>, Error> {
+		let header = BlockingWait::block_on(self.recv_header_with(&BlockingWait, &[]))?;
+		match header {
+			Some(header) => self.recv_command_frame(header).map(Some),
+			None => Ok(None),
+		}
 	}
 
 	pub fn recv_result(&self) -> Result<Option<ResultMsg>, Error> {
-		let mut msg = ResultMsg::default();
+		BlockingWait::block_on(self.recv_result_with(&BlockingWait))
+	}
 
-		const MSG_LEN: usize = size_of::<ResultMsg>();
-		let buf: &mut [u8; MSG_LEN] = unsafe {
-			(&mut msg as *mut ResultMsg)
-				.cast::<[u8; MSG_LEN]>()
-				.as_mut()
-				.unwrap()
-		};
+	pub async fn recv_result_with<W: IpcWait>(&self, wait: &W) -> Result<Option<ResultMsg>, Error> {
+		loop {
+			match self.recv_next_result_with(wait).await? {
+				// A pong arriving here belongs to a ping that already timed out
+				Some(ResultMsg::Ping(_)) => continue,
+				res => return Ok(res),
+			}
+		}
+	}
 
-		let mut rec_bytes: usize = 0;
-		let recv_res = IpcConnection::try_fcn_timeout(
-			|| {
-				let rec_buf = buf.split_at_mut(rec_bytes).1;
-				self.conn.borrow_mut().read_exact(rec_buf)?;
-				rec_bytes += rec_buf.len();
+	// Receive the next result. Events are queued and error results are converted to errors
+	async fn recv_next_result_with<W: IpcWait>(
+		&self,
+		wait: &W,
+	) -> Result<Option<ResultMsg>, Error> {
+		loop {
+			let header = self.recv_header_with(wait, &[]).await?;
+			let msg = match header {
+				Some(header) => self.recv_result_frame_with(wait, header).await?,
+				None => return Ok(None),
+			};
 
-				if rec_bytes >= MSG_LEN {
-					Ok::<Option<()>, Error>(Some(()))
-				} else {
-					Ok(None)
+			// Events can arrive at any time, queue them for recv_event_if_available
+			match msg {
+				ResultMsg::ImageEvent(event) => self.events.borrow_mut().push_back(event),
+				ResultMsg::Error(err) => return Err(Error::Server(err.to_server_error())),
+				msg => return Ok(Some(msg)),
+			}
+		}
+	}
+
+	// Return an event pushed by the server, without waiting for one
+	pub fn recv_event_if_available(&self) -> Result<Option<ImgEvent>, Error> {
+		BlockingWait::block_on(self.recv_event_if_available_with(&BlockingWait))
+	}
+
+	pub async fn recv_event_if_available_with<W: IpcWait>(
+		&self,
+		wait: &W,
+	) -> Result<Option<ImgEvent>, Error> {
+		loop {
+			if let Some(event) = self.events.borrow_mut().pop_front() {
+				return Ok(Some(event));
+			}
+
+			let mut first_bytes = [0u8; MsgHeader::SIZE];
+			if !wait.wait_fd(self.fd(), libc::POLLIN, Instant::now()).await? {
+				return Ok(None);
+			}
+
+			// The socket is readable, so no data means the peer closed it
+			let first_read = self.conn.borrow_mut().read(&mut first_bytes)?;
+			if first_read == 0 {
+				return Err(Error::Io(io::Error::new(
+					ErrorKind::UnexpectedEof,
+					"Connection closed by peer",
+				)));
+			}
+
+			let msg = match self.recv_header_with(wait, &first_bytes[..first_read]).await? {
+				Some(header) => self.recv_result_frame_with(wait, header).await?,
+				None => return Ok(None),
+			};
+			match msg {
+				ResultMsg::ImageEvent(event) => self.events.borrow_mut().push_back(event),
+				msg => {
+					return Err(Error::Protocol(format!(
+						"Received unexpected {:?} result",
+						msg.tag()
+					)))
 				}
-			},
-			&self.timeout,
-		)?;
+			}
+		}
+	}
+
+	async fn recv_result_frame_with<W: IpcWait>(
+		&self,
+		wait: &W,
+		header: MsgHeader,
+	) -> Result<ResultMsg, Error> {
+		let tag = header.get_tag(self.protocol_version.get())?;
+		header.check_payload_len(MAX_PAYLOAD_LEN)?;
 
-		Ok(recv_res.and_then(|_| Some(msg)))
+		let mut buf = vec![0u8; header.payload_len as usize];
+		self.recv_exact_with(wait, &mut buf)
+			.await?
+			.ok_or_else(|| Error::Timeout("Receiving result".to_string()))?;
+
+		ResultMsg::decode_payload(tag, &buf)
 	}
 
 	pub fn send_ack(&self) -> Result<(), Error> {
-		self.conn.borrow_mut().write_all(&[1 as u8]).map(|_| ())
+		BlockingWait::block_on(self.send_ack_with(&BlockingWait))
+	}
+
+	pub async fn send_ack_with<W: IpcWait>(&self, wait: &W) -> Result<(), Error> {
+		self.write_all_with(wait, &[1u8]).await
 	}
 
 	pub fn recv_ack(&self) -> Result<Option<()>, Error> {
-		let mut buf = [0 as u8];
+		let mut buf = [0u8];
 		IpcConnection::try_fcn_timeout(
 			|| {
-				self.conn
-					.borrow_mut()
-					.read_exact(&mut buf)
-					.map(|_| Some(()))
+				self.conn.borrow_mut().read_exact(&mut buf)?;
+				Ok(Some(()))
 			},
-			&self.timeout,
+			Some(self.fd()),
+			&self.timeout.get(),
 		)
 	}
 
-	fn try_fcn_timeout<R, F: FnMut() -> Result<Option<R>, Error>>(
+	fn try_fcn_timeout<R, F>(
+		f: F,
+		wait_fd: Option<RawFd>,
+		timeout: &Duration,
+	) -> Result<Option<R>, Error>
+	where
+		F: FnMut() -> Result<Option<R>, Error>,
+	{
+		BlockingWait::block_on(IpcConnection::try_fcn_timeout_with(
+			&BlockingWait,
+			f,
+			wait_fd,
+			timeout,
+		))
+	}
+
+	// Call f until it returns a value or the timeout passes. Before each attempt, wait until
+	// wait_fd is readable. Without an fd, the first attempt is made immediately and later ones
+	// are spaced by RETRY_INTERVAL
+	async fn try_fcn_timeout_with<R, F, W>(
+		wait: &W,
 		mut f: F,
+		wait_fd: Option<RawFd>,
 		timeout: &Duration,
-	) -> Result<Option<R>, Error> {
-		let start_time = SystemTime::now();
+	) -> Result<Option<R>, Error>
+	where
+		F: FnMut() -> Result<Option<R>, Error>,
+		W: IpcWait,
+	{
+		let deadline = Instant::now() + *timeout;
+		let mut first_attempt = true;
 		loop {
+			match wait_fd {
+				Some(fd) if !wait.wait_fd(fd, libc::POLLIN, deadline).await? => break Ok(None),
+				Some(_) => {}
+				None if !first_attempt => {
+					wait.sleep_until(deadline.min(Instant::now() + RETRY_INTERVAL))
+						.await
+				}
+				None => {}
+			}
+			first_attempt = false;
+
 			let r = match f() {
-				Err(e) => match e.kind() {
-					ErrorKind::WouldBlock => None,
-					_ => Err(e)?,
-				},
+				Err(Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => None,
+				Err(e) => Err(e)?,
 				Ok(r) => r,
 			};
 
@@ -284,7 +1021,7 @@ impl IpcConnection {
 				break Ok(r);
 			}
 
-			if SystemTime::now().duration_since(start_time).unwrap() > *timeout {
+			if Instant::now() >= deadline {
 				break Ok(None);
 			}
 		}
@@ -299,28 +1036,26 @@ impl IpcSocket {
 					ErrorKind::AddrInUse
 					| ErrorKind::AddrNotAvailable
 					| ErrorKind::AlreadyExists => Ok(None),
-					_ => Err(e),
+					_ => Err(e.into()),
 				},
 				Ok(r) => Ok(Some(r)),
 			},
+			None,
 			&timeout,
 		)?
-		.expect("Failed to create socket");
+		.ok_or_else(|| Error::Timeout(format!("Binding socket '{}'", socket_path)))?;
 		listener_socket.set_nonblocking(false)?;
 
-		// TODO: Use socket timeout instead of own implementation
-		// listener_socket.set_read_timeout(Some(timeout)).unwrap();
-		// listener_socket.set_write_timeout(Some(timeout)).unwrap();
-
 		Ok(IpcSocket {
 			listener_socket,
 			connections: Arc::new(Mutex::new(Vec::new())),
 			timeout,
+			server_instance_id: uuid::Uuid::new_v4().as_u128(),
 		})
 	}
 
 	pub fn get_socket(&self) -> &UnixListener {
-		return &self.listener_socket;
+		&self.listener_socket
 	}
 
 	pub fn try_accept(&self) -> Result<Option<()>, Error> {
@@ -330,38 +1065,50 @@ impl IpcSocket {
 				match self.listener_socket.accept() {
 					Err(e) => match e.kind() {
 						ErrorKind::WouldBlock => Ok(None),
-						_ => Err(e),
+						_ => Err(e.into()),
 					},
-					Ok(c) => {
-						let ipc_conn = IpcConnection::new(c.0, self.timeout);
-						self.connections
-							.lock()
-							.unwrap()
-							.push(RefCell::new(ipc_conn));
-						Ok(Some(()))
-					}
+					Ok(c) => IpcConnection::new(c.0, self.timeout).map(Some),
 				}
 			},
+			Some(self.listener_socket.as_raw_fd()),
 			&self.timeout,
 		)?;
 
-		if res.is_some() {
-			Ok(Some(()))
-		} else {
-			Ok(None)
-		}
+		// The handshake is the connection's first message, processed with its other commands
+		let ipc_conn = match res {
+			Some(c) => c,
+			None => return Ok(None),
+		};
+
+		self.connections
+			.lock()
+			.map_err(|e| Error::Lock(e.to_string()))?
+			.push(RefCell::new(ipc_conn));
+
+		Ok(Some(()))
 	}
 }
 
 #[cfg(test)]
 mod tests {
+	use std::io::Write;
 	use std::thread;
 	use std::{fs, os::fd::AsRawFd};
 
 	use super::*;
+	use crate::platform::img_data::{
+		DeviceStatsEntry, ImgEventKind, ImgListEntry, ImgStatsEntry, IMG_METADATA_MAX_LEN,
+		IMG_NAME_MAX_LEN,
+	};
+	use crate::platform::ipc_commands::{
+		CommFindImage, ResultError, ResultErrorCode, ResultFindImage, ResultFindImages,
+		ResultGetImageMetadata, ResultGetStats, ResultListImages, ResultPong, ResultSetImageMetadata,
+		ResultWaitForImage,
+	};
 
 	const TIMEOUT: Duration = Duration::from_millis(10000);
 	const SOCK_PATH: &str = "test_socket.sock";
+	const SERVER_INSTANCE_ID: u128 = 0x1234;
 
 	#[test]
 	fn socket_creation() {
@@ -369,14 +1116,22 @@ mod tests {
 		let _ = IpcSocket::new(SOCK_PATH, TIMEOUT).unwrap();
 	}
 
+	// Accept a connection and process its handshake, like the server's receive loop does
+	fn _accept_handshake(listener: &IpcSocket) -> Result<bool, Error> {
+		listener
+			.try_accept()?
+			.expect("Failed to listen for connection");
+		let conn_lock = listener.connections.lock().unwrap();
+		let server_conn = conn_lock.last().unwrap().borrow();
+		server_conn.accept_handshake(listener.server_instance_id)
+	}
+
 	fn _ipc_stream_create() -> (IpcSocket, IpcConnection) {
 		thread::sleep(Duration::from_secs(1));
 
 		let listen_thread = move || {
 			let listener = IpcSocket::new(SOCK_PATH, TIMEOUT)?;
-			let _ = listener
-				.try_accept()?
-				.expect("Failed to listen for connection");
+			assert!(_accept_handshake(&listener)?);
 			Ok::<_, Error>(listener)
 		};
 
@@ -397,7 +1152,157 @@ mod tests {
 	#[test]
 	fn ipc_stream_create() {
 		let _ = fs::remove_file(SOCK_PATH);
-		let _ = _ipc_stream_create();
+		let (listener, conn) = _ipc_stream_create();
+		let server_instance_id = listener.server_instance_id;
+		assert_eq!(conn.get_server_instance_id(), Some(server_instance_id));
+		drop(listener);
+
+		// Every server instance gets its own id
+		let _ = fs::remove_file(SOCK_PATH);
+		let (_listener, conn) = _ipc_stream_create();
+		assert_ne!(conn.get_server_instance_id(), Some(server_instance_id));
+	}
+
+	fn _raw_stream_create() -> UnixStream {
+		IpcConnection::try_fcn_timeout(
+			|| match UnixStream::connect(SOCK_PATH) {
+				Ok(c) => Ok(Some(c)),
+				Err(_) => Ok(None),
+			},
+			None,
+			&TIMEOUT,
+		)
+		.unwrap()
+		.expect("Failed to connect to socket")
+	}
+
+	#[test]
+	fn ipc_handshake_reject() {
+		let _ = fs::remove_file(SOCK_PATH);
+
+		let listen_thread = move || {
+			let listener = IpcSocket::new(SOCK_PATH, TIMEOUT)?;
+			let res = _accept_handshake(&listener)?;
+			Ok::<_, Error>((listener, res))
+		};
+
+		let connect_thread = || {
+			let conn = IpcConnection::new(_raw_stream_create(), TIMEOUT)?;
+			conn.handshake(
+				PROTOCOL_VERSION + 1,
+				PROTOCOL_VERSION + 2,
+				DEFAULT_NAMESPACE,
+			)
+		};
+
+		let listen_handle = thread::spawn(listen_thread);
+		let connect_handle = thread::spawn(connect_thread);
+
+		let conn_res = connect_handle.join().unwrap();
+		let (listener, listen_res) = listen_handle.join().unwrap().unwrap();
+
+		assert!(matches!(conn_res, Err(Error::Protocol(_))));
+		assert!(!listen_res);
+
+		// Rejected connections are closed by the server's receive loop
+		let conn_lock = listener.connections.lock().unwrap();
+		assert!(!conn_lock.last().unwrap().borrow().is_handshake_complete());
+	}
+
+	#[test]
+	fn ipc_invalid_payload() {
+		let (server_stream, client_stream) = UnixStream::pair().unwrap();
+		let server_conn = IpcConnection::new(server_stream, TIMEOUT).unwrap();
+
+		let connect_thread = move || {
+			let conn = IpcConnection::new(client_stream, TIMEOUT)?;
+			conn.handshake(PROTOCOL_VERSION_MIN, PROTOCOL_VERSION, "test_namespace")?;
+			Ok::<_, Error>(conn)
+		};
+		let connect_handle = thread::spawn(connect_thread);
+		assert!(server_conn.accept_handshake(SERVER_INSTANCE_ID).unwrap());
+		let client_conn = connect_handle.join().unwrap().unwrap();
+		assert_eq!(
+			client_conn.get_server_instance_id(),
+			Some(SERVER_INSTANCE_ID)
+		);
+
+		// Both sides agree on the namespace chosen by the client
+		assert_eq!(server_conn.get_namespace(), "test_namespace");
+		assert_eq!(client_conn.get_namespace(), "test_namespace");
+
+		let send_subscribe = |payload: &[u8]| {
+			let mut frame = MsgHeader::new(
+				PROTOCOL_VERSION,
+				CommandTag::Subscribe as u32,
+				payload.len(),
+			)
+			.to_bytes()
+			.to_vec();
+			frame.extend_from_slice(payload);
+			(&*client_conn.get_socket()).write_all(&frame).unwrap();
+		};
+
+		// Subscribe payload, the image name followed by the subscribe bool
+		let subscribe_payload = |name_len: u32, name: &[u8], subscribe: u8| {
+			let mut payload = name_len.to_ne_bytes().to_vec();
+			payload.extend_from_slice(name);
+			payload.push(subscribe);
+			payload
+		};
+
+		let mut trailing_payload = subscribe_payload(3, b"img", 1);
+		trailing_payload.push(0);
+
+		// Invalid bool value, empty name, name that is not valid UTF-8, name longer than the
+		// payload and trailing bytes
+		for payload in [
+			subscribe_payload(3, b"img", 2),
+			subscribe_payload(0, b"", 1),
+			subscribe_payload(2, &[0xc3, 0x28], 1),
+			subscribe_payload(8, b"img", 1),
+			trailing_payload,
+		] {
+			send_subscribe(&payload);
+			assert!(matches!(
+				server_conn.recv_command_if_available(),
+				Err(Error::Protocol(_))
+			));
+		}
+
+		// The stream is still usable after payloads with invalid names
+		send_subscribe(&subscribe_payload(3, b"img", 1));
+		let msg = server_conn.recv_command_if_available().unwrap().unwrap();
+		assert!(matches!(msg, CommandMsg::Subscribe(cmd) if cmd.image_name == "img"));
+
+		// Closed connections are reported as such
+		drop(client_conn);
+		let res = server_conn.recv_command_if_available();
+		assert!(res.is_err_and(|e| e.is_disconnect()));
+	}
+
+	#[test]
+	fn ipc_handshake_invalid_peer() {
+		let _ = fs::remove_file(SOCK_PATH);
+
+		let listen_thread = move || {
+			let listener = IpcSocket::new(SOCK_PATH, TIMEOUT).unwrap();
+			_accept_handshake(&listener)
+		};
+
+		let connect_thread = || {
+			let mut stream = _raw_stream_create();
+			stream.write_all(&[0xABu8; MsgHeader::SIZE])?;
+			Ok::<_, Error>(stream)
+		};
+
+		let listen_handle = thread::spawn(listen_thread);
+		let connect_handle = thread::spawn(connect_thread);
+
+		let _stream = connect_handle.join().unwrap().unwrap();
+		let listen_res = listen_handle.join().unwrap();
+
+		assert!(matches!(listen_res, Err(Error::Protocol(_))));
 	}
 
 	#[test]
@@ -421,8 +1326,8 @@ mod tests {
 		let s_handle = thread::spawn(send_thread);
 		let r_handle = thread::spawn(recv_thread);
 
-		let _ = s_handle.join().unwrap().expect("Failed to send ack");
-		let _ = r_handle
+		s_handle.join().unwrap().expect("Failed to send ack");
+		r_handle
 			.join()
 			.unwrap()
 			.unwrap()
@@ -436,8 +1341,7 @@ mod tests {
 
 		let conn_vector = listener.connections.clone();
 		let send_thread = move || {
-			let mut msg = CommandMsg::default();
-			unsafe { (*msg.data.find_img).image_name.fill(1) };
+			let msg = _long_name_msg();
 			conn_vector
 				.lock()
 				.unwrap()
@@ -452,20 +1356,132 @@ mod tests {
 		let s_handle = thread::spawn(send_thread);
 		let r_handle = thread::spawn(recv_thread);
 
-		let _s_res = s_handle.join().unwrap().expect("Failed to send cmd");
+		s_handle.join().unwrap().expect("Failed to send cmd");
 		let r_res = r_handle.join().unwrap().expect("Failed to recv cmd");
 
 		//assert!(s_res, size_of::<CommandMsg>());
 		assert!(r_res.is_some());
 
-		let mut cmp_msg = CommandMsg::default();
-		unsafe { (*cmp_msg.data.find_img).image_name.fill(1) };
-		let rec_msg = r_res.unwrap();
+		match (_long_name_msg(), r_res.unwrap()) {
+			(CommandMsg::FindImage(cmp_cmd), CommandMsg::FindImage(rec_cmd)) => {
+				assert_eq!(cmp_cmd.image_name, rec_cmd.image_name)
+			}
+			(_, rec_msg) => panic!("Received {:?}", rec_msg.tag()),
+		}
+	}
+
+	fn _long_name_msg() -> CommandMsg {
+		CommandMsg::FindImage(CommFindImage {
+			image_name: _long_name(),
+			..Default::default()
+		})
+	}
+
+	// Longest accepted name
+	fn _long_name() -> String {
+		img_name(&"a".repeat(IMG_NAME_MAX_LEN)).unwrap()
+	}
 
-		assert_eq!(cmp_msg.tag, rec_msg.tag);
-		assert_eq!(unsafe { cmp_msg.data.find_img.image_name }, unsafe {
-			rec_msg.data.find_img.image_name
+	#[test]
+	fn ipc_msg_size() {
+		// Names only take up the space they need
+		let msg = CommandMsg::FindImage(CommFindImage {
+			image_name: img_name("img").unwrap(),
+			..Default::default()
 		});
+		assert_eq!(
+			msg.encode_payload().len(),
+			4 + "img".len() + size_of::<u128>()
+		);
+
+		let msg = CommandMsg::InitImage(Default::default());
+		assert!(msg.encode_payload().len() < 128);
+	}
+
+	#[test]
+	fn ipc_metadata_value_len() {
+		// Values longer than names are sent, up to IMG_METADATA_MAX_LEN
+		let set_msg = |value: String| {
+			CommandMsg::SetImageMetadata(CommSetImageMetadata {
+				image_name: _long_name(),
+				entry: ImgMetadataEntry {
+					key: _long_name(),
+					value,
+				},
+				remove: false,
+			})
+		};
+		for len in [IMG_NAME_MAX_LEN + 1, IMG_METADATA_MAX_LEN] {
+			let value = "a".repeat(len);
+			assert!(ImgMetadataEntry::new("key", &value).is_ok());
+
+			let msg = set_msg(value);
+			let payload = msg.encode_payload();
+			assert!(payload.len() <= MAX_PAYLOAD_LEN);
+			match CommandMsg::decode_payload(msg.tag(), &payload).unwrap() {
+				CommandMsg::SetImageMetadata(cmd) => assert_eq!(cmd.entry.get_value().len(), len),
+				rec_msg => panic!("Received {:?}", rec_msg.tag()),
+			}
+		}
+
+		// Longer values are rejected by both sides
+		let value = "a".repeat(IMG_METADATA_MAX_LEN + 1);
+		assert!(matches!(
+			ImgMetadataEntry::new("key", &value),
+			Err(Error::MetadataTooLarge { .. })
+		));
+
+		let msg = set_msg(value);
+		assert!(matches!(
+			CommandMsg::decode_payload(msg.tag(), &msg.encode_payload()),
+			Err(Error::Protocol(_))
+		));
+	}
+
+	#[test]
+	fn ipc_invalid_result() {
+		let find_imgs_msg = |image_count: u32, image_index: u32, latest_buffer: u32| {
+			let mut res = ResultFindImages {
+				image_count,
+				image_index,
+				image_found: true,
+				img_data: ImgData::default(),
+			};
+			res.img_data.data.latest_buffer = latest_buffer;
+			ResultMsg::FindImages(res)
+		};
+
+		let valid = find_imgs_msg(2, 1, 0);
+		assert!(ResultMsg::decode_payload(valid.tag(), &valid.encode_payload()).is_ok());
+
+		// Index past the count, count past the maximum and latest buffer past the buffer count
+		for msg in [
+			find_imgs_msg(2, 2, 0),
+			find_imgs_msg(FIND_IMAGES_MAX_COUNT as u32 + 1, 0, 0),
+			find_imgs_msg(1, 0, 1),
+		] {
+			assert!(matches!(
+				ResultMsg::decode_payload(msg.tag(), &msg.encode_payload()),
+				Err(Error::Protocol(_))
+			));
+		}
+
+		// Empty lists are sent with index and count 0
+		let list_msg = |gpu_device_count: u32| {
+			ResultMsg::ListImages(ResultListImages {
+				image_count: 0,
+				image_index: 0,
+				image: ImgListEntry {
+					gpu_device_count,
+					..Default::default()
+				},
+			})
+		};
+		let msg = list_msg(0);
+		assert!(ResultMsg::decode_payload(msg.tag(), &msg.encode_payload()).is_ok());
+
+		let msg = list_msg(u32::MAX);
+		assert!(ResultMsg::decode_payload(msg.tag(), &msg.encode_payload()).is_err());
 	}
 
 	#[test]
@@ -474,8 +1490,7 @@ mod tests {
 		let (listener, conn) = _ipc_stream_create();
 
 		let send_thread = move || {
-			let mut msg = ResultMsg::default();
-			unsafe { (*msg.data.find_img).img_data.data.height = 1024 };
+			let msg = _find_result_msg(1024);
 			listener
 				.connections
 				.lock()
@@ -491,21 +1506,367 @@ mod tests {
 		let s_handle = thread::spawn(send_thread);
 		let r_handle = thread::spawn(recv_thread);
 
-		let _s_res = s_handle.join().unwrap().expect("Failed to send res");
+		s_handle.join().unwrap().expect("Failed to send res");
 		let r_res = r_handle.join().unwrap().expect("Failed to recv res");
 
 		//assert_eq!(s_res, size_of::<ResultMsg>());
 		assert!(r_res.is_some());
 
-		let mut cmp_msg = ResultMsg::default();
-		unsafe { (*cmp_msg.data.find_img).img_data.data.height = 1024 };
-		let rec_msg = r_res.unwrap();
+		match (_find_result_msg(1024), r_res.unwrap()) {
+			(ResultMsg::FindImage(cmp_res), ResultMsg::FindImage(rec_res)) => {
+				assert_eq!(cmp_res.img_data.data.height, rec_res.img_data.data.height)
+			}
+			(_, rec_msg) => panic!("Received {:?}", rec_msg.tag()),
+		}
+	}
 
-		assert_eq!(cmp_msg.tag, rec_msg.tag);
-		assert_eq!(
-			unsafe { cmp_msg.data.find_img.img_data.data.height },
-			unsafe { rec_msg.data.find_img.img_data.data.height }
-		);
+	fn _find_result_msg(height: u32) -> ResultMsg {
+		let mut img_data = ImgData::default();
+		img_data.data.height = height;
+		ResultMsg::FindImage(ResultFindImage {
+			image_found: false,
+			img_data,
+		})
+	}
+
+	#[test]
+	fn ipc_event_msg() {
+		let _ = fs::remove_file(SOCK_PATH);
+		let (listener, conn) = _ipc_stream_create();
+
+		let send_thread = move || {
+			let conn_lock = listener.connections.lock().unwrap();
+			let conn = conn_lock.last().unwrap().borrow();
+
+			conn.send_result(ResultMsg::ImageEvent(ImgEvent::new(
+				ImgEventKind::ImageResized,
+				"img",
+			)))?;
+			conn.send_result(ResultMsg::default())?;
+			drop(conn);
+			drop(conn_lock);
+			Ok::<_, Error>(listener)
+		};
+
+		let s_handle = thread::spawn(send_thread);
+		let listener = s_handle.join().unwrap().expect("Failed to send res");
+
+		// The event is skipped and queued while waiting for the result
+		let r_res = conn.recv_result().expect("Failed to recv res");
+		assert_eq!(r_res.unwrap().tag(), CommandTag::FindImage);
+
+		let event = conn.recv_event_if_available().unwrap().unwrap();
+		assert_eq!(event.kind, ImgEventKind::ImageResized);
+		assert_eq!(event.get_image_name(), "img");
+
+		assert!(conn.recv_event_if_available().unwrap().is_none());
+
+		// Closed connections are reported instead of looking idle
+		drop(listener);
+		let res = conn.recv_event_if_available();
+		assert!(res.is_err_and(|e| e.is_disconnect()));
+	}
+
+	#[test]
+	fn ipc_ping() {
+		let _ = fs::remove_file(SOCK_PATH);
+		let (listener, conn) = _ipc_stream_create();
+
+		let pong_thread = move || {
+			let conn_lock = listener.connections.lock().unwrap();
+			let server_conn = conn_lock.last().unwrap().borrow();
+
+			let cmd = server_conn.recv_command()?.expect("Failed to recv ping");
+			let CommandMsg::Ping(ping) = cmd else {
+				panic!("Received {:?}", cmd.tag());
+			};
+			assert_eq!(ping.heartbeat_interval_millis, 100);
+
+			// A stale pong followed by the matching one
+			for sequence in [ping.sequence - 1, ping.sequence] {
+				server_conn.send_result(ResultMsg::Ping(ResultPong { sequence }))?;
+			}
+
+			// Don't answer the second ping, but keep the connection open
+			let cmd = server_conn.recv_command()?.expect("Failed to recv ping");
+			assert_eq!(cmd.tag(), CommandTag::Ping);
+			thread::sleep(Duration::from_millis(500));
+			Ok::<_, Error>(())
+		};
+		let p_handle = thread::spawn(pong_thread);
+
+		let res = conn.ping(2, Some(Duration::from_millis(100)), TIMEOUT);
+		assert!(res.unwrap().is_some());
+
+		let res = conn.ping(3, None, Duration::from_millis(100));
+		assert!(res.unwrap().is_none());
+
+		p_handle.join().unwrap().unwrap();
+	}
+
+	#[test]
+	fn ipc_stats_msg() {
+		let _ = fs::remove_file(SOCK_PATH);
+		let (listener, conn) = _ipc_stream_create();
+
+		let stats_thread = move || {
+			let conn_lock = listener.connections.lock().unwrap();
+			let server_conn = conn_lock.last().unwrap().borrow();
+
+			let cmd = server_conn.recv_command()?.expect("Failed to recv command");
+			assert_eq!(cmd.tag(), CommandTag::GetStats);
+
+			// One image followed by two devices
+			for entry_index in 0..3 {
+				let image = ImgStatsEntry {
+					producer_count: 1,
+					..Default::default()
+				};
+				server_conn.send_result(ResultMsg::GetStats(ResultGetStats {
+					connection_count: 2,
+					gpu_copy_count: 5,
+					image_count: 1,
+					device_count: 2,
+					entry_index,
+					image,
+					device: DeviceStatsEntry::new(entry_index as u128, 1, 64),
+				}))?;
+			}
+			Ok::<_, Error>(())
+		};
+		let s_handle = thread::spawn(stats_thread);
+
+		let stats = conn.get_stats().unwrap().expect("Failed to recv stats");
+		assert_eq!(stats.connection_count, 2);
+		assert_eq!(stats.gpu_copy_count, 5);
+		assert_eq!(stats.images.len(), 1);
+		assert_eq!(stats.images[0].producer_count, 1);
+		assert!(stats.images[0].get_last_update().is_none());
+		assert_eq!(stats.devices.len(), 2);
+		assert_eq!(stats.devices[1].get_gpu_device_uuid(), 2);
+		assert_eq!(stats.devices[1].memory_usage, 64);
+
+		s_handle.join().unwrap().unwrap();
+	}
+
+	#[test]
+	fn ipc_image_metadata_msg() {
+		let _ = fs::remove_file(SOCK_PATH);
+		let (listener, conn) = _ipc_stream_create();
+
+		let metadata_thread = move || {
+			let conn_lock = listener.connections.lock().unwrap();
+			let server_conn = conn_lock.last().unwrap().borrow();
+
+			let cmd = server_conn.recv_command()?.expect("Failed to recv command");
+			let CommandMsg::SetImageMetadata(set_cmd) = cmd else {
+				panic!("Received {:?}", cmd.tag());
+			};
+			assert_eq!(set_cmd.image_name.as_str(), "img");
+			assert_eq!(set_cmd.entry.get_key(), "label");
+			assert_eq!(set_cmd.entry.get_value(), "");
+			assert!(set_cmd.remove);
+			server_conn.send_result(ResultMsg::SetImageMetadata(ResultSetImageMetadata {
+				image_found: true,
+			}))?;
+
+			let cmd = server_conn.recv_command()?.expect("Failed to recv command");
+			assert_eq!(cmd.tag(), CommandTag::GetImageMetadata);
+			for entry_index in 0..2 {
+				server_conn.send_result(ResultMsg::GetImageMetadata(ResultGetImageMetadata {
+					image_found: true,
+					entry_count: 2,
+					entry_index,
+					entry: ImgMetadataEntry::new(&format!("key_{}", entry_index), "value")
+						.unwrap(),
+				}))?;
+			}
+
+			// Missing images are reported with a single empty result
+			let cmd = server_conn.recv_command()?.expect("Failed to recv command");
+			assert_eq!(cmd.tag(), CommandTag::GetImageMetadata);
+			server_conn.send_result(ResultMsg::GetImageMetadata(ResultGetImageMetadata {
+				image_found: false,
+				entry_count: 0,
+				entry_index: 0,
+				entry: ImgMetadataEntry::default(),
+			}))?;
+			Ok::<_, Error>(())
+		};
+		let s_handle = thread::spawn(metadata_thread);
+
+		assert!(conn.set_image_metadata("img", "", Some("value")).is_err());
+		assert!(conn
+			.set_image_metadata("img", "label", None)
+			.unwrap()
+			.is_some());
+
+		let metadata = conn
+			.get_image_metadata("img")
+			.unwrap()
+			.expect("Failed to recv metadata");
+		assert_eq!(metadata.len(), 2);
+		assert_eq!(metadata[1].get_key(), "key_1");
+		assert_eq!(metadata[1].get_value(), "value");
+
+		assert!(conn.get_image_metadata("missing").unwrap().is_none());
+
+		s_handle.join().unwrap().unwrap();
+	}
+
+	#[test]
+	fn ipc_wait_for_image_msg() {
+		let (server_stream, client_stream) = UnixStream::pair().unwrap();
+		let server_conn = IpcConnection::new(server_stream, SHORT_TIMEOUT).unwrap();
+		let client_conn = IpcConnection::new(client_stream, SHORT_TIMEOUT).unwrap();
+
+		let wait_thread = move || {
+			assert!(server_conn.accept_handshake(SERVER_INSTANCE_ID)?);
+			let cmd = server_conn.recv_command()?.expect("Failed to recv command");
+			let CommandMsg::WaitForImage(wait_cmd) = cmd else {
+				panic!("Received {:?}", cmd.tag());
+			};
+			assert_eq!(wait_cmd.image_name.as_str(), "img");
+			assert_eq!(
+				wait_cmd.timeout_millis,
+				4 * SHORT_TIMEOUT.as_millis() as u64
+			);
+
+			// Answer later than the connection's timeout, but within the wait timeout
+			thread::sleep(2 * SHORT_TIMEOUT);
+			server_conn
+				.send_result(ResultMsg::WaitForImage(ResultWaitForImage { image_found: true }))?;
+			Ok::<_, Error>(server_conn)
+		};
+		let s_handle = thread::spawn(wait_thread);
+
+		client_conn
+			.handshake(PROTOCOL_VERSION_MIN, PROTOCOL_VERSION, DEFAULT_NAMESPACE)
+			.unwrap();
+		assert!(client_conn
+			.wait_for_image("img", 4 * SHORT_TIMEOUT)
+			.unwrap());
+		let _server_conn = s_handle.join().unwrap().unwrap();
+
+		// The connection's timeout is restored afterwards
+		let res = _assert_idle_timeout(|| client_conn.recv_result());
+		assert!(res.unwrap().is_none());
+	}
+
+	#[test]
+	fn ipc_find_images_msg() {
+		let _ = fs::remove_file(SOCK_PATH);
+		let (listener, conn) = _ipc_stream_create();
+
+		// Two commands are needed for one name more than fits into a single one
+		let image_names = (0..FIND_IMAGES_MAX_COUNT + 1)
+			.map(|i| format!("img_{}", i))
+			.collect::<Vec<_>>();
+
+		let find_thread = move || {
+			let conn_lock = listener.connections.lock().unwrap();
+			let server_conn = conn_lock.last().unwrap().borrow();
+
+			let mut name_index = 0;
+			for image_count in [FIND_IMAGES_MAX_COUNT, 1] {
+				let cmd = server_conn.recv_command()?.expect("Failed to recv command");
+				let CommandMsg::FindImages(cmd_data) = cmd else {
+					panic!("Received {:?}", cmd.tag());
+				};
+				assert_eq!(cmd_data.image_count as usize, image_count);
+				assert_eq!(cmd_data.gpu_device_uuid, 7);
+
+				// Every other image is found, every fourth one is double-buffered
+				let mut files = Vec::new();
+				for image_index in 0..image_count {
+					let image_name = &cmd_data.image_names[image_index];
+					assert_eq!(image_name.as_str(), format!("img_{}", name_index));
+
+					let image_found = name_index % 2 == 0;
+					let mut img_data = ImgData::default();
+					if image_found {
+						img_data.image_name = image_name.clone();
+						img_data.data.handle_id = name_index as u32;
+						img_data.data.buffer_count = 1 + (name_index % 4 == 0) as u32;
+						for _ in 0..img_data.data.buffer_count {
+							files.push(tempfile::tempfile().unwrap());
+						}
+					}
+
+					server_conn.send_result(ResultMsg::FindImages(ResultFindImages {
+						image_count: image_count as u32,
+						image_index: image_index as u32,
+						image_found,
+						img_data,
+					}))?;
+					name_index += 1;
+				}
+
+				let handles = files.iter().map(|f| f.as_raw_fd()).collect::<Vec<_>>();
+				server_conn.send_anillary_handles(&handles)?;
+				assert!(server_conn.recv_ack()?.is_some());
+			}
+			Ok::<_, Error>(())
+		};
+		let s_handle = thread::spawn(find_thread);
+
+		let image_names = image_names.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+		let images = conn
+			.find_images(&image_names, 7)
+			.unwrap()
+			.expect("Failed to recv images");
+		assert_eq!(images.len(), image_names.len());
+		for (i, image) in images.iter().enumerate() {
+			match image {
+				Some((img_data, fds)) => {
+					assert_eq!(i % 2, 0);
+					assert_eq!(img_data.image_name.as_str(), image_names[i]);
+					assert_eq!(img_data.data.handle_id, i as u32);
+					assert_eq!(fds.len(), 1 + (i % 4 == 0) as usize);
+					assert!(fds.iter().all(|fd| fd.as_raw_fd() >= 0));
+				}
+				None => assert_eq!(i % 2, 1),
+			}
+		}
+
+		s_handle.join().unwrap().unwrap();
+
+		// Invalid names are rejected before anything is sent
+		assert!(matches!(
+			conn.find_images(&["img", ""], 7),
+			Err(Error::InvalidName { .. })
+		));
+	}
+
+	#[test]
+	fn ipc_error_msg() {
+		let _ = fs::remove_file(SOCK_PATH);
+		let (listener, conn) = _ipc_stream_create();
+
+		let send_thread = move || {
+			listener
+				.connections
+				.lock()
+				.unwrap()
+				.last()
+				.unwrap()
+				.borrow()
+				.send_result(ResultMsg::Error(ResultError::new(
+					ResultErrorCode::GpuNotFound,
+					"GPU not found",
+				)))
+		};
+
+		let s_handle = thread::spawn(send_thread);
+		s_handle.join().unwrap().expect("Failed to send res");
+
+		let server_error = match conn.recv_result() {
+			Err(Error::Server(e)) => e,
+			Err(e) => panic!("Expected server error, received '{}'", e),
+			Ok(_) => panic!("Expected error result"),
+		};
+		assert_eq!(server_error.code, ResultErrorCode::GpuNotFound);
+		assert_eq!(server_error.message, "GPU not found");
 	}
 
 	#[test]
@@ -539,7 +1900,7 @@ mod tests {
 		let r_handle = thread::spawn(recv_thread);
 
 		let mut r_res = r_handle.join().unwrap().expect("Failed to recv ancillary");
-		let _s_res = s_handle.join().unwrap().expect("Failed to send ancillary");
+		s_handle.join().unwrap().expect("Failed to send ancillary");
 
 		//assert_ne!(s_res, 0);
 		assert_eq!(r_res.len(), handle_count);
@@ -551,4 +1912,217 @@ mod tests {
 
 		//tmp1.close().unwrap();
 	}
+
+	const SHORT_TIMEOUT: Duration = Duration::from_millis(300);
+	const MISSING_SOCK_PATH: &str = "test_missing_socket.sock";
+
+	// CPU time spent by the calling thread
+	fn _thread_cpu_time() -> Duration {
+		let mut ts = libc::timespec {
+			tv_sec: 0,
+			tv_nsec: 0,
+		};
+		unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) };
+		Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+	}
+
+	// Run f and check that it waited for the timeout without keeping the CPU busy
+	fn _assert_idle_timeout<R>(f: impl FnOnce() -> R) -> R {
+		let start_time = Instant::now();
+		let start_cpu_time = _thread_cpu_time();
+		let res = f();
+		let cpu_time = _thread_cpu_time() - start_cpu_time;
+		let elapsed = start_time.elapsed();
+
+		assert!(elapsed >= SHORT_TIMEOUT, "Returned after {:?}", elapsed);
+		assert!(
+			elapsed < SHORT_TIMEOUT + Duration::from_secs(1),
+			"Returned after {:?}",
+			elapsed
+		);
+		assert!(
+			cpu_time < elapsed / 4,
+			"Spent {:?} of {:?} on the CPU",
+			cpu_time,
+			elapsed
+		);
+
+		res
+	}
+
+	#[test]
+	fn ipc_connect_timeout() {
+		let _ = fs::remove_file(MISSING_SOCK_PATH);
+
+		let res =
+			_assert_idle_timeout(|| IpcConnection::try_connect(MISSING_SOCK_PATH, SHORT_TIMEOUT));
+		assert!(res.unwrap().is_none());
+	}
+
+	#[test]
+	fn ipc_accept_timeout() {
+		let _ = fs::remove_file(SOCK_PATH);
+		let listener = IpcSocket::new(SOCK_PATH, SHORT_TIMEOUT).unwrap();
+
+		let res = _assert_idle_timeout(|| listener.try_accept());
+		assert!(res.unwrap().is_none());
+		assert!(listener.connections.lock().unwrap().is_empty());
+	}
+
+	#[test]
+	fn ipc_recv_timeout() {
+		let (server_stream, client_stream) = UnixStream::pair().unwrap();
+		let server_conn = IpcConnection::new(server_stream, SHORT_TIMEOUT).unwrap();
+		let client_conn = IpcConnection::new(client_stream, SHORT_TIMEOUT).unwrap();
+
+		let res = _assert_idle_timeout(|| server_conn.recv_command());
+		assert!(res.unwrap().is_none());
+
+		let res = _assert_idle_timeout(|| client_conn.recv_result());
+		assert!(res.unwrap().is_none());
+
+		let res = _assert_idle_timeout(|| client_conn.recv_ack());
+		assert!(res.unwrap().is_none());
+
+		let res = _assert_idle_timeout(|| client_conn.recv_ancillary(1));
+		assert!(matches!(res, Err(Error::Timeout(_))));
+
+		// Timeouts are not mistaken for a closed connection
+		let err = res.unwrap_err();
+		assert!(err.is_timeout() && !err.is_disconnect());
+
+		// Polling for events must not wait at all
+		let start_time = Instant::now();
+		assert!(client_conn.recv_event_if_available().unwrap().is_none());
+		assert!(start_time.elapsed() < SHORT_TIMEOUT);
+	}
+
+	#[test]
+	fn ipc_partial_frame_timeout() {
+		let (server_stream, mut client_stream) = UnixStream::pair().unwrap();
+		let server_conn = IpcConnection::new(server_stream, SHORT_TIMEOUT).unwrap();
+
+		// A frame that was started but never completed leaves the stream unusable
+		client_stream.write_all(&[0u8; 3]).unwrap();
+		let res = _assert_idle_timeout(|| server_conn.recv_command());
+		assert!(matches!(res, Err(Error::Timeout(_))));
+	}
+
+	#[cfg(feature = "tokio")]
+	fn _runtime() -> tokio::runtime::Runtime {
+		tokio::runtime::Builder::new_current_thread()
+			.enable_all()
+			.build()
+			.unwrap()
+	}
+
+	#[test]
+	#[cfg(feature = "tokio")]
+	fn ipc_async_msg() {
+		use crate::platform::ipc_commands::CommListImages;
+		use crate::TokioWait;
+		use tempfile::tempfile;
+
+		let _ = fs::remove_file(SOCK_PATH);
+
+		let server_thread = thread::spawn(|| {
+			let listener = IpcSocket::new(SOCK_PATH, TIMEOUT)?;
+			assert!(_accept_handshake(&listener)?);
+
+			let conn_lock = listener.connections.lock().unwrap();
+			let server_conn = conn_lock.last().unwrap().borrow();
+			let cmd = server_conn.recv_command()?.expect("Failed to recv command");
+			assert_eq!(cmd.tag(), CommandTag::ListImages);
+
+			// An event queued before the result, followed by a handle
+			server_conn.send_result(ResultMsg::ImageEvent(ImgEvent::new(
+				ImgEventKind::ImageRemoved,
+				"test_img",
+			)))?;
+			server_conn.send_result(ResultMsg::default())?;
+
+			let file = tempfile().unwrap();
+			server_conn.send_anillary_handles(&[file.as_raw_fd()])?;
+			server_conn.recv_ack()?.expect("Failed to recv ack");
+			Ok::<_, Error>(())
+		});
+
+		_runtime().block_on(async move {
+			let wait = TokioWait;
+			let conn = IpcConnection::try_connect_namespace_with(
+				&wait,
+				SOCK_PATH,
+				DEFAULT_NAMESPACE,
+				TIMEOUT,
+			)
+			.await
+			.unwrap()
+			.expect("Failed to connect to socket");
+
+			conn.send_command_with(&wait, CommandMsg::ListImages(CommListImages {}))
+				.await
+				.unwrap();
+
+			let res = conn.recv_result_with(&wait).await.unwrap().unwrap();
+			assert_eq!(res.tag(), CommandTag::FindImage);
+
+			let fds = conn.recv_ancillary_with(&wait, 1).await.unwrap();
+			assert_eq!(fds.len(), 1);
+			conn.send_ack_with(&wait).await.unwrap();
+
+			let event = conn.recv_event_if_available_with(&wait).await.unwrap();
+			assert_eq!(event.unwrap().kind, ImgEventKind::ImageRemoved);
+
+			// Closed connections are reported instead of looking idle
+			server_thread.join().unwrap().unwrap();
+			let res = conn.recv_event_if_available_with(&wait).await;
+			assert!(res.is_err_and(|e| e.is_disconnect()));
+		});
+	}
+
+	#[test]
+	#[cfg(feature = "tokio")]
+	fn ipc_async_wait_yields() {
+		use crate::TokioWait;
+
+		let (server_stream, client_stream) = UnixStream::pair().unwrap();
+		let server_conn = IpcConnection::new(server_stream, TIMEOUT).unwrap();
+		let client_conn = IpcConnection::new(client_stream, TIMEOUT).unwrap();
+
+		// Both sides run on a single thread, so the server only replies if waiting for the reply
+		// yields
+		let local_set = tokio::task::LocalSet::new();
+		let res = local_set.block_on(&_runtime(), async {
+			tokio::task::spawn_local(async move {
+				tokio::time::sleep(Duration::from_millis(50)).await;
+				assert!(server_conn.accept_handshake(SERVER_INSTANCE_ID).unwrap());
+			});
+			client_conn
+				.handshake_with(
+					&TokioWait,
+					PROTOCOL_VERSION_MIN,
+					PROTOCOL_VERSION,
+					DEFAULT_NAMESPACE,
+				)
+				.await
+		});
+		assert_eq!(res.unwrap(), PROTOCOL_VERSION);
+		assert_eq!(client_conn.get_server_instance_id(), Some(SERVER_INSTANCE_ID));
+	}
+
+	#[test]
+	#[cfg(feature = "tokio")]
+	fn ipc_async_connect_timeout() {
+		use crate::TokioWait;
+
+		let _ = fs::remove_file(MISSING_SOCK_PATH);
+
+		let res = _runtime().block_on(IpcConnection::try_connect_namespace_with(
+			&TokioWait,
+			MISSING_SOCK_PATH,
+			DEFAULT_NAMESPACE,
+			SHORT_TIMEOUT,
+		));
+		assert!(res.unwrap().is_none());
+	}
 }
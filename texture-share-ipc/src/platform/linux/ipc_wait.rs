@@ -0,0 +1,143 @@
+use std::future::{self, Future};
+use std::io::{self, ErrorKind};
+use std::os::fd::RawFd;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Instant;
+
+use crate::Error;
+
+// How a connection waits for its socket. IpcConnection implements the protocol once on top of
+// this, blocking and async connections only differ in how they wait
+pub trait IpcWait {
+	// Wait until fd signals one of events or the deadline passes. Errors and hangups count as
+	// ready, so that the following call reports them. Returns false on timeout
+	fn wait_fd(
+		&self,
+		fd: RawFd,
+		events: libc::c_short,
+		deadline: Instant,
+	) -> impl Future<Output = Result<bool, Error>>;
+
+	// Wait before retrying an operation that can't be polled
+	fn sleep_until(&self, deadline: Instant) -> impl Future<Output = ()>;
+}
+
+// Blocks the calling thread. Its futures complete on the first poll
+pub struct BlockingWait;
+
+impl BlockingWait {
+	// Run a future that only waits with BlockingWait to completion
+	pub fn block_on<F: Future>(fut: F) -> F::Output {
+		match pin!(fut).poll(&mut Context::from_waker(Waker::noop())) {
+			Poll::Ready(res) => res,
+			Poll::Pending => unreachable!("Blocking waits never yield"),
+		}
+	}
+}
+
+impl IpcWait for BlockingWait {
+	fn wait_fd(
+		&self,
+		fd: RawFd,
+		events: libc::c_short,
+		deadline: Instant,
+	) -> impl Future<Output = Result<bool, Error>> {
+		future::ready(poll_fd(fd, events, deadline))
+	}
+
+	fn sleep_until(&self, deadline: Instant) -> impl Future<Output = ()> {
+		thread::sleep(deadline.saturating_duration_since(Instant::now()));
+		future::ready(())
+	}
+}
+
+// Yields to the tokio runtime while waiting. The runtime needs IO and time enabled
+#[cfg(feature = "tokio")]
+pub struct TokioWait;
+
+#[cfg(feature = "tokio")]
+impl IpcWait for TokioWait {
+	async fn wait_fd(
+		&self,
+		fd: RawFd,
+		events: libc::c_short,
+		deadline: Instant,
+	) -> Result<bool, Error> {
+		use tokio::io::unix::AsyncFd;
+		use tokio::io::Interest;
+
+		let interest = match events & libc::POLLOUT {
+			0 => Interest::READABLE,
+			_ => Interest::WRITABLE,
+		};
+
+		// Registered on the first wait, as most calls find the socket ready
+		let mut async_fd = None;
+		loop {
+			if poll_fd(fd, events, Instant::now())? {
+				return Ok(true);
+			}
+			if Instant::now() >= deadline {
+				return Ok(false);
+			}
+
+			let async_fd = match &mut async_fd {
+				Some(async_fd) => async_fd,
+				None => async_fd.insert(AsyncFd::with_interest(SocketFd(fd), interest)?),
+			};
+
+			// Readiness is only a hint, poll confirms it above
+			let deadline = tokio::time::Instant::from_std(deadline);
+			match tokio::time::timeout_at(deadline, async_fd.ready(interest)).await {
+				Ok(guard) => guard?.clear_ready(),
+				Err(_) => return Ok(false),
+			}
+		}
+	}
+
+	async fn sleep_until(&self, deadline: Instant) {
+		tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)).await
+	}
+}
+
+// Lets tokio watch a socket it doesn't own
+#[cfg(feature = "tokio")]
+struct SocketFd(RawFd);
+
+#[cfg(feature = "tokio")]
+impl std::os::fd::AsRawFd for SocketFd {
+	fn as_raw_fd(&self) -> RawFd {
+		self.0
+	}
+}
+
+// Block until one of the requested events is signaled on fd or the deadline passes. Returns
+// false on timeout
+pub(crate) fn poll_fd(fd: RawFd, events: libc::c_short, deadline: Instant) -> Result<bool, Error> {
+	loop {
+		// Round up, so that the deadline has passed once poll times out
+		let remaining = deadline.saturating_duration_since(Instant::now());
+		let timeout_millis = remaining
+			.as_nanos()
+			.div_ceil(1_000_000)
+			.min(libc::c_int::MAX as u128) as libc::c_int;
+
+		let mut pfd = libc::pollfd {
+			fd,
+			events,
+			revents: 0,
+		};
+		let res = unsafe { libc::poll(&mut pfd, 1, timeout_millis) };
+		if res < 0 {
+			let e = io::Error::last_os_error();
+			match e.kind() {
+				ErrorKind::Interrupted => continue,
+				_ => return Err(e.into()),
+			}
+		}
+
+		return Ok(res > 0);
+	}
+}
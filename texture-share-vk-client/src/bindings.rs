@@ -7,7 +7,14 @@ use std::{
 use texture_share_vk_base::{
 	ash::vk,
 	bindings::vk_setup_from_c,
-	ipc::platform::{img_data::ImgFormat, ReadLockGuard, ShmemDataInternal},
+	ipc::bindings::{CImgEvent, CImgListEntry, CImgMetadataEntry, CImgStatsEntry},
+	ipc::platform::{
+		daemon_launch::ReconnectPolicy,
+		img_data::{DeviceStatsEntry, ImgFormat, ImgFrameInfo},
+		ipc_commands::{ServerStats, DEFAULT_NAMESPACE},
+		ShmemDataInternal,
+	},
+	ipc::Error,
 	vk_device::VkDevice,
 	vk_instance::VkInstance,
 	vk_setup::VkSetup,
@@ -35,24 +42,52 @@ fn get_str<'a>(buf: &'a *const c_char) -> Cow<'a, str> {
 //#[repr(transparent)]
 //struct ClientImageData(ShmemDataInternal);
 
-struct ClientImageDataGuard<'a> {
-	_read_lock: ReadLockGuard<'a>,
-	image_data: &'a ShmemDataInternal,
+// Holds a copy of the image data, so that no lock on the image is kept
+struct ClientImageDataGuard {
+	image_data: ShmemDataInternal,
 }
 
-impl<'a> ClientImageDataGuard<'a> {
+impl ClientImageDataGuard {
 	#[no_mangle]
 	extern "C" fn get_image_data(&self) -> &ShmemDataInternal {
 		&self.image_data
 	}
 }
 
+struct ClientImageList {
+	images: Vec<CImgListEntry>,
+}
+
+struct ClientImageMetadata {
+	entries: Vec<CImgMetadataEntry>,
+}
+
+struct ClientServerStats {
+	stats: ServerStats,
+	images: Vec<CImgStatsEntry>,
+}
+
 #[no_mangle]
 extern "C" fn vk_client_new(
 	socket_path: *const c_char,
 	vk_setup: Option<NonNull<VkSetup>>,
 	timeout_in_millis: u64,
 ) -> *mut VkClient {
+	vk_client_new_with_namespace(socket_path, ptr::null(), vk_setup, timeout_in_millis)
+}
+
+// A NULL namespace selects the default namespace
+#[no_mangle]
+extern "C" fn vk_client_new_with_namespace(
+	socket_path: *const c_char,
+	namespace: *const c_char,
+	vk_setup: Option<NonNull<VkSetup>>,
+	timeout_in_millis: u64,
+) -> *mut VkClient {
+	let namespace = match namespace.is_null() {
+		true => Cow::from(DEFAULT_NAMESPACE),
+		false => get_str(&namespace),
+	};
 	let vk_setup = match vk_setup {
 		Some(ptr) => unsafe { vk_setup_from_c(ptr.as_ptr()) },
 		None => {
@@ -76,8 +111,9 @@ extern "C" fn vk_client_new(
 		}
 	};
 
-	let vk_client = VkClient::new(
+	let vk_client = VkClient::new_with_namespace(
 		&get_str(&socket_path),
+		&namespace,
 		vk_setup,
 		Duration::from_millis(timeout_in_millis),
 	);
@@ -187,6 +223,34 @@ extern "C" fn vk_client_init_image(
 	}
 }
 
+#[no_mangle]
+extern "C" fn vk_client_init_image_buffered(
+	vk_client: *mut VkClient,
+	image_name: *const c_char,
+	width: u32,
+	height: u32,
+	format: ImgFormat,
+	overwrite_existing: bool,
+	buffer_count: u32,
+) -> ImageLookupResult {
+	match unsafe { vk_client.as_mut() }.unwrap().init_image_buffered(
+		&get_str(&image_name),
+		width,
+		height,
+		format,
+		overwrite_existing,
+		buffer_count,
+	) {
+		Ok(Some(true)) => return ImageLookupResult::RequiresUpdate,
+		Ok(Some(false)) => return ImageLookupResult::Found,
+		Ok(None) => return ImageLookupResult::NotFound,
+		Err(e) => {
+			println!("Failed to init image with err '{:}'", e);
+			return ImageLookupResult::Error;
+		}
+	}
+}
+
 #[no_mangle]
 extern "C" fn vk_client_find_image(
 	vk_client: *mut VkClient,
@@ -208,23 +272,150 @@ extern "C" fn vk_client_find_image(
 	}
 }
 
+// Block for up to timeout_in_millis until the image is initialized, then import it like
+// find_image. Returns NotFound if the image didn't appear in time
+#[no_mangle]
+extern "C" fn vk_client_wait_for_image(
+	vk_client: *mut VkClient,
+	image_name: *const c_char,
+	timeout_in_millis: u64,
+	force_update: bool,
+) -> ImageLookupResult {
+	let local_image = unsafe { vk_client.as_mut() }.unwrap().wait_for_image(
+		&get_str(&image_name),
+		Duration::from_millis(timeout_in_millis),
+		force_update,
+	);
+
+	match local_image {
+		Ok(Some(true)) => return ImageLookupResult::RequiresUpdate,
+		Ok(Some(false)) => return ImageLookupResult::Found,
+		Ok(None) => return ImageLookupResult::NotFound,
+		Err(e) => {
+			println!("Failed to wait for image with err '{:}'", e);
+			return ImageLookupResult::Error;
+		}
+	}
+}
+
+// Look up image_count images at once. results must hold image_count entries, which are filled in
+// the order of image_names. Returns the number of found images or -1 on error
+#[no_mangle]
+extern "C" fn vk_client_find_images(
+	vk_client: *mut VkClient,
+	image_names: *const *const c_char,
+	image_count: usize,
+	force_update: bool,
+	results: *mut ImageLookupResult,
+) -> c_int {
+	let image_names = match image_count {
+		0 => Vec::new(),
+		_ => unsafe { std::slice::from_raw_parts(image_names, image_count) }
+			.iter()
+			.map(get_str)
+			.collect::<Vec<_>>(),
+	};
+	let image_names = image_names.iter().map(|x| x.as_ref()).collect::<Vec<_>>();
+
+	let res = unsafe { vk_client.as_mut() }
+		.unwrap()
+		.find_images(&image_names, force_update);
+
+	let local_images = match res {
+		Ok(Some(local_images)) => local_images,
+		Ok(None) => vec![None; image_count],
+		Err(e) => {
+			println!("Failed to find images with err '{:}'", e);
+			return -1;
+		}
+	};
+
+	let mut found_count = 0;
+	for (i, local_image) in local_images.into_iter().enumerate() {
+		let res = match local_image {
+			Some(true) => ImageLookupResult::RequiresUpdate,
+			Some(false) => ImageLookupResult::Found,
+			None => ImageLookupResult::NotFound,
+		};
+		if local_image.is_some() {
+			found_count += 1;
+		}
+		unsafe { results.add(i).write(res) };
+	}
+
+	found_count
+}
+
+#[no_mangle]
+extern "C" fn vk_client_rename_image(
+	vk_client: *mut VkClient,
+	old_image_name: *const c_char,
+	new_image_name: *const c_char,
+) -> ImageLookupResult {
+	let res = unsafe { vk_client.as_mut() }
+		.unwrap()
+		.rename_image(&get_str(&old_image_name), &get_str(&new_image_name));
+
+	match res {
+		Ok(Some(_)) => return ImageLookupResult::Found,
+		Ok(None) => return ImageLookupResult::NotFound,
+		Err(e) => {
+			println!("Failed to rename image with err '{:}'", e);
+			return ImageLookupResult::Error;
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_release_image(
+	vk_client: *mut VkClient,
+	image_name: *const c_char,
+) -> ImageLookupResult {
+	let res = unsafe { vk_client.as_mut() }
+		.unwrap()
+		.release_image(&get_str(&image_name));
+
+	match res {
+		Ok(Some(_)) => return ImageLookupResult::Found,
+		Ok(None) => return ImageLookupResult::NotFound,
+		Err(e) => {
+			println!("Failed to release image with err '{:}'", e);
+			return ImageLookupResult::Error;
+		}
+	}
+}
+
 #[no_mangle]
-extern "C" fn vk_client_find_image_data<'a>(
+extern "C" fn vk_client_delete_image(
+	vk_client: *mut VkClient,
+	image_name: *const c_char,
+) -> ImageLookupResult {
+	let res = unsafe { vk_client.as_mut() }
+		.unwrap()
+		.delete_image(&get_str(&image_name));
+
+	match res {
+		Ok(Some(_)) => return ImageLookupResult::Found,
+		Ok(None) => return ImageLookupResult::NotFound,
+		Err(e) => {
+			println!("Failed to delete image with err '{:}'", e);
+			return ImageLookupResult::Error;
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_find_image_data(
 	vk_client: *mut VkClient,
 	image_name: *const c_char,
 	force_update: bool,
-) -> *mut ClientImageDataGuard<'a> {
+) -> *mut ClientImageDataGuard {
 	let local_image = unsafe { vk_client.as_mut() }
 		.unwrap()
-		.find_image_data(&get_str(&image_name), force_update);
+		.read_image_data(&get_str(&image_name), force_update);
 
 	match local_image {
-		Ok(Some(d)) => {
-			return Box::into_raw(Box::new(ClientImageDataGuard {
-				_read_lock: d.0,
-				image_data: d.1,
-			}))
-		}
+		Ok(Some(d)) => return Box::into_raw(Box::new(ClientImageDataGuard { image_data: d })),
 		Ok(None) => return null_mut(),
 		Err(e) => {
 			println!("Failed to find image with error '{:}'", e);
@@ -235,7 +426,7 @@ extern "C" fn vk_client_find_image_data<'a>(
 
 #[no_mangle]
 extern "C" fn vk_client_image_data_guard_read<'a>(
-	image_data_guard: *const ClientImageDataGuard<'a>,
+	image_data_guard: *const ClientImageDataGuard,
 ) -> &'a ShmemDataInternal {
 	&unsafe { image_data_guard.as_ref() }.unwrap().image_data
 }
@@ -252,6 +443,337 @@ extern "C" fn vk_client_image_data_guard_destroy(
 	drop(unsafe { Box::from_raw(image_data_guard) });
 }
 
+#[no_mangle]
+extern "C" fn vk_client_list_images(vk_client: *mut VkClient) -> *mut ClientImageList {
+	let res = unsafe { vk_client.as_mut() }.unwrap().list_images();
+
+	match res {
+		Ok(Some(images)) => {
+			let images = images.iter().map(CImgListEntry::from).collect();
+			return Box::into_raw(Box::new(ClientImageList { images }));
+		}
+		Ok(None) => {
+			println!("Timed out while listing images");
+			return null_mut();
+		}
+		Err(e) => {
+			println!("Failed to list images with error '{:}'", e);
+			return null_mut();
+		}
+	}
+}
+
+// List the images whose name matches a glob pattern like "studio/cam1/*"
+#[no_mangle]
+extern "C" fn vk_client_list_images_matching(
+	vk_client: *mut VkClient,
+	pattern: *const c_char,
+) -> *mut ClientImageList {
+	let res = unsafe { vk_client.as_mut() }
+		.unwrap()
+		.list_images_matching(&get_str(&pattern));
+
+	match res {
+		Ok(Some(images)) => {
+			let images = images.iter().map(CImgListEntry::from).collect();
+			return Box::into_raw(Box::new(ClientImageList { images }));
+		}
+		Ok(None) => {
+			println!("Timed out while listing images");
+			return null_mut();
+		}
+		Err(e) => {
+			println!("Failed to list images with error '{:}'", e);
+			return null_mut();
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_image_list_len(image_list: *const ClientImageList) -> usize {
+	unsafe { image_list.as_ref() }.map_or(0, |x| x.images.len())
+}
+
+#[no_mangle]
+extern "C" fn vk_client_image_list_get(
+	image_list: *const ClientImageList,
+	index: usize,
+) -> *const CImgListEntry {
+	match unsafe { image_list.as_ref() }.and_then(|x| x.images.get(index)) {
+		Some(entry) => entry,
+		None => ptr::null(),
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_image_list_destroy(image_list: Option<NonNull<ClientImageList>>) {
+	if let Some(image_list) = image_list {
+		drop(unsafe { Box::from_raw(image_list.as_ptr()) });
+	}
+}
+
+// A NULL value removes the entry
+#[no_mangle]
+extern "C" fn vk_client_set_image_metadata(
+	vk_client: *mut VkClient,
+	image_name: *const c_char,
+	key: *const c_char,
+	value: *const c_char,
+) -> ImageLookupResult {
+	let value = match value.is_null() {
+		true => None,
+		false => Some(get_str(&value)),
+	};
+	let res = unsafe { vk_client.as_mut() }.unwrap().set_image_metadata(
+		&get_str(&image_name),
+		&get_str(&key),
+		value.as_deref(),
+	);
+
+	match res {
+		Ok(Some(_)) => return ImageLookupResult::Found,
+		Ok(None) => return ImageLookupResult::NotFound,
+		Err(e) => {
+			println!("Failed to set image metadata with err '{:}'", e);
+			return ImageLookupResult::Error;
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_get_image_metadata(
+	vk_client: *mut VkClient,
+	image_name: *const c_char,
+) -> *mut ClientImageMetadata {
+	let res = unsafe { vk_client.as_mut() }
+		.unwrap()
+		.get_image_metadata(&get_str(&image_name));
+
+	match res {
+		Ok(Some(entries)) => {
+			let entries = entries.iter().map(CImgMetadataEntry::from).collect();
+			return Box::into_raw(Box::new(ClientImageMetadata { entries }));
+		}
+		Ok(None) => return null_mut(),
+		Err(e) => {
+			println!("Failed to get image metadata with error '{:}'", e);
+			return null_mut();
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_image_metadata_len(metadata: *const ClientImageMetadata) -> usize {
+	unsafe { metadata.as_ref() }.map_or(0, |x| x.entries.len())
+}
+
+#[no_mangle]
+extern "C" fn vk_client_image_metadata_get(
+	metadata: *const ClientImageMetadata,
+	index: usize,
+) -> *const CImgMetadataEntry {
+	match unsafe { metadata.as_ref() }.and_then(|x| x.entries.get(index)) {
+		Some(entry) => entry,
+		None => ptr::null(),
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_image_metadata_destroy(metadata: Option<NonNull<ClientImageMetadata>>) {
+	if let Some(metadata) = metadata {
+		drop(unsafe { Box::from_raw(metadata.as_ptr()) });
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_get_stats(vk_client: *mut VkClient) -> *mut ClientServerStats {
+	let res = unsafe { vk_client.as_mut() }.unwrap().get_stats();
+
+	match res {
+		Ok(Some(stats)) => {
+			let images = stats.images.iter().map(CImgStatsEntry::from).collect();
+			return Box::into_raw(Box::new(ClientServerStats { stats, images }));
+		}
+		Ok(None) => {
+			println!("Timed out while requesting server stats");
+			return null_mut();
+		}
+		Err(e) => {
+			println!("Failed to get server stats with error '{:}'", e);
+			return null_mut();
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_stats_connection_count(stats: *const ClientServerStats) -> u32 {
+	unsafe { stats.as_ref() }.map_or(0, |x| x.stats.connection_count)
+}
+
+#[no_mangle]
+extern "C" fn vk_client_stats_gpu_copy_count(stats: *const ClientServerStats) -> u64 {
+	unsafe { stats.as_ref() }.map_or(0, |x| x.stats.gpu_copy_count)
+}
+
+#[no_mangle]
+extern "C" fn vk_client_stats_image_len(stats: *const ClientServerStats) -> usize {
+	unsafe { stats.as_ref() }.map_or(0, |x| x.images.len())
+}
+
+#[no_mangle]
+extern "C" fn vk_client_stats_image_get(
+	stats: *const ClientServerStats,
+	index: usize,
+) -> *const CImgStatsEntry {
+	match unsafe { stats.as_ref() }.and_then(|x| x.images.get(index)) {
+		Some(entry) => entry,
+		None => ptr::null(),
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_stats_device_len(stats: *const ClientServerStats) -> usize {
+	unsafe { stats.as_ref() }.map_or(0, |x| x.stats.devices.len())
+}
+
+#[no_mangle]
+extern "C" fn vk_client_stats_device_get(
+	stats: *const ClientServerStats,
+	index: usize,
+) -> *const DeviceStatsEntry {
+	match unsafe { stats.as_ref() }.and_then(|x| x.stats.devices.get(index)) {
+		Some(entry) => entry,
+		None => ptr::null(),
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_stats_destroy(stats: Option<NonNull<ClientServerStats>>) {
+	if let Some(stats) = stats {
+		drop(unsafe { Box::from_raw(stats.as_ptr()) });
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_subscribe(
+	vk_client: *mut VkClient,
+	image_name: *const c_char,
+	subscribe: bool,
+) -> ImageLookupResult {
+	let res = unsafe { vk_client.as_mut() }
+		.unwrap()
+		.subscribe(&get_str(&image_name), subscribe);
+
+	match res {
+		Ok(Some(true)) => return ImageLookupResult::Found,
+		Ok(_) => return ImageLookupResult::NotFound,
+		Err(e) => {
+			println!("Failed to subscribe to image with err '{:}'", e);
+			return ImageLookupResult::Error;
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_poll_event(vk_client: *mut VkClient, event: *mut CImgEvent) -> c_int {
+	let res = unsafe { vk_client.as_mut() }.unwrap().poll_event();
+
+	match res {
+		Ok(Some(e)) => {
+			unsafe { event.write(CImgEvent::from(&e)) };
+			return 1;
+		}
+		Ok(None) => return 0,
+		Err(e) => {
+			println!("Failed to poll event with error '{:}'", e);
+			return -1;
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_set_heartbeat_interval(
+	vk_client: *mut VkClient,
+	interval_in_millis: u64,
+) -> c_int {
+	let interval = match interval_in_millis {
+		0 => None,
+		millis => Some(Duration::from_millis(millis)),
+	};
+	let res = unsafe { vk_client.as_mut() }
+		.unwrap()
+		.set_heartbeat_interval(interval);
+
+	match res {
+		Ok(_) => 1,
+		Err(Error::ServerUnresponsive(_)) => 0,
+		Err(e) => {
+			println!("Failed to set heartbeat interval with error '{:}'", e);
+			-1
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_ping(vk_client: *mut VkClient) -> c_int {
+	let res = unsafe { vk_client.as_mut() }.unwrap().ping();
+
+	match res {
+		Ok(_) => 1,
+		Err(Error::ServerUnresponsive(_)) => 0,
+		Err(e) => {
+			println!("Failed to ping server with error '{:}'", e);
+			-1
+		}
+	}
+}
+
+#[no_mangle]
+extern "C" fn vk_client_heartbeat(vk_client: *mut VkClient) -> c_int {
+	let res = unsafe { vk_client.as_mut() }.unwrap().heartbeat();
+
+	match res {
+		Ok(_) => 1,
+		Err(Error::ServerUnresponsive(_)) => 0,
+		Err(e) => {
+			println!("Failed to send heartbeat with error '{:}'", e);
+			-1
+		}
+	}
+}
+
+// max_attempts == 0 disables reconnecting
+#[no_mangle]
+extern "C" fn vk_client_set_reconnect_policy(
+	vk_client: *mut VkClient,
+	max_attempts: u32,
+	retry_interval_in_millis: u64,
+) {
+	let policy = match max_attempts {
+		0 => None,
+		max_attempts => Some(ReconnectPolicy::new(
+			max_attempts,
+			Duration::from_millis(retry_interval_in_millis),
+		)),
+	};
+	unsafe { vk_client.as_mut() }
+		.unwrap()
+		.set_reconnect_policy(policy);
+}
+
+#[no_mangle]
+extern "C" fn vk_client_reconnect(vk_client: *mut VkClient) -> c_int {
+	let res = unsafe { vk_client.as_mut() }.unwrap().reconnect();
+
+	match res {
+		Ok(_) => 1,
+		Err(e) => {
+			println!("Failed to reconnect to server with error '{:}'", e);
+			-1
+		}
+	}
+}
+
 #[no_mangle]
 extern "C" fn vk_client_send_image(
 	vk_client: *mut VkClient,
@@ -298,12 +820,37 @@ extern "C" fn vk_client_recv_image(
 	target_layout: VkImageLayout,
 	fence: VkFence,
 	extents: Option<NonNull<VkOffset3D>>,
+) -> c_int {
+	vk_client_recv_image_frame(
+		vk_client,
+		image_name,
+		image,
+		orig_layout,
+		target_layout,
+		fence,
+		extents,
+		null_mut(),
+	)
+}
+
+// Like vk_client_recv_image, but also writes the number and timestamp of the received frame to
+// frame_info if it isn't NULL
+#[no_mangle]
+extern "C" fn vk_client_recv_image_frame(
+	vk_client: *mut VkClient,
+	image_name: *const c_char,
+	image: VkImage,
+	orig_layout: VkImageLayout,
+	target_layout: VkImageLayout,
+	fence: VkFence,
+	extents: Option<NonNull<VkOffset3D>>,
+	frame_info: *mut ImgFrameInfo,
 ) -> c_int {
 	let vk_client = unsafe { vk_client.as_mut() }.unwrap();
 	let image_name = &get_str(&image_name);
 
 	let res = match extents {
-		None => vk_client.recv_image(image_name, image, orig_layout, target_layout, fence),
+		None => vk_client.recv_image_frame(image_name, image, orig_layout, target_layout, fence),
 		Some(s) => unsafe {
 			vk_client.recv_image_with_extents_unchecked(
 				image_name,
@@ -317,7 +864,12 @@ extern "C" fn vk_client_recv_image(
 	};
 
 	match res {
-		Ok(Some(_)) => return 1,
+		Ok(Some(info)) => {
+			if let Some(frame_info) = unsafe { frame_info.as_mut() } {
+				*frame_info = info;
+			}
+			return 1;
+		}
 		Ok(None) => return 0,
 		Err(e) => {
 			println!("Failed to send image with error '{:}'", e);
@@ -325,3 +877,30 @@ extern "C" fn vk_client_recv_image(
 		}
 	}
 }
+
+// Read the last frame of an image without copying it. Returns 1 if frame_info was written, 0
+// if the image wasn't found and -1 on error
+#[no_mangle]
+extern "C" fn vk_client_get_frame_info(
+	vk_client: *mut VkClient,
+	image_name: *const c_char,
+	frame_info: *mut ImgFrameInfo,
+) -> c_int {
+	let res = unsafe { vk_client.as_ref() }
+		.unwrap()
+		.get_frame_info(&get_str(&image_name));
+
+	match res {
+		Ok(Some(info)) => {
+			if let Some(frame_info) = unsafe { frame_info.as_mut() } {
+				*frame_info = info;
+			}
+			return 1;
+		}
+		Ok(None) => return 0,
+		Err(e) => {
+			println!("Failed to read frame info with error '{:}'", e);
+			return -1;
+		}
+	}
+}
@@ -1,17 +1,25 @@
-use std::collections::HashMap;
-use std::io::{Error, ErrorKind};
-use std::{mem::ManuallyDrop, os::fd::OwnedFd, time::Duration};
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+use std::{os::fd::OwnedFd, time::Duration};
 
 use texture_share_vk_base::ash::vk;
-use texture_share_vk_base::ipc::platform::daemon_launch::server_connect_and_daemon_launch;
-use texture_share_vk_base::ipc::platform::img_data::{ImgData, ImgFormat};
+use texture_share_vk_base::ipc::platform::daemon_launch::{DaemonLaunchConfig, ReconnectPolicy};
+use texture_share_vk_base::ipc::platform::img_data::{
+	img_name, ImgData, ImgEvent, ImgFormat, ImgFrameInfo, ImgListEntry, ImgMetadataEntry,
+	IMG_MAX_BUFFER_COUNT,
+};
 use texture_share_vk_base::ipc::platform::ipc_commands::{
-	CommCopyImage, CommFindImage, CommInitImage, CommandData, CommandMsg, CommandTag,
+	CommCopyImage, CommDeleteImage, CommFindImage, CommInitImage, CommListImages,
+	CommListImagesMatching, CommReleaseImage, CommRenameImage, CommSubscribe,
+	CommandMsg, CommandTag, ResultMsg, ServerStats, DEFAULT_NAMESPACE,
 };
 use texture_share_vk_base::ipc::platform::ShmemDataInternal;
 use texture_share_vk_base::ipc::platform::{ReadLockGuard, Timeout};
-use texture_share_vk_base::ipc::{IpcConnection, IpcShmem};
+#[cfg(feature = "tokio")]
+use texture_share_vk_base::ipc::TokioWait;
+use texture_share_vk_base::ipc::{BlockingWait, Error, IpcConnection, IpcShmem, IpcWait};
 
+use texture_share_vk_base::uuid::Uuid;
 use texture_share_vk_base::vk_device::VkDevice;
 use texture_share_vk_base::vk_setup::VkSetup;
 use texture_share_vk_base::vk_shared_image::VkSharedImage;
@@ -20,6 +28,70 @@ use texture_share_vk_base::vk_shared_image::{ImageBlit, SharedImageData};
 pub struct ImageData {
 	pub ipc_info: IpcShmem,
 	pub vk_shared_image: VkSharedImage,
+	// Buffers 1.. of a multi-buffered image, vk_shared_image is buffer 0
+	pub extra_buffers: Vec<VkSharedImage>,
+}
+
+impl ImageData {
+	pub fn get_buffer_count(&self) -> u32 {
+		1 + self.extra_buffers.len() as u32
+	}
+
+	pub fn get_buffer(&self, index: u32) -> Result<&VkSharedImage, Error> {
+		match index {
+			0 => Ok(&self.vk_shared_image),
+			_ => self.extra_buffers.get(index as usize - 1).ok_or_else(|| {
+				Error::Protocol(format!(
+					"Buffer {} of {} out of range, update the image with find_image",
+					index,
+					self.get_buffer_count()
+				))
+			}),
+		}
+	}
+
+	pub fn destroy(self, vk_device: &VkDevice) {
+		self.vk_shared_image.destroy(vk_device);
+		self.extra_buffers
+			.into_iter()
+			.for_each(|x| x.destroy(vk_device));
+	}
+
+	// Blit a frame into the buffer after the latest one and publish it. A single buffer is write
+	// locked while blitting. Otherwise the lock is only taken to publish the frame, as readers
+	// only copy the latest buffer
+	fn write_frame(
+		&self,
+		blit: impl FnOnce(&VkSharedImage) -> Result<(), vk::Result>,
+	) -> Result<ImgFrameInfo, Error> {
+		let timeout = || Timeout::Val(VkClient::IPC_TIMEOUT);
+		if self.extra_buffers.is_empty() {
+			let lock = self.ipc_info.acquire_lock(timeout())?;
+			blit(&self.vk_shared_image)?;
+			return Ok(IpcShmem::acquire_data(&lock).next_frame());
+		}
+
+		let latest_buffer = self.ipc_info.read_data(timeout())?.latest_buffer;
+		let buffer = (latest_buffer + 1) % self.get_buffer_count();
+		blit(self.get_buffer(buffer)?)?;
+
+		let lock = self.ipc_info.acquire_lock(timeout())?;
+		Ok(IpcShmem::acquire_data(&lock).next_frame_in_buffer(buffer))
+	}
+
+	// Blit the latest frame out of the image. Returns its number and timestamp
+	fn read_frame(
+		&self,
+		blit: impl FnOnce(&VkSharedImage) -> Result<(), vk::Result>,
+	) -> Result<ImgFrameInfo, Error> {
+		let rlock = self
+			.ipc_info
+			.acquire_rlock(Timeout::Val(VkClient::IPC_TIMEOUT))?;
+		let rdata = IpcShmem::acquire_rdata(&rlock);
+		blit(self.get_buffer(rdata.latest_buffer)?)?;
+
+		Ok(rdata.get_frame_info())
+	}
 }
 
 pub struct VkClient {
@@ -27,6 +99,16 @@ pub struct VkClient {
 	vk_setup: Box<VkSetup>,
 	shared_images: HashMap<String, ImageData>,
 	gpu_device_uuid: u128,
+	heartbeat_interval: Option<Duration>,
+	last_heartbeat: Instant,
+	ping_sequence: u64,
+	socket_path: String,
+	timeout: Duration,
+	daemon_launch: Option<DaemonLaunchConfig>,
+	reconnect_policy: Option<ReconnectPolicy>,
+	// Images initialized or subscribed to by this client, restored after reconnecting
+	produced_images: HashSet<String>,
+	subscriptions: HashSet<String>,
 }
 
 impl Drop for VkClient {
@@ -34,40 +116,72 @@ impl Drop for VkClient {
 		// Ensure that images are cleared before destroying vulkan instance
 		self.shared_images
 			.drain()
-			.for_each(|x| x.1.vk_shared_image.destroy(&self.vk_setup.device));
+			.for_each(|x| x.1.destroy(&self.vk_setup.device));
 	}
 }
 
 impl VkClient {
 	const IPC_TIMEOUT: Duration = Duration::from_millis(5000);
+	// Heartbeat intervals to wait for a pong, so that a briefly busy server isn't reported as
+	// unresponsive. Matches the number of heartbeats the server lets a client miss
+	pub const HEARTBEAT_MISSED_LIMIT: u32 = 3;
 
 	pub fn new(
 		socket_path: &str,
 		vk_setup: Box<VkSetup>,
 		timeout: Duration,
 	) -> Result<VkClient, Error> {
-		let connection = IpcConnection::try_connect(socket_path, timeout)?;
-		if connection.is_none() {
-			return Err(Error::new(
-				ErrorKind::Interrupted,
-				format!("Connection to '{}' timed out", socket_path),
-			));
-		}
+		VkClient::new_with_namespace(socket_path, DEFAULT_NAMESPACE, vk_setup, timeout)
+	}
 
-		let shared_images = HashMap::default();
+	// Only images of the same namespace are visible to the client. Other namespaces may use the
+	// same image names without conflicts
+	pub fn new_with_namespace(
+		socket_path: &str,
+		namespace: &str,
+		vk_setup: Box<VkSetup>,
+		timeout: Duration,
+	) -> Result<VkClient, Error> {
+		let connection = IpcConnection::try_connect_namespace(socket_path, namespace, timeout)?
+			.ok_or_else(|| Error::Timeout(format!("Connecting to '{}'", socket_path)))?;
 
+		Ok(VkClient::with_connection(
+			connection,
+			vk_setup,
+			socket_path,
+			timeout,
+			None,
+		))
+	}
+
+	fn with_connection(
+		connection: IpcConnection,
+		vk_setup: Box<VkSetup>,
+		socket_path: &str,
+		timeout: Duration,
+		daemon_launch: Option<DaemonLaunchConfig>,
+	) -> VkClient {
 		let gpu_device_uuid = VkDevice::get_gpu_device_uuid(
 			&vk_setup.instance.instance,
 			vk_setup.device.physical_device,
 		)
 		.as_u128();
 
-		Ok(VkClient {
-			connection: connection.unwrap(),
+		VkClient {
+			connection,
 			vk_setup,
-			shared_images,
+			shared_images: HashMap::default(),
 			gpu_device_uuid,
-		})
+			heartbeat_interval: None,
+			last_heartbeat: Instant::now(),
+			ping_sequence: 0,
+			socket_path: socket_path.to_string(),
+			timeout,
+			daemon_launch,
+			reconnect_policy: None,
+			produced_images: HashSet::default(),
+			subscriptions: HashSet::default(),
+		}
 	}
 
 	pub fn new_with_server_launch(
@@ -84,19 +198,16 @@ impl VkClient {
 		server_lockfile_timeout: Duration,
 		server_spawn_timeout: Duration,
 	) -> Result<VkClient, Error> {
-		let conn_fn = || {
-			let connection = match IpcConnection::try_connect(socket_path, client_timeout) {
-				Err(e) => match e.kind() {
-					ErrorKind::ConnectionRefused => Ok(None),
-					_ => Err(e),
-				},
-				s => s,
-			}?;
-			if connection.is_none() {
-				return Ok(None);
-			}
-
-			Ok(Some(connection.unwrap()))
+		let daemon_launch = DaemonLaunchConfig {
+			program_path: server_program.to_string(),
+			lock_file_path: server_lock_path.to_string(),
+			socket_path: server_socket_path.to_string(),
+			shmem_prefix: shmem_prefix.to_string(),
+			socket_timeout: server_socket_timeout,
+			connection_wait_timeout: server_connection_wait_timeout,
+			ipc_timeout: server_ipc_timeout,
+			lockfile_timeout: server_lockfile_timeout,
+			spawn_timeout: server_spawn_timeout,
 		};
 
 		let gpu_device_uuid = VkDevice::get_gpu_device_uuid(
@@ -104,35 +215,30 @@ impl VkClient {
 			vk_setup.device.physical_device,
 		);
 
-		let res = server_connect_and_daemon_launch(
-			server_program,
-			server_lock_path,
-			server_socket_path,
-			shmem_prefix,
-			server_socket_timeout,
-			server_connection_wait_timeout,
-			server_ipc_timeout,
-			server_lockfile_timeout,
-			server_spawn_timeout,
+		let res = daemon_launch.connect(
+			socket_path,
+			DEFAULT_NAMESPACE,
+			client_timeout,
 			Some(gpu_device_uuid),
-			&conn_fn,
 		)?;
 
 		if let Some(connection) = res {
-			return Ok(VkClient {
+			return Ok(VkClient::with_connection(
 				connection,
 				vk_setup,
-				shared_images: HashMap::default(),
-				gpu_device_uuid: gpu_device_uuid.as_u128(),
-			});
-		} else {
-			return Err(Error::new(
-				ErrorKind::Interrupted,
-				format!("Connection to '{}' timed out", socket_path),
+				socket_path,
+				client_timeout,
+				Some(daemon_launch),
 			));
+		} else {
+			return Err(Error::Timeout(format!("Connecting to '{}'", socket_path)));
 		}
 	}
 
+	pub fn get_namespace(&self) -> String {
+		self.connection.get_namespace()
+	}
+
 	pub fn get_vk_setup(&self) -> &VkSetup {
 		&self.vk_setup
 	}
@@ -142,7 +248,24 @@ impl VkClient {
 	}
 
 	fn is_update_available(image_data: &ImageData) -> bool {
-		image_data.ipc_info.get_id_unchecked() != image_data.vk_shared_image.get_image_data().id
+		// A segment that can't be read is treated as changed, so that the image is looked up again
+		image_data
+			.ipc_info
+			.get_id(Timeout::Val(VkClient::IPC_TIMEOUT))
+			.map_or(true, |id| {
+				id != image_data.vk_shared_image.get_image_data().id
+			})
+	}
+
+	// Images imported from an earlier server instance are stale, even if their handle ids match
+	fn is_current_instance(
+		image_data: &ImageData,
+		server_instance_id: Option<u128>,
+	) -> bool {
+		image_data
+			.ipc_info
+			.get_server_instance_id(Timeout::Val(VkClient::IPC_TIMEOUT))
+			.is_ok_and(|id| server_instance_id == Some(id))
 	}
 
 	pub fn init_image(
@@ -152,57 +275,73 @@ impl VkClient {
 		height: u32,
 		format: ImgFormat,
 		overwrite_existing: bool,
-	) -> Result<Option<bool>, Box<dyn std::error::Error>> {
-		let image_name_buf = ImgData::convert_shmem_str_to_array(image_name);
-		let cmd_msg = CommandMsg {
-			tag: CommandTag::InitImage,
-			data: CommandData {
-				init_img: ManuallyDrop::new(CommInitImage {
-					image_name: image_name_buf,
-					shmem_name: image_name_buf,
-					width,
-					height,
-					format,
-					overwrite_existing,
-					gpu_device_uuid: self.gpu_device_uuid,
-				}),
-			},
-		};
-
-		self.connection.send_command(cmd_msg)?;
+	) -> Result<Option<bool>, Error> {
+		self.init_image_buffered(image_name, width, height, format, overwrite_existing, 1)
+	}
 
-		// Receive message and check for validity
-		let res_msg = self.connection.recv_result()?;
-		let res_data: Option<&ImgData> = match &res_msg {
-			None => Ok(None),
-			Some(msg) => match msg.tag {
-				CommandTag::InitImage => {
-					let data = unsafe { &msg.data.init_img };
-					if data.image_created {
-						Ok(Some(&data.img_data))
-					} else {
-						Ok(None)
-					}
-				}
-				_ => Err(Box::new(Error::new(
-					ErrorKind::InvalidData,
-					"Received invalid data from server",
-				))),
-			},
-		}?;
+	// Allocate buffer_count images for the name, up to IMG_MAX_BUFFER_COUNT. send_image writes
+	// the buffer after the latest one and recv_image reads the latest, so a slow reader doesn't
+	// block the writer
+	pub fn init_image_buffered(
+		&mut self,
+		image_name: &str,
+		width: u32,
+		height: u32,
+		format: ImgFormat,
+		overwrite_existing: bool,
+		buffer_count: u32,
+	) -> Result<Option<bool>, Error> {
+		self.retry_on_disconnect(|client| {
+			BlockingWait::block_on(client.init_image_once_with(
+				&BlockingWait,
+				image_name,
+				width,
+				height,
+				format,
+				overwrite_existing,
+				buffer_count,
+			))
+		})
+	}
 
-		// Don't import image if not created
-		if res_data.is_none() {
-			return Ok(None);
-		}
+	async fn init_image_once_with<W: IpcWait>(
+		&mut self,
+		wait: &W,
+		image_name: &str,
+		width: u32,
+		height: u32,
+		format: ImgFormat,
+		overwrite_existing: bool,
+		buffer_count: u32,
+	) -> Result<Option<bool>, Error> {
+		let cmd_msg = VkClient::init_image_msg(
+			image_name,
+			width,
+			height,
+			format,
+			overwrite_existing,
+			buffer_count,
+			self.gpu_device_uuid,
+		)?;
+		self.connection.send_command_with(wait, cmd_msg).await?;
 
-		let res_data = res_data.unwrap();
+		// Receive message and check for validity
+		let res_msg = self.connection.recv_result_with(wait).await?;
+		let res_data = match VkClient::get_result_img_data(&res_msg, CommandTag::InitImage)? {
+			Some(res_data) => res_data,
+			// Don't import image if not created
+			None => return Ok(None),
+		};
 
-		let mut share_handles = self.connection.recv_ancillary(1)?;
+		let share_handles = self
+			.connection
+			.recv_ancillary_with(wait, VkClient::get_buffer_count(res_data)?)
+			.await?;
 
-		self.connection.send_ack()?;
+		self.connection.send_ack_with(wait).await?;
+		self.produced_images.insert(image_name.to_string());
 
-		let res = self.add_new_image(&res_data, &mut share_handles)?;
+		let res = self.add_new_image(&res_data, share_handles)?;
 
 		let res = match res {
 			Some(r) => Some(VkClient::is_update_available(r)),
@@ -215,22 +354,108 @@ impl VkClient {
 		&mut self,
 		image_name: &str,
 		force_update: bool,
-	) -> Result<Option<bool>, Box<dyn std::error::Error>> {
-		let res = self.find_image_internal(image_name, force_update)?;
-		let res = match res {
-			Some(r) => Some(VkClient::is_update_available(r)),
-			None => None,
+	) -> Result<Option<bool>, Error> {
+		self.retry_on_disconnect(|client| {
+			let res = BlockingWait::block_on(client.find_image_internal_with(
+				&BlockingWait,
+				image_name,
+				force_update,
+			))?;
+			Ok(res.map(VkClient::is_update_available))
+		})
+	}
+
+	// Look up several images with a single round trip per FIND_IMAGES_MAX_COUNT names. Results are
+	// ordered like image_names and match those of find_image
+	pub fn find_images(
+		&mut self,
+		image_names: &[&str],
+		force_update: bool,
+	) -> Result<Option<Vec<Option<bool>>>, Error> {
+		self.retry_on_disconnect(|client| client.find_images_once(image_names, force_update))
+	}
+
+	// Block until the image is initialized by a producer or timeout passed, then import it like
+	// find_image. Returns None if the image didn't appear in time
+	pub fn wait_for_image(
+		&mut self,
+		image_name: &str,
+		timeout: Duration,
+		force_update: bool,
+	) -> Result<Option<bool>, Error> {
+		self.retry_on_disconnect(|client| {
+			if !client.connection.wait_for_image(image_name, timeout)? {
+				return Ok(None);
+			}
+
+			let res = BlockingWait::block_on(client.find_image_internal_with(
+				&BlockingWait,
+				image_name,
+				force_update,
+			))?;
+			Ok(res.map(VkClient::is_update_available))
+		})
+	}
+
+	fn find_images_once(
+		&mut self,
+		image_names: &[&str],
+		force_update: bool,
+	) -> Result<Option<Vec<Option<bool>>>, Error> {
+		// Only request images that weren't imported yet, each of them once
+		let mut request_names = Vec::new();
+		for image_name in image_names.iter() {
+			if (force_update || !self.has_current_image(image_name))
+				&& !request_names.contains(image_name)
+			{
+				request_names.push(*image_name);
+			}
+		}
+
+		let found_images = match self
+			.connection
+			.find_images(&request_names, self.gpu_device_uuid)?
+		{
+			Some(found_images) => found_images,
+			None => return Ok(None),
 		};
-		Ok(res)
+
+		let mut missing_names = Vec::new();
+		for (image_name, found_image) in request_names.iter().zip(found_images) {
+			match found_image {
+				Some((img_data, fds)) => {
+					VkClient::insert_local_image(
+						&self.vk_setup,
+						&mut self.shared_images,
+						image_name,
+						&img_data,
+						fds,
+					)?;
+				}
+				None => missing_names.push(*image_name),
+			}
+		}
+
+		let res = image_names
+			.iter()
+			.map(|image_name| match missing_names.contains(image_name) {
+				true => None,
+				false => self
+					.shared_images
+					.get(*image_name)
+					.map(VkClient::is_update_available),
+			})
+			.collect();
+		Ok(Some(res))
 	}
 
 	pub fn find_image_data(
 		&mut self,
 		image_name: &str,
 		force_update: bool,
-	) -> Result<Option<(ReadLockGuard, &ShmemDataInternal)>, Box<dyn std::error::Error>> {
-		let res = self.find_image_internal(image_name, force_update)?;
-		let res = match res {
+	) -> Result<Option<(ReadLockGuard, &ShmemDataInternal)>, Error> {
+		self.find_image(image_name, force_update)?;
+		let res = match self.shared_images.get(image_name) {
 			Some(image_data) => {
 				let rlock: ReadLockGuard = image_data
 					.ipc_info
@@ -243,6 +468,419 @@ impl VkClient {
 		Ok(res)
 	}
 
+	// Copy of the image data, read without holding a lock on it. Cheaper than find_image_data
+	// if the name or metadata aren't needed
+	pub fn read_image_data(
+		&mut self,
+		image_name: &str,
+		force_update: bool,
+	) -> Result<Option<ShmemDataInternal>, Error> {
+		self.find_image(image_name, force_update)?;
+		let res = match self.shared_images.get(image_name) {
+			Some(image_data) => Some(
+				image_data
+					.ipc_info
+					.read_data(Timeout::Val(VkClient::IPC_TIMEOUT))?,
+			),
+			None => None,
+		};
+		Ok(res)
+	}
+
+	pub fn rename_image(
+		&mut self,
+		old_image_name: &str,
+		new_image_name: &str,
+	) -> Result<Option<()>, Error> {
+		self.retry_on_disconnect(|client| client.rename_image_once(old_image_name, new_image_name))
+	}
+
+	fn rename_image_once(
+		&mut self,
+		old_image_name: &str,
+		new_image_name: &str,
+	) -> Result<Option<()>, Error> {
+		let cmd_msg = CommandMsg::RenameImage(CommRenameImage {
+			old_image_name: img_name(old_image_name)?,
+			new_image_name: img_name(new_image_name)?,
+		});
+		self.connection.send_command(cmd_msg)?;
+
+		let res_msg = self.connection.recv_result()?;
+		let res_data: Option<&ImgData> = match &res_msg {
+			None => Ok(None),
+			Some(ResultMsg::RenameImage(data)) => match data.image_found {
+				true => Ok(Some(&data.img_data)),
+				false => Ok(None),
+			},
+			Some(_) => Err(Error::Protocol(
+				"Received invalid data from server".to_string(),
+			)),
+		}?;
+
+		if res_data.is_none() {
+			return Ok(None);
+		}
+
+		// Memory is unchanged, only move the local image to the renamed shmem segment
+		if let Some(mut image_data) = self.shared_images.remove(old_image_name) {
+			let res_data = res_data.unwrap();
+			image_data.ipc_info =
+				IpcShmem::new(res_data.shmem_name.as_str(), new_image_name, false)?;
+			self.shared_images
+				.insert(new_image_name.to_string(), image_data)
+				.map(|x| x.destroy(&self.vk_setup.device));
+		}
+
+		if self.produced_images.remove(old_image_name) {
+			self.produced_images.insert(new_image_name.to_string());
+		}
+
+		Ok(Some(()))
+	}
+
+	pub fn list_images(&mut self) -> Result<Option<Vec<ImgListEntry>>, Error> {
+		self.retry_on_disconnect(|client| client.list_images_once(None))
+	}
+
+	// List the images whose name matches a glob pattern like "studio/cam1/*", see
+	// img_name_matches
+	pub fn list_images_matching(
+		&mut self,
+		pattern: &str,
+	) -> Result<Option<Vec<ImgListEntry>>, Error> {
+		self.retry_on_disconnect(|client| client.list_images_once(Some(pattern)))
+	}
+
+	fn list_images_once(
+		&mut self,
+		pattern: Option<&str>,
+	) -> Result<Option<Vec<ImgListEntry>>, Error> {
+		let cmd_msg = match pattern {
+			None => CommandMsg::ListImages(CommListImages {}),
+			Some(pattern) => CommandMsg::ListImagesMatching(CommListImagesMatching {
+				pattern: img_name(pattern)?,
+			}),
+		};
+		self.connection.send_command(cmd_msg)?;
+
+		// The server sends one result per image
+		let mut images = Vec::new();
+		loop {
+			let res_msg = self.connection.recv_result()?;
+			let res_data = match &res_msg {
+				None if images.is_empty() => return Ok(None),
+				None => Err(Error::Timeout("Receiving image list".to_string())),
+				Some(ResultMsg::ListImages(data)) if pattern.is_none() => Ok(data),
+				Some(ResultMsg::ListImagesMatching(data)) if pattern.is_some() => Ok(data),
+				Some(_) => Err(Error::Protocol(
+					"Received invalid data from server".to_string(),
+				)),
+			}?;
+
+			if res_data.image_index as usize != images.len() {
+				return Err(Error::Protocol(
+					"Received image list out of order".to_string(),
+				));
+			}
+
+			if res_data.image_count > 0 {
+				images.push(res_data.image.clone());
+			}
+
+			if images.len() >= res_data.image_count as usize {
+				break;
+			}
+		}
+
+		Ok(Some(images))
+	}
+
+	// Returns None if the server did not answer in time
+	pub fn get_stats(&mut self) -> Result<Option<ServerStats>, Error> {
+		self.retry_on_disconnect(|client| client.connection.get_stats())
+	}
+
+	// Set the metadata entry key of an image, or remove it if value is None. Returns None if the
+	// image was not found
+	pub fn set_image_metadata(
+		&mut self,
+		image_name: &str,
+		key: &str,
+		value: Option<&str>,
+	) -> Result<Option<()>, Error> {
+		self.retry_on_disconnect(|client| {
+			client.connection.set_image_metadata(image_name, key, value)
+		})
+	}
+
+	// The entries can also be read without a round trip from the image's shmem segment, see
+	// IpcShmem::acquire_rmetadata. Returns None if the image was not found
+	pub fn get_image_metadata(
+		&mut self,
+		image_name: &str,
+	) -> Result<Option<Vec<ImgMetadataEntry>>, Error> {
+		self.retry_on_disconnect(|client| client.connection.get_image_metadata(image_name))
+	}
+
+	// Returns Some(true) if the server destroyed the image, as no other client references it
+	pub fn release_image(
+		&mut self,
+		image_name: &str,
+	) -> Result<Option<bool>, Error> {
+		self.retry_on_disconnect(|client| client.release_image_once(image_name))
+	}
+
+	fn release_image_once(
+		&mut self,
+		image_name: &str,
+	) -> Result<Option<bool>, Error> {
+		let cmd_msg = CommandMsg::ReleaseImage(CommReleaseImage {
+			image_name: img_name(image_name)?,
+		});
+		self.connection.send_command(cmd_msg)?;
+
+		let res_msg = self.connection.recv_result()?;
+		let res_data = match &res_msg {
+			None => Ok(None),
+			Some(ResultMsg::ReleaseImage(data)) => match data.image_released {
+				true => Ok(Some(data.image_destroyed)),
+				false => Ok(None),
+			},
+			Some(_) => Err(Error::Protocol(
+				"Received invalid data from server".to_string(),
+			)),
+		}?;
+
+		self.shared_images
+			.remove(image_name)
+			.map(|x| x.destroy(&self.vk_setup.device));
+		self.produced_images.remove(image_name);
+
+		Ok(res_data)
+	}
+
+	// Destroy an image on the server, even if other clients still reference it
+	pub fn delete_image(
+		&mut self,
+		image_name: &str,
+	) -> Result<Option<()>, Error> {
+		self.retry_on_disconnect(|client| client.delete_image_once(image_name))
+	}
+
+	fn delete_image_once(
+		&mut self,
+		image_name: &str,
+	) -> Result<Option<()>, Error> {
+		let cmd_msg = CommandMsg::DeleteImage(CommDeleteImage {
+			image_name: img_name(image_name)?,
+		});
+		self.connection.send_command(cmd_msg)?;
+
+		let res_msg = self.connection.recv_result()?;
+		let res_data = match &res_msg {
+			None => Ok(None),
+			Some(ResultMsg::DeleteImage(data)) => match data.image_found {
+				true => Ok(Some(())),
+				false => Ok(None),
+			},
+			Some(_) => Err(Error::Protocol(
+				"Received invalid data from server".to_string(),
+			)),
+		}?;
+
+		self.shared_images
+			.remove(image_name)
+			.map(|x| x.destroy(&self.vk_setup.device));
+		self.produced_images.remove(image_name);
+
+		Ok(res_data)
+	}
+
+	// Start or stop receiving events for an image. Returns Some(true) if the image exists
+	pub fn subscribe(
+		&mut self,
+		image_name: &str,
+		subscribe: bool,
+	) -> Result<Option<bool>, Error> {
+		self.retry_on_disconnect(|client| client.subscribe_once(image_name, subscribe))
+	}
+
+	fn subscribe_once(
+		&mut self,
+		image_name: &str,
+		subscribe: bool,
+	) -> Result<Option<bool>, Error> {
+		let cmd_msg = CommandMsg::Subscribe(CommSubscribe {
+			image_name: img_name(image_name)?,
+			subscribe,
+		});
+		self.connection.send_command(cmd_msg)?;
+
+		let res_msg = self.connection.recv_result()?;
+		let res_data = match &res_msg {
+			None => Ok(None),
+			Some(ResultMsg::Subscribe(data)) => Ok(Some(data.image_found)),
+			Some(_) => Err(Error::Protocol(
+				"Received invalid data from server".to_string(),
+			)),
+		}?;
+
+		if res_data.is_some() {
+			match subscribe {
+				true => self.subscriptions.insert(image_name.to_string()),
+				false => self.subscriptions.remove(image_name),
+			};
+		}
+
+		Ok(res_data)
+	}
+
+	// Return the next event of a subscribed image, without waiting for one
+	pub fn poll_event(&mut self) -> Result<Option<ImgEvent>, Error> {
+		Ok(self.connection.recv_event_if_available()?)
+	}
+
+	pub fn poll_events(&mut self) -> Result<Vec<ImgEvent>, Error> {
+		let mut events = Vec::new();
+		while let Some(event) = self.poll_event()? {
+			events.push(event);
+		}
+
+		Ok(events)
+	}
+
+	// Ping the server every interval from heartbeat(), so that it can detect a stalled client.
+	// The server is reported as unresponsive if a ping isn't answered within
+	// HEARTBEAT_MISSED_LIMIT intervals. Unanswered pings don't trigger a reconnect
+	pub fn set_heartbeat_interval(&mut self, interval: Option<Duration>) -> Result<(), Error> {
+		self.heartbeat_interval = interval;
+
+		// Announce the new interval to the server
+		self.ping().map(|_| ())
+	}
+
+	// Check that the server is responsive. Returns the round trip time
+	pub fn ping(&mut self) -> Result<Duration, Error> {
+		self.retry_on_disconnect(|client| client.ping_once())
+	}
+
+	fn ping_once(&mut self) -> Result<Duration, Error> {
+		let timeout = self
+			.heartbeat_interval
+			.map_or(VkClient::IPC_TIMEOUT, |i| i.saturating_mul(VkClient::HEARTBEAT_MISSED_LIMIT));
+		self.ping_sequence += 1;
+		self.last_heartbeat = Instant::now();
+
+		self.connection
+			.ping(self.ping_sequence, self.heartbeat_interval, timeout)?
+			.ok_or(Error::ServerUnresponsive(timeout))
+	}
+
+	// Ping the server if the heartbeat interval elapsed. Should be called regularly, e.g. once
+	// per frame
+	pub fn heartbeat(&mut self) -> Result<(), Error> {
+		match self.heartbeat_interval {
+			Some(interval) if self.last_heartbeat.elapsed() >= interval => self.ping().map(|_| ()),
+			_ => Ok(()),
+		}
+	}
+
+	// Reconnect automatically if a command fails because the server went away. None disables
+	// reconnecting, which is the default
+	pub fn set_reconnect_policy(&mut self, policy: Option<ReconnectPolicy>) {
+		self.reconnect_policy = policy;
+	}
+
+	// Connect to the server again, relaunching it if this client was created with
+	// new_with_server_launch. Images initialized by this client are initialized again, all others
+	// are looked up again. Images that no longer exist are dropped
+	pub fn reconnect(&mut self) -> Result<(), Error> {
+		let policy = self.reconnect_policy.clone().unwrap_or_default();
+		self.connection = policy.connect(
+			&self.socket_path,
+			&self.connection.get_namespace(),
+			self.timeout,
+			self.daemon_launch.as_ref(),
+			Some(Uuid::from_u128(self.gpu_device_uuid)),
+		)?;
+
+		// Announce the heartbeat interval to the new server
+		if self.heartbeat_interval.is_some() {
+			self.ping_once()?;
+		}
+
+		// Initialize own images first, so that other clients can find them
+		let mut find_names = Vec::new();
+		let image_names: Vec<String> = self.shared_images.keys().cloned().collect();
+		for image_name in image_names {
+			if !self.produced_images.contains(&image_name) {
+				find_names.push(image_name);
+				continue;
+			}
+
+			let image_data = &self.shared_images[&image_name];
+			let data = image_data.vk_shared_image.get_image_data();
+			let (width, height) = (data.width, data.height);
+			let format = VkSharedImage::get_img_format(data.format);
+			let buffer_count = image_data.get_buffer_count();
+
+			// Another client may have initialized the image already
+			let res = BlockingWait::block_on(self.init_image_once_with(
+				&BlockingWait,
+				&image_name,
+				width,
+				height,
+				format,
+				false,
+				buffer_count,
+			))?;
+			if res.is_none() {
+				find_names.push(image_name);
+			}
+		}
+
+		let find_names: Vec<&str> = find_names.iter().map(|x| x.as_str()).collect();
+		let found = self
+			.find_images_once(&find_names, true)?
+			.ok_or_else(|| Error::Timeout("Restoring images".to_string()))?;
+		for (image_name, found) in find_names.iter().zip(found) {
+			if found.is_none() {
+				if let Some(image_data) = self.shared_images.remove(*image_name) {
+					image_data.destroy(&self.vk_setup.device);
+				}
+				self.produced_images.remove(*image_name);
+			}
+		}
+
+		let subscriptions: Vec<String> = self.subscriptions.iter().cloned().collect();
+		for image_name in subscriptions {
+			self.subscribe_once(&image_name, true)?;
+		}
+
+		Ok(())
+	}
+
+	// Run f again after reconnecting if it failed because the connection broke and a reconnect
+	// policy is set. Logs the reconnect error and returns the original error if reconnecting
+	// fails
+	fn retry_on_disconnect<T>(
+		&mut self,
+		mut f: impl FnMut(&mut VkClient) -> Result<T, Error>,
+	) -> Result<T, Error> {
+		let res = f(self);
+		if let Err(e) = &res {
+			if e.is_disconnect() && self.reconnect_policy.is_some() {
+				match self.reconnect() {
+					Ok(()) => return f(self),
+					Err(err) => println!("Failed to reconnect: {}", err),
+				}
+			}
+		}
+
+		res
+	}
+
 	pub fn send_image(
 		&mut self,
 		image_name: &str,
@@ -250,21 +888,24 @@ impl VkClient {
 		orig_layout: vk::ImageLayout,
 		target_layout: vk::ImageLayout,
 		fence: vk::Fence,
-	) -> Result<Option<()>, Box<dyn std::error::Error>> {
+	) -> Result<Option<()>, Error> {
 		let remote_image = self.shared_images.get_mut(image_name);
 		if remote_image.is_none() {
 			return Ok(None);
 		}
 
-		// Send image
+		// Send image. The frame number is only bumped once the frame was written, so that readers
+		// never see the number of a frame that wasn't written yet
 		let remote_image = remote_image.unwrap();
-		remote_image.vk_shared_image.recv_image_blit(
-			&self.vk_setup.device,
-			&image,
-			orig_layout,
-			target_layout,
-			fence,
-		)?;
+		remote_image.write_frame(|buffer| {
+			buffer.recv_image_blit(
+				&self.vk_setup.device,
+				&image,
+				orig_layout,
+				target_layout,
+				fence,
+			)
+		})?;
 
 		Ok(Some(()))
 	}
@@ -277,7 +918,7 @@ impl VkClient {
 		target_layout: vk::ImageLayout,
 		fence: vk::Fence,
 		extents: &[vk::Offset3D; 2],
-	) -> Result<Option<()>, Box<dyn std::error::Error>> {
+	) -> Result<Option<()>, Error> {
 		unsafe {
 			self.send_image_with_extents_unchecked(
 				image_name,
@@ -298,7 +939,7 @@ impl VkClient {
 		target_layout: vk::ImageLayout,
 		fence: vk::Fence,
 		extents: *const vk::Offset3D,
-	) -> Result<Option<()>, Box<dyn std::error::Error>> {
+	) -> Result<Option<()>, Error> {
 		let remote_image = self.shared_images.get_mut(image_name);
 		if remote_image.is_none() {
 			return Ok(None);
@@ -311,16 +952,18 @@ impl VkClient {
 		};
 
 		let remote_image = remote_image.unwrap();
-		remote_image.vk_shared_image.recv_image_blit_with_extents(
-			&self.vk_setup.device,
-			&image,
-			orig_layout,
-			target_layout,
-			extents,
-			fence,
-		)?;
+		remote_image.write_frame(|buffer| {
+			buffer.recv_image_blit_with_extents(
+				&self.vk_setup.device,
+				&image,
+				orig_layout,
+				target_layout,
+				extents,
+				fence,
+			)
+		})?;
 
-		self.copy_image_cmd(image_name)?;
+		self.retry_on_disconnect(|client| client.copy_image_cmd(image_name))?;
 
 		Ok(Some(()))
 	}
@@ -332,25 +975,60 @@ impl VkClient {
 		orig_layout: vk::ImageLayout,
 		target_layout: vk::ImageLayout,
 		fence: vk::Fence,
-	) -> Result<Option<()>, Box<dyn std::error::Error>> {
+	) -> Result<Option<()>, Error> {
+		self.recv_image_frame(image_name, image, orig_layout, target_layout, fence)
+			.map(|x| x.map(|_| ()))
+	}
+
+	// Like recv_image, but returns the number and timestamp of the received frame
+	pub fn recv_image_frame(
+		&mut self,
+		image_name: &str,
+		image: vk::Image,
+		orig_layout: vk::ImageLayout,
+		target_layout: vk::ImageLayout,
+		fence: vk::Fence,
+	) -> Result<Option<ImgFrameInfo>, Error> {
 		let remote_image = self.shared_images.get_mut(image_name);
 		if remote_image.is_none() {
 			return Ok(None);
 		}
 
 		let remote_image = remote_image.unwrap();
-		remote_image.vk_shared_image.send_image_blit(
-			&self.vk_setup.device,
-			&image,
+		let frame_info = remote_image.read_frame(|buffer| {
+			buffer.send_image_blit(
+				&self.vk_setup.device,
+				&image,
+				orig_layout,
+				target_layout,
+				fence,
+			)
+		})?;
+
+		Ok(Some(frame_info))
+	}
+
+	pub fn recv_image_with_extents(
+		&mut self,
+		image_name: &str,
+		image: vk::Image,
+		orig_layout: vk::ImageLayout,
+		target_layout: vk::ImageLayout,
+		fence: vk::Fence,
+		extents: &[vk::Offset3D; 2],
+	) -> Result<Option<()>, Error> {
+		self.recv_image_frame_with_extents(
+			image_name,
+			image,
 			orig_layout,
 			target_layout,
 			fence,
-		)?;
-
-		Ok(Some(()))
+			extents,
+		)
+		.map(|x| x.map(|_| ()))
 	}
 
-	pub fn recv_image_with_extents(
+	pub fn recv_image_frame_with_extents(
 		&mut self,
 		image_name: &str,
 		image: vk::Image,
@@ -358,7 +1036,7 @@ impl VkClient {
 		target_layout: vk::ImageLayout,
 		fence: vk::Fence,
 		extents: &[vk::Offset3D; 2],
-	) -> Result<Option<()>, Box<dyn std::error::Error>> {
+	) -> Result<Option<ImgFrameInfo>, Error> {
 		unsafe {
 			self.recv_image_with_extents_unchecked(
 				image_name,
@@ -379,7 +1057,7 @@ impl VkClient {
 		target_layout: vk::ImageLayout,
 		fence: vk::Fence,
 		extents: *const vk::Offset3D,
-	) -> Result<Option<()>, Box<dyn std::error::Error>> {
+	) -> Result<Option<ImgFrameInfo>, Error> {
 		let remote_image = self.shared_images.get_mut(image_name);
 		if remote_image.is_none() {
 			return Ok(None);
@@ -392,148 +1070,407 @@ impl VkClient {
 		};
 
 		let remote_image = remote_image.unwrap();
-		remote_image.vk_shared_image.send_image_blit_with_extents(
-			&self.vk_setup.device,
-			&image,
-			orig_layout,
-			target_layout,
-			extents,
-			fence,
-		)?;
-		Ok(Some(()))
+		let frame_info = remote_image.read_frame(|buffer| {
+			buffer.send_image_blit_with_extents(
+				&self.vk_setup.device,
+				&image,
+				orig_layout,
+				target_layout,
+				extents,
+				fence,
+			)
+		})?;
+		Ok(Some(frame_info))
+	}
+
+	// Number and timestamp of the last frame sent to a local image, without copying it. Compare
+	// with the frame returned by recv_image_frame to skip duplicate frames
+	pub fn get_frame_info(
+		&self,
+		image_name: &str,
+	) -> Result<Option<ImgFrameInfo>, Error> {
+		let remote_image = match self.shared_images.get(image_name) {
+			Some(remote_image) => remote_image,
+			None => return Ok(None),
+		};
+
+		let rdata = remote_image
+			.ipc_info
+			.read_data(Timeout::Val(VkClient::IPC_TIMEOUT))?;
+		Ok(Some(rdata.get_frame_info()))
 	}
 
 	fn add_new_image(
 		&mut self,
 		img_data: &ImgData,
-		share_handles: &mut Vec<OwnedFd>,
-	) -> Result<Option<&ImageData>, Box<dyn std::error::Error>> {
-		// TODO: Update if sharing more handles
-		debug_assert_eq!(share_handles.len(), 1);
-		let fd = share_handles.pop().unwrap();
-
-		let image_name = ImgData::convert_shmem_array_to_str(&img_data.data.name);
-		let image_data = Self::create_local_image(&self.vk_setup, img_data, fd)?;
-		self.shared_images
+		share_handles: Vec<OwnedFd>,
+	) -> Result<Option<&ImageData>, Error> {
+		let image_name = img_data.image_name.to_string();
+		let image_data = VkClient::insert_local_image(
+			&self.vk_setup,
+			&mut self.shared_images,
+			&image_name,
+			img_data,
+			share_handles,
+		)?;
+
+		Ok(Some(image_data))
+	}
+
+	// Import an image received from the server with one handle per buffer, replacing an older
+	// local copy
+	fn insert_local_image<'a>(
+		vk_setup: &VkSetup,
+		shared_images: &'a mut HashMap<String, ImageData>,
+		image_name: &str,
+		img_data: &ImgData,
+		fds: Vec<OwnedFd>,
+	) -> Result<&'a ImageData, Error> {
+		let image_data = Self::create_local_image(vk_setup, img_data, fds)?;
+		shared_images
 			.insert(image_name.to_string(), image_data)
-			.map(|x| x.vk_shared_image.destroy(&self.vk_setup.device));
+			.map(|x| x.destroy(&vk_setup.device));
 
-		Ok(Some(self.shared_images.get(&image_name).unwrap()))
+		Ok(shared_images.get(image_name).unwrap())
+	}
+
+	fn init_image_msg(
+		image_name: &str,
+		width: u32,
+		height: u32,
+		format: ImgFormat,
+		overwrite_existing: bool,
+		buffer_count: u32,
+		gpu_device_uuid: u128,
+	) -> Result<CommandMsg, Error> {
+		let image_name = img_name(image_name)?;
+		Ok(CommandMsg::InitImage(CommInitImage {
+			shmem_name: image_name.clone(),
+			image_name,
+			width,
+			height,
+			format,
+			overwrite_existing,
+			gpu_device_uuid,
+			buffer_count,
+		}))
+	}
+
+	fn find_image_msg(
+		image_name: &str,
+		gpu_device_uuid: u128,
+	) -> Result<CommandMsg, Error> {
+		Ok(CommandMsg::FindImage(CommFindImage {
+			image_name: img_name(image_name)?,
+			gpu_device_uuid,
+		}))
+	}
+
+	// Image data of an InitImage or FindImage result. None if the server didn't create or find
+	// the image, in which case no handles follow
+	fn get_result_img_data(
+		res_msg: &Option<ResultMsg>,
+		tag: CommandTag,
+	) -> Result<Option<&ImgData>, Error> {
+		let (image_available, img_data) = match (tag, res_msg) {
+			(_, None) => return Ok(None),
+			(CommandTag::InitImage, Some(ResultMsg::InitImage(data))) => {
+				(data.image_created, &data.img_data)
+			}
+			(CommandTag::FindImage, Some(ResultMsg::FindImage(data))) => {
+				(data.image_found, &data.img_data)
+			}
+			_ => {
+				return Err(Error::Protocol(
+					"Received invalid data from server".to_string(),
+				))
+			}
+		};
+
+		Ok(match image_available {
+			true => Some(img_data),
+			false => None,
+		})
 	}
 
 	fn create_local_image(
 		vk_setup: &VkSetup,
 		img_data: &ImgData,
-		img_mem_fd: OwnedFd,
-	) -> Result<ImageData, Box<dyn std::error::Error>> {
+		img_mem_fds: Vec<OwnedFd>,
+	) -> Result<ImageData, Error> {
+		if img_mem_fds.is_empty() {
+			return Err(Error::Protocol(
+				"Received no image handles".to_string(),
+			));
+		}
+
 		let shmem = IpcShmem::new(
-			&ImgData::convert_shmem_array_to_str(&img_data.shmem_name),
-			&ImgData::convert_shmem_array_to_str(&img_data.data.name),
+			img_data.shmem_name.as_str(),
+			img_data.image_name.as_str(),
 			false,
 		)?;
 
-		let vk_shared_image = {
+		let mut buffers = Vec::with_capacity(img_mem_fds.len());
+		{
 			let rlock = shmem.acquire_rlock(Timeout::Val(VkClient::IPC_TIMEOUT))?;
 			let _rdata = IpcShmem::acquire_rdata(&rlock);
 
-			let vk_shared_image = VkSharedImage::import_from_handle(
-				&vk_setup.instance,
-				&vk_setup.device,
-				img_mem_fd,
-				SharedImageData::from_shmem_img_data(&img_data.data),
-			)?;
-			vk_shared_image
-		};
+			for img_mem_fd in img_mem_fds {
+				let res = VkSharedImage::import_from_handle(
+					&vk_setup.instance,
+					&vk_setup.device,
+					img_mem_fd,
+					SharedImageData::from_shmem_img_data(&img_data.data),
+				);
+				match res {
+					Ok(vk_shared_image) => buffers.push(vk_shared_image),
+					Err(e) => {
+						// Free the buffers imported so far
+						buffers
+							.into_iter()
+							.for_each(|x| x.destroy(&vk_setup.device));
+						return Err(e.into());
+					}
+				}
+			}
+		}
 
+		let vk_shared_image = buffers.remove(0);
 		Ok(ImageData {
 			ipc_info: shmem,
 			vk_shared_image,
+			extra_buffers: buffers,
 		})
 	}
 
-	fn find_image_internal(
+	// Number of handles that follow an InitImage or FindImage result
+	fn get_buffer_count(img_data: &ImgData) -> Result<usize, Error> {
+		let buffer_count = img_data.data.buffer_count;
+		if buffer_count == 0 || buffer_count > IMG_MAX_BUFFER_COUNT {
+			return Err(Error::Protocol(format!(
+				"Image buffer count {} out of range 1-{}",
+				buffer_count, IMG_MAX_BUFFER_COUNT
+			)));
+		}
+
+		Ok(buffer_count as usize)
+	}
+
+	// Drop the local image if it was imported from an earlier server instance. Returns true if a
+	// current local image exists
+	fn has_current_image(&mut self, image_name: &str) -> bool {
+		let server_instance_id = self.connection.get_server_instance_id();
+		match self.shared_images.get(image_name) {
+			None => false,
+			Some(image_data) if VkClient::is_current_instance(image_data, server_instance_id) => {
+				true
+			}
+			Some(_) => {
+				self.shared_images
+					.remove(image_name)
+					.map(|x| x.destroy(&self.vk_setup.device));
+				false
+			}
+		}
+	}
+
+	async fn find_image_internal_with<W: IpcWait>(
 		&mut self,
+		wait: &W,
 		image_name: &str,
 		force_update: bool,
-	) -> Result<Option<&ImageData>, Box<dyn std::error::Error>> {
+	) -> Result<Option<&ImageData>, Error> {
 		if force_update {
-			let res = self.find_image_cmd(image_name)?;
+			let res = self.find_image_cmd_with(wait, image_name).await?;
 			return Ok(res);
 		}
 
-		let res = match self.shared_images.contains_key(image_name) {
+		let res = match self.has_current_image(image_name) {
 			true => self.shared_images.get(image_name),
-			false => self.find_image_cmd(image_name)?,
+			false => self.find_image_cmd_with(wait, image_name).await?,
 		};
 
 		Ok(res)
 	}
 
-	fn find_image_cmd(
+	async fn find_image_cmd_with<W: IpcWait>(
 		&mut self,
+		wait: &W,
 		image_name: &str,
-	) -> Result<Option<&ImageData>, Box<dyn std::error::Error>> {
-		let cmd_dat = ManuallyDrop::new(CommFindImage {
-			image_name: ImgData::convert_shmem_str_to_array(image_name),
+	) -> Result<Option<&ImageData>, Error> {
+		let cmd_msg = VkClient::find_image_msg(image_name, self.gpu_device_uuid)?;
+		self.connection.send_command_with(wait, cmd_msg).await?;
+
+		let res_msg = self.connection.recv_result_with(wait).await?;
+		let res_data = match VkClient::get_result_img_data(&res_msg, CommandTag::FindImage)? {
+			Some(res_data) => res_data,
+			None => return Ok(None),
+		};
+
+		let share_handles = self
+			.connection
+			.recv_ancillary_with(wait, VkClient::get_buffer_count(res_data)?)
+			.await?;
+
+		self.connection.send_ack_with(wait).await?;
+
+		let image_data = VkClient::insert_local_image(
+			&self.vk_setup,
+			&mut self.shared_images,
+			image_name,
+			&res_data,
+			share_handles,
+		)?;
+		Ok(Some(image_data))
+	}
+
+	fn copy_image_cmd(&mut self, image_name: &str) -> Result<(), Error> {
+		let cmd_msg = CommandMsg::CopyImage(CommCopyImage {
+			image_name: img_name(image_name)?,
 			gpu_device_uuid: self.gpu_device_uuid,
 		});
-		let cmd_msg = CommandMsg {
-			tag: CommandTag::FindImage,
-			data: CommandData { find_img: cmd_dat },
-		};
 		self.connection.send_command(cmd_msg)?;
 
-		let res_msg = self.connection.recv_result()?;
-		let res_data: Option<&ImgData> = match &res_msg {
-			None => Ok(None),
-			Some(msg) => match msg.tag {
-				CommandTag::FindImage => {
-					let data = unsafe { &msg.data.find_img };
-					if data.image_found {
-						Ok(Some(&data.img_data))
-					} else {
-						Ok(None)
-					}
-				}
-				_ => Err(Box::new(Error::new(
-					ErrorKind::InvalidData,
-					"Received invalid data from server",
-				))),
-			},
-		}?;
+		Ok(())
+	}
+}
 
-		if res_data.is_none() {
-			return Ok(None);
-		}
+// Async variant of VkClient. Waiting for the server yields to the runtime instead of blocking
+// the render thread, image copies are still executed synchronously. Commands don't reconnect
+// automatically
+#[cfg(feature = "tokio")]
+pub struct AsyncVkClient {
+	client: VkClient,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncVkClient {
+	pub async fn new(
+		socket_path: &str,
+		vk_setup: Box<VkSetup>,
+		timeout: Duration,
+	) -> Result<AsyncVkClient, Error> {
+		let connection = IpcConnection::try_connect_namespace_with(
+			&TokioWait,
+			socket_path,
+			DEFAULT_NAMESPACE,
+			timeout,
+		)
+		.await?
+		.ok_or_else(|| Error::Timeout(format!("Connecting to '{}'", socket_path)))?;
 
-		let res_data = res_data.unwrap();
+		Ok(AsyncVkClient {
+			client: VkClient::with_connection(connection, vk_setup, socket_path, timeout, None),
+		})
+	}
 
-		let mut share_handles = self.connection.recv_ancillary(1)?;
+	pub fn get_vk_setup(&self) -> &VkSetup {
+		self.client.get_vk_setup()
+	}
 
-		self.connection.send_ack()?;
+	pub fn get_vk_setup_mut(&mut self) -> &mut VkSetup {
+		self.client.get_vk_setup_mut()
+	}
 
-		let fd = share_handles.pop().unwrap();
+	pub async fn init_image(
+		&mut self,
+		image_name: &str,
+		width: u32,
+		height: u32,
+		format: ImgFormat,
+		overwrite_existing: bool,
+	) -> Result<Option<bool>, Error> {
+		self.init_image_buffered(image_name, width, height, format, overwrite_existing, 1)
+			.await
+	}
 
-		let image_data = Self::create_local_image(&self.vk_setup, &res_data, fd)?;
-		self.shared_images
-			.insert(image_name.to_string(), image_data)
-			.map(|x| x.vk_shared_image.destroy(&self.vk_setup.device));
+	// See VkClient::init_image_buffered
+	pub async fn init_image_buffered(
+		&mut self,
+		image_name: &str,
+		width: u32,
+		height: u32,
+		format: ImgFormat,
+		overwrite_existing: bool,
+		buffer_count: u32,
+	) -> Result<Option<bool>, Error> {
+		self.client
+			.init_image_once_with(
+				&TokioWait,
+				image_name,
+				width,
+				height,
+				format,
+				overwrite_existing,
+				buffer_count,
+			)
+			.await
+	}
 
-		Ok(Some(&self.shared_images.get(image_name).unwrap()))
+	pub async fn find_image(
+		&mut self,
+		image_name: &str,
+		force_update: bool,
+	) -> Result<Option<bool>, Error> {
+		let res = self
+			.client
+			.find_image_internal_with(&TokioWait, image_name, force_update)
+			.await?;
+		Ok(res.map(VkClient::is_update_available))
 	}
 
-	fn copy_image_cmd(&mut self, image_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-		let cmd_dat = ManuallyDrop::new(CommCopyImage {
-			image_name: ImgData::convert_shmem_str_to_array(image_name),
-			gpu_device_uuid: self.gpu_device_uuid,
-		});
-		let cmd_msg = CommandMsg {
-			tag: CommandTag::CopyImage,
-			data: CommandData { copy_img: cmd_dat },
+	pub async fn find_image_data(
+		&mut self,
+		image_name: &str,
+		force_update: bool,
+	) -> Result<Option<(ReadLockGuard<'_>, &ShmemDataInternal)>, Error> {
+		let res = self
+			.client
+			.find_image_internal_with(&TokioWait, image_name, force_update)
+			.await?;
+		let res = match res {
+			Some(image_data) => {
+				let rlock = image_data
+					.ipc_info
+					.acquire_rlock(Timeout::Val(VkClient::IPC_TIMEOUT))?;
+				let rdata = IpcShmem::acquire_rdata(&rlock);
+				Some((rlock, rdata))
+			}
+			None => None,
 		};
-		self.connection.send_command(cmd_msg)?;
+		Ok(res)
+	}
 
-		Ok(())
+	// Return the next event of a subscribed image, without waiting for one
+	pub async fn poll_event(&mut self) -> Result<Option<ImgEvent>, Error> {
+		self.client
+			.connection
+			.recv_event_if_available_with(&TokioWait)
+			.await
+	}
+
+	pub fn send_image(
+		&mut self,
+		image_name: &str,
+		image: vk::Image,
+		orig_layout: vk::ImageLayout,
+		target_layout: vk::ImageLayout,
+		fence: vk::Fence,
+	) -> Result<Option<()>, Error> {
+		self.client
+			.send_image(image_name, image, orig_layout, target_layout, fence)
+	}
+
+	pub fn recv_image(
+		&mut self,
+		image_name: &str,
+		image: vk::Image,
+		orig_layout: vk::ImageLayout,
+		target_layout: vk::ImageLayout,
+		fence: vk::Fence,
+	) -> Result<Option<()>, Error> {
+		self.client
+			.recv_image(image_name, image, orig_layout, target_layout, fence)
 	}
 }
 
@@ -563,7 +1500,14 @@ mod tests {
 
 		let server_socket_fcn = || {
 			let server_socket = _create_server_socket();
-			server_socket.try_accept().unwrap()
+			server_socket.try_accept().unwrap()?;
+
+			// The handshake is processed after the connection was accepted
+			let conn_lock = server_socket.connections.lock().unwrap();
+			let server_conn = conn_lock.last().unwrap().borrow();
+			server_conn
+				.accept_handshake(server_socket.server_instance_id)
+				.ok()
 		};
 
 		let server_thread = thread::spawn(server_socket_fcn);
@@ -580,6 +1524,6 @@ mod tests {
 		let _client = VkClient::new(SOCKET_PATH, vk_setup, TIMEOUT).unwrap();
 
 		let server_res = server_thread.join().unwrap();
-		assert!(server_res.is_some());
+		assert_eq!(server_res, Some(true));
 	}
 }
@@ -11,7 +11,12 @@ use std::{
 
 use texture_share_vk_base::{ash::vk, vk_device::VkDevice, vk_instance::VkInstance};
 use texture_share_vk_base::{
-	ipc::platform::img_data::ImgFormat, vk_setup::VkSetup, vk_shared_image::VkSharedImage,
+	ipc::platform::daemon_launch::ReconnectPolicy,
+	ipc::platform::img_data::{ImgEvent, ImgEventKind, ImgFormat, ImgListEntry, IMG_NAME_MAX_LEN},
+	ipc::platform::ipc_commands::DEFAULT_NAMESPACE,
+	ipc::IpcShmem,
+	vk_setup::VkSetup,
+	vk_shared_image::VkSharedImage,
 };
 use texture_share_vk_client::VkClient;
 use texture_share_vk_server::VkServer;
@@ -35,6 +40,10 @@ fn _server_create() -> VkServer {
 }
 
 fn _client_create() -> VkClient {
+	_client_create_in_namespace(DEFAULT_NAMESPACE)
+}
+
+fn _client_create_in_namespace(namespace: &str) -> VkClient {
 	let vk_instance = VkInstance::new(
 		None,
 		CStr::from_bytes_with_nul(b"VkClient\0").unwrap(),
@@ -44,10 +53,24 @@ fn _client_create() -> VkClient {
 	let vk_device = VkDevice::new(&vk_instance, None).unwrap();
 	let vk_setup = Box::new(VkSetup::new(vk_instance, vk_device));
 
-	VkClient::new(SOCKET_PATH, vk_setup, SOCKET_TIMEOUT)
+	VkClient::new_with_namespace(SOCKET_PATH, namespace, vk_setup, SOCKET_TIMEOUT)
 		.expect("Client failed to connect to server")
 }
 
+fn _wait_for_events(client: &mut VkClient, count: usize) -> Vec<ImgEvent> {
+	let mut events = Vec::new();
+	for _ in 0..100 {
+		events.append(&mut client.poll_events().unwrap());
+		if events.len() >= count {
+			break;
+		}
+
+		thread::sleep(Duration::from_millis(20));
+	}
+
+	events
+}
+
 #[test]
 fn server_client_connect() {
 	let _ = fs::remove_file(SOCKET_PATH);
@@ -241,6 +264,151 @@ fn server_client_find_image() {
 	server_thread.join().unwrap();
 }
 
+#[test]
+fn server_client_list_images_matching() {
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const IMAGE_NAMES: [&str; 3] = [
+		"studio/cam1/color",
+		"studio/cam1/depth",
+		"studio/cam2/color",
+	];
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+
+	let stop_clone = stop_bit.clone();
+	let server_fcn = move || {
+		let server = _server_create();
+		server.loop_server(stop_clone).expect("Server loop failed")
+	};
+
+	let client_fcn = move || {
+		let mut client = _client_create();
+		println!("Connection successful");
+
+		for image_name in IMAGE_NAMES.iter() {
+			let res = client
+				.init_image(image_name, 1, 1, ImgFormat::R8G8B8A8, false)
+				.unwrap();
+			assert!(res.is_some());
+		}
+		println!("Images created");
+
+		let list_names = |res: Vec<ImgListEntry>| {
+			res.iter()
+				.map(|x| x.name.as_str().to_string())
+				.collect::<Vec<_>>()
+		};
+
+		let res = client
+			.list_images_matching("studio/cam1/*")
+			.unwrap()
+			.unwrap();
+		assert_eq!(list_names(res), IMAGE_NAMES[0..2]);
+
+		let res = client
+			.list_images_matching("studio/*/color")
+			.unwrap()
+			.unwrap();
+		assert_eq!(list_names(res), [IMAGE_NAMES[0], IMAGE_NAMES[2]]);
+
+		let res = client
+			.list_images_matching("studio/cam3/*")
+			.unwrap()
+			.unwrap();
+		assert!(res.is_empty());
+		println!("Images listed by pattern");
+
+		// Empty patterns are rejected before they are sent
+		assert!(client.list_images_matching("").is_err());
+	};
+
+	let server_thread = thread::spawn(server_fcn);
+	let client_thread = thread::spawn(client_fcn);
+
+	thread::sleep(Duration::from_secs(2));
+	loop {
+		stop_bit.clone().store(true, Ordering::Relaxed);
+
+		if server_thread.is_finished() && client_thread.is_finished() {
+			break;
+		}
+	}
+
+	client_thread.join().unwrap();
+	server_thread.join().unwrap();
+}
+
+#[test]
+fn server_client_find_images() {
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const IMAGE_NAMES: [&str; 2] = ["test_img_a", "test_img_b"];
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+
+	let stop_clone = stop_bit.clone();
+	let server_fcn = move || {
+		let server = _server_create();
+		server.loop_server(stop_clone).expect("Server loop failed")
+	};
+
+	let client_fcn = move || {
+		let mut producer = _client_create();
+		let mut consumer = _client_create();
+		println!("Connections successful");
+
+		for image_name in IMAGE_NAMES.iter() {
+			let res = producer
+				.init_image(image_name, 1, 1, ImgFormat::R8G8B8A8, false)
+				.unwrap();
+			assert!(res.is_some());
+		}
+		println!("Images created");
+
+		// Missing and repeated names are resolved in the same request
+		let image_names = [
+			IMAGE_NAMES[0],
+			"test_img_missing",
+			IMAGE_NAMES[1],
+			IMAGE_NAMES[0],
+		];
+		let res = consumer.find_images(&image_names, false).unwrap().unwrap();
+		assert_eq!(res.len(), image_names.len());
+		assert!(res[0].is_some());
+		assert!(res[1].is_none());
+		assert!(res[2].is_some());
+		assert!(res[3].is_some());
+		println!("Images found from import");
+
+		let res = consumer.find_image(IMAGE_NAMES[1], false).unwrap();
+		assert!(res.is_some());
+		println!("Image found in local cache");
+
+		let res = consumer.find_images(&IMAGE_NAMES, true).unwrap().unwrap();
+		assert!(res.iter().all(|x| x.is_some()));
+		println!("Images found from forced import");
+
+		// Invalid names fail the whole lookup
+		assert!(consumer.find_images(&[IMAGE_NAMES[0], ""], true).is_err());
+	};
+
+	let server_thread = thread::spawn(server_fcn);
+	let client_thread = thread::spawn(client_fcn);
+
+	thread::sleep(Duration::from_secs(2));
+	loop {
+		stop_bit.clone().store(true, Ordering::Relaxed);
+
+		if server_thread.is_finished() && client_thread.is_finished() {
+			break;
+		}
+	}
+
+	client_thread.join().unwrap();
+	server_thread.join().unwrap();
+}
+
 #[test]
 fn server_client_find_image_data() {
 	let _ = fs::remove_file(SOCKET_PATH);
@@ -305,6 +473,11 @@ fn server_client_find_image_data() {
 				res.unwrap().1.handle_id,
 				"Handle was not updated between rewrite"
 			);
+
+			let data = client.read_image_data(IMAGE_NAME, false).unwrap().unwrap();
+			assert_eq!(data.width, width + 1);
+			assert_eq!(data.height, height + 1);
+			println!("Image data read without lock");
 		}
 	};
 
@@ -325,10 +498,11 @@ fn server_client_find_image_data() {
 }
 
 #[test]
-fn server_client_send_image() {
+fn server_client_rename_image() {
 	let _ = fs::remove_file(SOCKET_PATH);
 
 	const IMAGE_NAME: &str = "test_img";
+	const NEW_IMAGE_NAME: &str = "test_img_renamed";
 
 	let stop_bit = Arc::new(AtomicBool::new(false));
 
@@ -342,48 +516,797 @@ fn server_client_send_image() {
 		let mut client = _client_create();
 		println!("Connection successful");
 
+		let res = client.rename_image(IMAGE_NAME, NEW_IMAGE_NAME).unwrap();
+		assert!(res.is_none());
+		println!("Image not renamed, as expected");
+
 		let res = client
 			.init_image(IMAGE_NAME, 1, 1, ImgFormat::R8G8B8A8, false)
 			.unwrap();
 		assert!(res.is_some());
 		println!("Image created");
 
-		let local_image = VkSharedImage::new(
-			&client.get_vk_setup().instance,
-			&client.get_vk_setup().device,
-			1,
-			1,
-			vk::Format::R8G8B8A8_UNORM,
-			0,
-		)
-		.unwrap();
+		let res = client.rename_image(IMAGE_NAME, NEW_IMAGE_NAME).unwrap();
+		assert!(res.is_some());
+		println!("Image renamed");
 
-		let fence = client.get_vk_setup().device.create_fence(None).unwrap();
-		let res = client
-			.send_image(
-				IMAGE_NAME,
-				local_image.image,
-				local_image.image_layout,
-				local_image.image_layout,
-				fence,
-			)
+		let res = client.find_image(IMAGE_NAME, true).unwrap();
+		assert!(res.is_none());
+		println!("Old image name not found, as expected");
+
+		let res = client.find_image(NEW_IMAGE_NAME, true).unwrap();
+		assert!(res.is_some());
+		println!("Image found under new name");
+	};
+
+	let server_thread = thread::spawn(server_fcn);
+	let client_thread = thread::spawn(client_fcn);
+
+	thread::sleep(Duration::from_secs(2));
+	loop {
+		stop_bit.clone().store(true, Ordering::Relaxed);
+
+		if server_thread.is_finished() && client_thread.is_finished() {
+			break;
+		}
+	}
+
+	client_thread.join().unwrap();
+	server_thread.join().unwrap();
+}
+
+#[test]
+fn server_client_list_images() {
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const IMAGE_NAMES: [&str; 2] = ["test_img_a", "test_img_b"];
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+
+	let stop_clone = stop_bit.clone();
+	let server_fcn = move || {
+		let server = _server_create();
+		server.loop_server(stop_clone).expect("Server loop failed")
+	};
+
+	let client_fcn = move || {
+		let mut client = _client_create();
+		println!("Connection successful");
+
+		let res = client.list_images().unwrap();
+		assert_eq!(res.unwrap().len(), 0);
+		println!("No images listed, as expected");
+
+		// Lookups of missing images must not show up in the list
+		let res = client.find_image("test_img_missing", false).unwrap();
+		assert!(res.is_none());
+
+		for (i, image_name) in IMAGE_NAMES.iter().enumerate() {
+			let res = client
+				.init_image(image_name, 1, i as u32 + 1, ImgFormat::R8G8B8A8, false)
+				.unwrap();
+			assert!(res.is_some());
+		}
+		println!("Images created");
+
+		// Invalid names are rejected before they are sent
+		let long_name = "a".repeat(IMG_NAME_MAX_LEN + 1);
+		for image_name in ["", "test\0img", long_name.as_str()] {
+			let res = client.init_image(image_name, 1, 1, ImgFormat::R8G8B8A8, false);
+			assert!(res.is_err());
+		}
+
+		let res = client.list_images().unwrap().unwrap();
+		assert_eq!(res.len(), IMAGE_NAMES.len());
+		for (i, entry) in res.iter().enumerate() {
+			assert_eq!(entry.name.as_str(), IMAGE_NAMES[i]);
+			assert_eq!(entry.width, 1);
+			assert_eq!(entry.height, i as u32 + 1);
+			assert_eq!(entry.format, ImgFormat::R8G8B8A8);
+			assert_eq!(entry.get_gpu_device_uuids().len(), 1);
+		}
+		println!("Images listed");
+	};
+
+	let server_thread = thread::spawn(server_fcn);
+	let client_thread = thread::spawn(client_fcn);
+
+	thread::sleep(Duration::from_secs(2));
+	loop {
+		stop_bit.clone().store(true, Ordering::Relaxed);
+
+		if server_thread.is_finished() && client_thread.is_finished() {
+			break;
+		}
+	}
+
+	client_thread.join().unwrap();
+	server_thread.join().unwrap();
+}
+
+#[test]
+fn server_client_stats() {
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const IMAGE_NAME: &str = "test_img";
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+
+	let stop_clone = stop_bit.clone();
+	let server_fcn = move || {
+		let server = _server_create();
+		server.loop_server(stop_clone).expect("Server loop failed")
+	};
+
+	let client_fcn = move || {
+		let mut producer = _client_create();
+		let mut consumer = _client_create();
+		println!("Connection successful");
+
+		let stats = producer.get_stats().unwrap().unwrap();
+		assert_eq!(stats.connection_count, 2);
+		assert_eq!(stats.images.len(), 0);
+		println!("No images reported, as expected");
+
+		let res = producer
+			.init_image(IMAGE_NAME, 1, 2, ImgFormat::R8G8B8A8, false)
+			.unwrap();
+		assert!(res.is_some());
+		let res = consumer.find_image(IMAGE_NAME, false).unwrap();
+		assert!(res.is_some());
+		println!("Image created and imported");
+
+		let stats = consumer.get_stats().unwrap().unwrap();
+		assert_eq!(stats.images.len(), 1);
+		let entry = &stats.images[0];
+		assert_eq!(entry.image.name.as_str(), IMAGE_NAME);
+		assert_eq!(entry.image.height, 2);
+		assert_eq!(entry.image.format, ImgFormat::R8G8B8A8);
+		assert!(entry.allocation_size > 0);
+		assert_eq!(entry.producer_count, 1);
+		assert_eq!(entry.consumer_count, 1);
+		assert!(entry.get_last_update().is_some());
+
+		let device = stats
+			.devices
+			.iter()
+			.find(|x| x.get_gpu_device_uuid() == entry.image.get_gpu_device_uuids()[0])
 			.unwrap();
+		assert_eq!(device.image_count, 1);
+		assert!(device.memory_usage >= entry.allocation_size);
+		println!("Image and device stats received");
+	};
 
-		assert!(res.is_some(), "Failed to send image");
-		println!("Image sent");
+	let server_thread = thread::spawn(server_fcn);
+	let client_thread = thread::spawn(client_fcn);
 
-		let res = client
-			.recv_image(
-				IMAGE_NAME,
-				local_image.image,
-				local_image.image_layout,
-				local_image.image_layout,
-				fence,
-			)
+	thread::sleep(Duration::from_secs(2));
+	loop {
+		stop_bit.clone().store(true, Ordering::Relaxed);
+
+		if server_thread.is_finished() && client_thread.is_finished() {
+			break;
+		}
+	}
+
+	client_thread.join().unwrap();
+	server_thread.join().unwrap();
+}
+
+#[test]
+fn server_client_image_metadata() {
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const IMAGE_NAME: &str = "test_img";
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+
+	let stop_clone = stop_bit.clone();
+	let server_fcn = move || {
+		let server = _server_create();
+		server.loop_server(stop_clone).expect("Server loop failed")
+	};
+
+	let client_fcn = move || {
+		let mut producer = _client_create();
+		let mut consumer = _client_create();
+		println!("Connection successful");
+
+		let res = producer
+			.set_image_metadata(IMAGE_NAME, "label", Some("test"))
 			.unwrap();
+		assert!(res.is_none());
+		assert!(consumer.get_image_metadata(IMAGE_NAME).unwrap().is_none());
+		println!("Metadata of missing image rejected, as expected");
 
-		assert!(res.is_some(), "Failed to receive image");
-		println!("Image received");
+		let res = producer
+			.init_image(IMAGE_NAME, 1, 2, ImgFormat::R8G8B8A8, false)
+			.unwrap();
+		assert!(res.is_some());
+		let res = consumer.subscribe(IMAGE_NAME, true).unwrap();
+		assert!(res.is_some());
+
+		let metadata = consumer.get_image_metadata(IMAGE_NAME).unwrap().unwrap();
+		assert!(metadata.is_empty());
+
+		producer
+			.set_image_metadata(IMAGE_NAME, "color_space", Some("srgb"))
+			.unwrap()
+			.unwrap();
+		producer
+			.set_image_metadata(IMAGE_NAME, "label", Some("test"))
+			.unwrap()
+			.unwrap();
+		producer
+			.set_image_metadata(IMAGE_NAME, "label", Some("renamed"))
+			.unwrap()
+			.unwrap();
+		println!("Metadata set");
+
+		let events = _wait_for_events(&mut consumer, 3);
+		assert_eq!(events.len(), 3);
+		assert!(events
+			.iter()
+			.all(|x| x.kind == ImgEventKind::MetadataChanged));
+
+		let metadata = consumer.get_image_metadata(IMAGE_NAME).unwrap().unwrap();
+		assert_eq!(metadata.len(), 2);
+		assert_eq!(metadata[0].get_key(), "color_space");
+		assert_eq!(metadata[0].get_value(), "srgb");
+		assert_eq!(metadata[1].get_key(), "label");
+		assert_eq!(metadata[1].get_value(), "renamed");
+		println!("Metadata received from server");
+
+		{
+			let (lock, _data) = consumer
+				.find_image_data(IMAGE_NAME, false)
+				.unwrap()
+				.unwrap();
+			assert!(IpcShmem::acquire_rmetadata(&lock).unwrap() == metadata);
+			println!("Metadata read from shared memory");
+		}
+
+		producer
+			.set_image_metadata(IMAGE_NAME, "label", None)
+			.unwrap()
+			.unwrap();
+		let metadata = consumer.get_image_metadata(IMAGE_NAME).unwrap().unwrap();
+		assert_eq!(metadata.len(), 1);
+		println!("Metadata entry removed");
+
+		// Eight entries with the longest values exceed the space in the shmem segment
+		let value = "a".repeat(IMG_NAME_MAX_LEN);
+		let res = (0..8)
+			.map(|i| producer.set_image_metadata(IMAGE_NAME, &format!("large_{}", i), Some(&value)))
+			.collect::<Vec<_>>();
+		assert!(res.iter().any(|x| x.is_err()));
+		let metadata = consumer.get_image_metadata(IMAGE_NAME).unwrap().unwrap();
+		assert!(metadata.len() < 9);
+		println!("Too large metadata rejected, as expected");
+	};
+
+	let server_thread = thread::spawn(server_fcn);
+	let client_thread = thread::spawn(client_fcn);
+
+	thread::sleep(Duration::from_secs(2));
+	loop {
+		stop_bit.clone().store(true, Ordering::Relaxed);
+
+		if server_thread.is_finished() && client_thread.is_finished() {
+			break;
+		}
+	}
+
+	client_thread.join().unwrap();
+	server_thread.join().unwrap();
+}
+
+#[test]
+fn server_client_release_image() {
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const IMAGE_NAME: &str = "test_img";
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+
+	let stop_clone = stop_bit.clone();
+	let server_fcn = move || {
+		let server = _server_create();
+		server.loop_server(stop_clone).expect("Server loop failed")
+	};
+
+	let client_fcn = move || {
+		let mut producer = _client_create();
+		let mut consumer = _client_create();
+		println!("Connection successful");
+
+		let res = producer.release_image(IMAGE_NAME).unwrap();
+		assert!(res.is_none());
+		println!("Image not released, as expected");
+
+		let res = producer
+			.init_image(IMAGE_NAME, 1, 1, ImgFormat::R8G8B8A8, false)
+			.unwrap();
+		assert!(res.is_some());
+		println!("Image created");
+
+		let res = consumer.find_image(IMAGE_NAME, false).unwrap();
+		assert!(res.is_some());
+		println!("Image found");
+
+		let res = producer.release_image(IMAGE_NAME).unwrap();
+		assert_eq!(res, Some(false));
+		println!("Image released, consumer still holds a reference");
+
+		let res = consumer.find_image(IMAGE_NAME, true).unwrap();
+		assert!(res.is_some());
+		println!("Image still available");
+
+		let res = consumer.release_image(IMAGE_NAME).unwrap();
+		assert_eq!(res, Some(true));
+		println!("Image released and destroyed");
+
+		let res = consumer.find_image(IMAGE_NAME, true).unwrap();
+		assert!(res.is_none());
+		println!("Image not found, as expected");
+
+		let res = producer
+			.init_image(IMAGE_NAME, 1, 1, ImgFormat::R8G8B8A8, false)
+			.unwrap();
+		assert!(res.is_some());
+		println!("Image recreated");
+
+		let res = consumer.delete_image(IMAGE_NAME).unwrap();
+		assert!(res.is_some());
+		println!("Image deleted");
+
+		let res = producer.find_image(IMAGE_NAME, true).unwrap();
+		assert!(res.is_none());
+		println!("Image not found, as expected");
+	};
+
+	let server_thread = thread::spawn(server_fcn);
+	let client_thread = thread::spawn(client_fcn);
+
+	thread::sleep(Duration::from_secs(2));
+	loop {
+		stop_bit.clone().store(true, Ordering::Relaxed);
+
+		if server_thread.is_finished() && client_thread.is_finished() {
+			break;
+		}
+	}
+
+	client_thread.join().unwrap();
+	server_thread.join().unwrap();
+}
+
+#[test]
+fn server_client_image_events() {
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const IMAGE_NAME: &str = "test_img";
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+
+	let stop_clone = stop_bit.clone();
+	let server_fcn = move || {
+		let server = _server_create();
+		server.loop_server(stop_clone).expect("Server loop failed")
+	};
+
+	let client_fcn = move || {
+		let mut producer = _client_create();
+		let mut consumer = _client_create();
+		println!("Connection successful");
+
+		let res = consumer.subscribe(IMAGE_NAME, true).unwrap();
+		assert_eq!(res, Some(false));
+		println!("Subscribed to image");
+
+		let res = producer
+			.init_image(IMAGE_NAME, 1, 2, ImgFormat::R8G8B8A8, false)
+			.unwrap();
+		assert!(res.is_some());
+		println!("Image created");
+
+		let events = _wait_for_events(&mut consumer, 1);
+		assert_eq!(events.len(), 1);
+		assert_eq!(events[0].kind, ImgEventKind::ImageResized);
+		assert_eq!(events[0].get_image_name(), IMAGE_NAME);
+		assert_eq!(events[0].data.height, 2);
+		println!("Received resize event");
+
+		drop(producer);
+
+		let events = _wait_for_events(&mut consumer, 2);
+		assert_eq!(events.len(), 2);
+		assert_eq!(events[0].kind, ImgEventKind::ProducerDisconnected);
+		assert_eq!(events[1].kind, ImgEventKind::ImageRemoved);
+		println!("Received disconnect and removal events");
+
+		let res = consumer.subscribe(IMAGE_NAME, false).unwrap();
+		assert_eq!(res, Some(false));
+		println!("Unsubscribed from image");
+	};
+
+	let server_thread = thread::spawn(server_fcn);
+	let client_thread = thread::spawn(client_fcn);
+
+	thread::sleep(Duration::from_secs(2));
+	loop {
+		stop_bit.clone().store(true, Ordering::Relaxed);
+
+		if server_thread.is_finished() && client_thread.is_finished() {
+			break;
+		}
+	}
+
+	client_thread.join().unwrap();
+	server_thread.join().unwrap();
+}
+
+#[test]
+fn server_client_rename_subscribed_image() {
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const IMAGE_NAME: &str = "test_img";
+	const NEW_IMAGE_NAME: &str = "test_img_renamed";
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+
+	let stop_clone = stop_bit.clone();
+	let server_fcn = move || {
+		let server = _server_create();
+		server.loop_server(stop_clone).expect("Server loop failed")
+	};
+
+	let client_fcn = move || {
+		let mut producer = _client_create();
+		let mut consumer = _client_create();
+		println!("Connection successful");
+
+		let res = consumer.subscribe(IMAGE_NAME, true).unwrap();
+		assert_eq!(res, Some(false));
+		println!("Subscribed to image");
+
+		let res = producer
+			.init_image(IMAGE_NAME, 1, 2, ImgFormat::R8G8B8A8, false)
+			.unwrap();
+		assert!(res.is_some());
+		let events = _wait_for_events(&mut consumer, 1);
+		assert_eq!(events.len(), 1);
+		assert_eq!(events[0].get_image_name(), IMAGE_NAME);
+		println!("Image created");
+
+		let res = producer.rename_image(IMAGE_NAME, NEW_IMAGE_NAME).unwrap();
+		assert!(res.is_some());
+		let events = _wait_for_events(&mut consumer, 1);
+		assert_eq!(events.len(), 1);
+		assert_eq!(events[0].kind, ImgEventKind::ImageResized);
+		assert_eq!(events[0].get_image_name(), NEW_IMAGE_NAME);
+		assert_eq!(events[0].data.height, 2);
+		println!("Received event for new image name");
+
+		drop(producer);
+
+		let events = _wait_for_events(&mut consumer, 2);
+		assert_eq!(events.len(), 2);
+		assert_eq!(events[0].kind, ImgEventKind::ProducerDisconnected);
+		assert_eq!(events[1].kind, ImgEventKind::ImageRemoved);
+		assert_eq!(events[1].get_image_name(), NEW_IMAGE_NAME);
+		println!("Subscription followed the renamed image");
+
+		let res = consumer.subscribe(NEW_IMAGE_NAME, false).unwrap();
+		assert_eq!(res, Some(false));
+		println!("Unsubscribed from renamed image");
+	};
+
+	let server_thread = thread::spawn(server_fcn);
+	let client_thread = thread::spawn(client_fcn);
+
+	thread::sleep(Duration::from_secs(2));
+	loop {
+		stop_bit.clone().store(true, Ordering::Relaxed);
+
+		if server_thread.is_finished() && client_thread.is_finished() {
+			break;
+		}
+	}
+
+	client_thread.join().unwrap();
+	server_thread.join().unwrap();
+}
+
+#[test]
+fn server_client_heartbeat() {
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+
+	let stop_clone = stop_bit.clone();
+	let server_fcn = move || {
+		let mut server = _server_create();
+		server.set_heartbeat_interval(HEARTBEAT_INTERVAL);
+		server.loop_server(stop_clone).expect("Server loop failed")
+	};
+
+	let client_fcn = move || {
+		let mut client = _client_create();
+		println!("Connection successful");
+
+		client.ping().unwrap();
+		println!("Received pong");
+
+		client
+			.set_heartbeat_interval(Some(HEARTBEAT_INTERVAL))
+			.unwrap();
+		for _ in 0..10 {
+			thread::sleep(HEARTBEAT_INTERVAL / 2);
+			client.heartbeat().unwrap();
+		}
+		println!("Connection kept alive by heartbeats");
+
+		// Stop sending heartbeats. Server should close the connection
+		thread::sleep(HEARTBEAT_INTERVAL * 6);
+		let err = client.ping().unwrap_err();
+		assert!(err.is_disconnect());
+		println!("Connection closed after missed heartbeats");
+	};
+
+	let server_thread = thread::spawn(server_fcn);
+	let client_thread = thread::spawn(client_fcn);
+
+	thread::sleep(Duration::from_secs(2));
+	loop {
+		stop_bit.clone().store(true, Ordering::Relaxed);
+
+		if server_thread.is_finished() && client_thread.is_finished() {
+			break;
+		}
+	}
+
+	client_thread.join().unwrap();
+	server_thread.join().unwrap();
+}
+
+#[test]
+fn server_client_reconnect() {
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const IMAGE_NAME: &str = "test_img";
+
+	let server_fcn = |stop_bit: Arc<AtomicBool>| {
+		move || {
+			let server = _server_create();
+			server.loop_server(stop_bit).expect("Server loop failed")
+		}
+	};
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+	let server_thread = thread::spawn(server_fcn(stop_bit.clone()));
+
+	let mut producer = _client_create();
+	producer.set_reconnect_policy(Some(ReconnectPolicy::new(20, Duration::from_millis(100))));
+	println!("Connection successful");
+
+	let res = producer
+		.init_image(IMAGE_NAME, 1, 2, ImgFormat::R8G8B8A8, false)
+		.unwrap();
+	assert!(res.is_some());
+	let res = producer.subscribe(IMAGE_NAME, true).unwrap();
+	assert_eq!(res, Some(true));
+	let (rlock, rdata) = producer
+		.find_image_data(IMAGE_NAME, false)
+		.unwrap()
+		.unwrap();
+	let server_instance_id = rdata.get_server_instance_id();
+	drop(rlock);
+	println!("Image created");
+
+	// Restart the server, which drops all images
+	stop_bit.store(true, Ordering::Relaxed);
+	server_thread.join().unwrap();
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+	let server_thread = thread::spawn(server_fcn(stop_bit.clone()));
+	println!("Server restarted");
+
+	// The next command reconnects and initializes the image again
+	let res = producer.list_images().unwrap().unwrap();
+	assert_eq!(res.len(), 1);
+	assert_eq!(res[0].name.as_str(), IMAGE_NAME);
+	assert_eq!(res[0].height, 2);
+	let (rlock, rdata) = producer
+		.find_image_data(IMAGE_NAME, false)
+		.unwrap()
+		.unwrap();
+	assert_ne!(rdata.get_server_instance_id(), server_instance_id);
+	drop(rlock);
+	println!("Image restored after reconnecting");
+
+	let mut consumer = _client_create();
+	let res = consumer.find_image(IMAGE_NAME, false).unwrap();
+	assert!(res.is_some());
+	println!("Restored image found by other client");
+
+	// The subscription was restored as well
+	let res = consumer
+		.init_image(IMAGE_NAME, 1, 3, ImgFormat::R8G8B8A8, true)
+		.unwrap();
+	assert!(res.is_some());
+	let events = _wait_for_events(&mut producer, 1);
+	assert_eq!(events.len(), 1);
+	assert_eq!(events[0].kind, ImgEventKind::ImageResized);
+	println!("Received resize event");
+
+	// Without a policy, the broken connection is reported
+	consumer.set_reconnect_policy(None);
+	stop_bit.store(true, Ordering::Relaxed);
+	server_thread.join().unwrap();
+
+	let err = consumer.ping().unwrap_err();
+	assert!(err.is_disconnect());
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn server_client_async_find_image() {
+	use texture_share_vk_client::AsyncVkClient;
+
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const IMAGE_NAME: &str = "test_img";
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+
+	let stop_clone = stop_bit.clone();
+	let server_fcn = move || {
+		let server = _server_create();
+		server.loop_server(stop_clone).expect("Server loop failed")
+	};
+
+	let client_fcn = move || {
+		let vk_instance = VkInstance::new(
+			None,
+			CStr::from_bytes_with_nul(b"AsyncVkClient\0").unwrap(),
+			true,
+		)
+		.unwrap();
+		let vk_device = VkDevice::new(&vk_instance, None).unwrap();
+		let vk_setup = Box::new(VkSetup::new(vk_instance, vk_device));
+
+		let runtime = tokio::runtime::Builder::new_current_thread()
+			.enable_all()
+			.build()
+			.unwrap();
+		runtime.block_on(async {
+			let mut client = AsyncVkClient::new(SOCKET_PATH, vk_setup, SOCKET_TIMEOUT)
+				.await
+				.expect("Client failed to connect to server");
+			println!("Connection successful");
+
+			let res = client.find_image(IMAGE_NAME, false).await.unwrap();
+			assert!(res.is_none());
+			println!("Image not found, as expected");
+
+			let res = client
+				.init_image(IMAGE_NAME, 1, 1, ImgFormat::R8G8B8A8, false)
+				.await
+				.unwrap();
+			assert!(res.is_some());
+			println!("Image created");
+
+			let res = client.find_image(IMAGE_NAME, true).await.unwrap();
+			assert!(res.is_some());
+			println!("Image found from forced import");
+
+			let (_rlock, rdata) = client
+				.find_image_data(IMAGE_NAME, false)
+				.await
+				.unwrap()
+				.unwrap();
+			assert_eq!(rdata.width, 1);
+			assert_eq!(rdata.height, 1);
+		});
+	};
+
+	let server_thread = thread::spawn(server_fcn);
+	let client_thread = thread::spawn(client_fcn);
+
+	thread::sleep(Duration::from_secs(2));
+	loop {
+		stop_bit.clone().store(true, Ordering::Relaxed);
+
+		if server_thread.is_finished() && client_thread.is_finished() {
+			break;
+		}
+	}
+
+	client_thread.join().unwrap();
+	server_thread.join().unwrap();
+}
+
+#[test]
+fn server_client_send_image() {
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const IMAGE_NAME: &str = "test_img";
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+
+	let stop_clone = stop_bit.clone();
+	let server_fcn = move || {
+		let server = _server_create();
+		server.loop_server(stop_clone).expect("Server loop failed")
+	};
+
+	let client_fcn = move || {
+		let mut client = _client_create();
+		println!("Connection successful");
+
+		let res = client
+			.init_image(IMAGE_NAME, 1, 1, ImgFormat::R8G8B8A8, false)
+			.unwrap();
+		assert!(res.is_some());
+		println!("Image created");
+
+		let local_image = VkSharedImage::new(
+			&client.get_vk_setup().instance,
+			&client.get_vk_setup().device,
+			1,
+			1,
+			vk::Format::R8G8B8A8_UNORM,
+			0,
+		)
+		.unwrap();
+
+		let fence = client.get_vk_setup().device.create_fence(None).unwrap();
+		let frame_info = client.get_frame_info(IMAGE_NAME).unwrap().unwrap();
+		assert_eq!(frame_info.frame_number, 0);
+
+		let res = client
+			.send_image(
+				IMAGE_NAME,
+				local_image.image,
+				local_image.image_layout,
+				local_image.image_layout,
+				fence,
+			)
+			.unwrap();
+
+		assert!(res.is_some(), "Failed to send image");
+		println!("Image sent");
+
+		let res = client
+			.recv_image(
+				IMAGE_NAME,
+				local_image.image,
+				local_image.image_layout,
+				local_image.image_layout,
+				fence,
+			)
+			.unwrap();
+
+		assert!(res.is_some(), "Failed to receive image");
+		println!("Image received");
+
+		let frame_info = client
+			.recv_image_frame(
+				IMAGE_NAME,
+				local_image.image,
+				local_image.image_layout,
+				local_image.image_layout,
+				fence,
+			)
+			.unwrap()
+			.expect("Failed to receive frame");
+		assert_eq!(frame_info.frame_number, 1);
+		assert!(frame_info.get_age() < Duration::from_secs(10));
+		assert!(client.get_frame_info(IMAGE_NAME).unwrap() == Some(frame_info));
+		println!("Frame number received");
 
 		client.get_vk_setup().device.destroy_fence(fence);
 		local_image.destroy(&client.get_vk_setup().device);
@@ -404,3 +1327,235 @@ fn server_client_send_image() {
 	client_thread.join().unwrap();
 	server_thread.join().unwrap();
 }
+
+#[test]
+fn server_client_send_buffered_image() {
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const IMAGE_NAME: &str = "test_img";
+	const BUFFER_COUNT: u32 = 3;
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+
+	let stop_clone = stop_bit.clone();
+	let server_fcn = move || {
+		let server = _server_create();
+		server.loop_server(stop_clone).expect("Server loop failed")
+	};
+
+	let client_fcn = move || {
+		let mut client = _client_create();
+		println!("Connection successful");
+
+		let res = client
+			.init_image_buffered(IMAGE_NAME, 1, 1, ImgFormat::R8G8B8A8, false, BUFFER_COUNT)
+			.unwrap();
+		assert!(res.is_some());
+
+		let data = client.read_image_data(IMAGE_NAME, false).unwrap().unwrap();
+		assert_eq!(data.buffer_count, BUFFER_COUNT);
+		assert_eq!(data.latest_buffer, 0);
+		println!("Buffered image created");
+
+		let local_image = VkSharedImage::new(
+			&client.get_vk_setup().instance,
+			&client.get_vk_setup().device,
+			1,
+			1,
+			vk::Format::R8G8B8A8_UNORM,
+			0,
+		)
+		.unwrap();
+		let fence = client.get_vk_setup().device.create_fence(None).unwrap();
+
+		// Every frame is written to the buffer after the latest one
+		for frame_number in 1..=BUFFER_COUNT as u64 + 1 {
+			let res = client
+				.send_image(
+					IMAGE_NAME,
+					local_image.image,
+					local_image.image_layout,
+					local_image.image_layout,
+					fence,
+				)
+				.unwrap();
+			assert!(res.is_some(), "Failed to send image");
+
+			let data = client.read_image_data(IMAGE_NAME, false).unwrap().unwrap();
+			assert_eq!(data.latest_buffer, frame_number as u32 % BUFFER_COUNT);
+			assert_eq!(data.frame_number, frame_number);
+		}
+		println!("Frames sent");
+
+		let frame_info = client
+			.recv_image_frame(
+				IMAGE_NAME,
+				local_image.image,
+				local_image.image_layout,
+				local_image.image_layout,
+				fence,
+			)
+			.unwrap()
+			.expect("Failed to receive frame");
+		assert_eq!(frame_info.frame_number, BUFFER_COUNT as u64 + 1);
+		println!("Latest frame received");
+
+		// All buffers are imported again with the image
+		let data = client.read_image_data(IMAGE_NAME, true).unwrap().unwrap();
+		assert_eq!(data.buffer_count, BUFFER_COUNT);
+		let res = client
+			.recv_image(
+				IMAGE_NAME,
+				local_image.image,
+				local_image.image_layout,
+				local_image.image_layout,
+				fence,
+			)
+			.unwrap();
+		assert!(res.is_some(), "Failed to receive image");
+
+		client.get_vk_setup().device.destroy_fence(fence);
+		local_image.destroy(&client.get_vk_setup().device);
+	};
+
+	let server_thread = thread::spawn(server_fcn);
+	let client_thread = thread::spawn(client_fcn);
+
+	thread::sleep(Duration::from_secs(2));
+	loop {
+		stop_bit.clone().store(true, Ordering::Relaxed);
+
+		if server_thread.is_finished() && client_thread.is_finished() {
+			break;
+		}
+	}
+
+	client_thread.join().unwrap();
+	server_thread.join().unwrap();
+}
+
+#[test]
+fn server_client_wait_for_image() {
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const IMAGE_NAME: &str = "test_img_wait";
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+
+	let stop_clone = stop_bit.clone();
+	let server_fcn = move || {
+		let server = _server_create();
+		server.loop_server(stop_clone).expect("Server loop failed")
+	};
+
+	let client_fcn = move || {
+		let mut producer = _client_create();
+		println!("Producer connected");
+
+		// The consumer blocks until the producer initializes the image
+		let consumer_thread = thread::spawn(|| {
+			let mut consumer = _client_create();
+			let res = consumer
+				.wait_for_image(IMAGE_NAME, Duration::from_millis(1000), false)
+				.unwrap();
+			assert!(res.is_some());
+			println!("Image found after wait");
+
+			// Images that don't appear in time aren't found
+			let res = consumer
+				.wait_for_image("test_img_missing", Duration::from_millis(100), false)
+				.unwrap();
+			assert!(res.is_none());
+			println!("Wait for missing image timed out");
+		});
+
+		thread::sleep(Duration::from_millis(300));
+		let res = producer
+			.init_image(IMAGE_NAME, 1, 1, ImgFormat::R8G8B8A8, false)
+			.unwrap();
+		assert!(res.is_some());
+		println!("Image created");
+
+		consumer_thread.join().unwrap();
+	};
+
+	let server_thread = thread::spawn(server_fcn);
+	let client_thread = thread::spawn(client_fcn);
+
+	thread::sleep(Duration::from_secs(2));
+	loop {
+		stop_bit.clone().store(true, Ordering::Relaxed);
+
+		if server_thread.is_finished() && client_thread.is_finished() {
+			break;
+		}
+	}
+
+	client_thread.join().unwrap();
+	server_thread.join().unwrap();
+}
+
+#[test]
+fn server_client_namespaces() {
+	let _ = fs::remove_file(SOCKET_PATH);
+
+	const IMAGE_NAME: &str = "output";
+
+	let stop_bit = Arc::new(AtomicBool::new(false));
+
+	let stop_clone = stop_bit.clone();
+	let server_fcn = move || {
+		let server = _server_create();
+		server.loop_server(stop_clone).expect("Server loop failed")
+	};
+
+	let client_fcn = move || {
+		let mut producer_a = _client_create_in_namespace("app_a");
+		let mut producer_b = _client_create_in_namespace("app_b");
+		let mut consumer_a = _client_create_in_namespace("app_a");
+		let mut default_client = _client_create();
+		assert_eq!(consumer_a.get_namespace(), "app_a");
+		assert_eq!(default_client.get_namespace(), DEFAULT_NAMESPACE);
+		println!("Connections successful");
+
+		// Both apps use the same name without overwriting each other's image
+		let res = producer_a
+			.init_image(IMAGE_NAME, 1, 1, ImgFormat::R8G8B8A8, false)
+			.unwrap();
+		assert!(res.is_some());
+		let res = producer_b
+			.init_image(IMAGE_NAME, 2, 2, ImgFormat::R8G8B8A8, false)
+			.unwrap();
+		assert!(res.is_some());
+		println!("Images created");
+
+		let res = consumer_a.find_image(IMAGE_NAME, false).unwrap();
+		assert!(res.is_some());
+		let res = consumer_a.list_images().unwrap().unwrap();
+		assert_eq!(res.len(), 1);
+		assert_eq!(res[0].width, 1);
+		println!("Image found in own namespace");
+
+		// Images of other namespaces are invisible
+		let res = default_client.find_image(IMAGE_NAME, false).unwrap();
+		assert!(res.is_none());
+		let res = default_client.list_images().unwrap().unwrap();
+		assert!(res.is_empty());
+		println!("Image not found in default namespace");
+	};
+
+	let server_thread = thread::spawn(server_fcn);
+	let client_thread = thread::spawn(client_fcn);
+
+	thread::sleep(Duration::from_secs(2));
+	loop {
+		stop_bit.clone().store(true, Ordering::Relaxed);
+
+		if server_thread.is_finished() && client_thread.is_finished() {
+			break;
+		}
+	}
+
+	client_thread.join().unwrap();
+	server_thread.join().unwrap();
+}
@@ -6,14 +6,19 @@ use std::{
 	path::Path,
 	str::FromStr,
 	sync::{atomic::AtomicBool, Arc},
-	time::{Duration, SystemTime},
+	thread,
+	time::{Duration, Instant},
 };
 
 use clap::{builder::TypedValueParser, Parser};
 use fs2::FileExt;
+use texture_share_vk_base::ipc::{Error, IpcConnection};
 use texture_share_vk_base::{uuid, vk_device::VkPhysicalDeviceOptions};
 use texture_share_vk_server::VkServer;
 
+// Delay between attempts to acquire the lock file
+const LOCKFILE_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
 #[derive(Clone)]
 struct UuidParser;
 
@@ -73,6 +78,9 @@ struct Args {
 	#[arg(long, default_value_t = 2000)]
 	lockfile_timeout_millis: u64,
 
+	#[arg(long, default_value_t = 1000)]
+	heartbeat_interval_millis: u64,
+
 	#[arg(long, required = false)]
 	gpu_vendor_id: Option<u32>,
 
@@ -84,31 +92,95 @@ struct Args {
 
 	#[arg(long, required = false, value_parser=clap::builder::ValueParser::new(UuidParser{}))]
 	gpu_device_uuid: Option<uuid::Uuid>,
+
+	// Print the state of the server listening on socket_file and exit
+	#[arg(long, default_value_t = false)]
+	stats: bool,
+}
+
+fn print_server_stats(
+	socket_path: &str,
+	timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let conn = IpcConnection::try_connect(socket_path, timeout)?
+		.ok_or_else(|| Error::Timeout(format!("Connecting to '{}'", socket_path)))?;
+	let stats = conn
+		.get_stats()?
+		.ok_or_else(|| Error::Timeout("Receiving server stats".to_string()))?;
+
+	// The connection used to request the stats is counted as well
+	println!("Connections: {}", stats.connection_count);
+	println!("GPU copies: {}", stats.gpu_copy_count);
+
+	println!("Images: {}", stats.images.len());
+	for entry in stats.images.iter() {
+		let last_update = match entry.get_last_update().and_then(|t| t.elapsed().ok()) {
+			Some(elapsed) => format!("{:.1?} ago", elapsed),
+			None => "never".to_string(),
+		};
+		println!(
+			"  '{}': {}x{} {:?}, {} bytes, {} producer(s), {} consumer(s), updated {}",
+			entry.image.name,
+			entry.image.width,
+			entry.image.height,
+			entry.image.format,
+			entry.allocation_size,
+			entry.producer_count,
+			entry.consumer_count,
+			last_update
+		);
+		for gpu_device_uuid in entry.image.get_gpu_device_uuids() {
+			println!("    GPU {}", uuid::Uuid::from_u128(gpu_device_uuid));
+		}
+	}
+
+	println!("Devices: {}", stats.devices.len());
+	for device in stats.devices.iter() {
+		println!(
+			"  GPU {}: {} image(s), {} bytes",
+			uuid::Uuid::from_u128(device.get_gpu_device_uuid()),
+			device.image_count,
+			device.memory_usage
+		);
+	}
+
+	Ok(())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
 	let args = Args::parse();
 
+	if args.stats {
+		return print_server_stats(
+			&args.socket_file,
+			Duration::from_millis(args.socket_timeout_millis),
+		);
+	}
+
 	let lock_file_path = Path::new(&args.lock_file);
 	fs::create_dir_all(&lock_file_path.parent().unwrap_or(Path::new(".")))?;
 
 	// Take ownership of lock_file
 	let lock_file = {
-		let stop_time = SystemTime::now() + Duration::from_millis(args.lockfile_timeout_millis);
+		let stop_time = Instant::now() + Duration::from_millis(args.lockfile_timeout_millis);
+		let file = OpenOptions::new()
+			.create(true)
+			.write(true)
+			.open(lock_file_path)?;
 		loop {
-			let file = OpenOptions::new()
-				.create(true)
-				.write(true)
-				.open(lock_file_path)?;
 			let lock_res = file.try_lock_exclusive();
 
 			if lock_res.is_ok() {
 				break Ok(file);
 			}
 
-			if SystemTime::now() > stop_time {
+			if Instant::now() > stop_time {
 				break Err(lock_res.err().unwrap());
 			}
+
+			// The lock can't be waited on with a timeout, retry until the deadline
+			let remaining = stop_time.saturating_duration_since(Instant::now());
+			thread::sleep(LOCKFILE_RETRY_INTERVAL.min(remaining));
 		}
 	}
 	.expect(&format!(
@@ -129,7 +201,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 		..Default::default()
 	};
 
-	let vk_server = VkServer::new(
+	let mut vk_server = VkServer::new(
 		&args.socket_file,
 		&args.shmem_prefix,
 		Duration::from_millis(args.socket_timeout_millis),
@@ -137,6 +209,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 		Duration::from_millis(args.ipc_timeout_millis),
 		Some(physical_device_properties),
 	)?;
+	vk_server.set_heartbeat_interval(Duration::from_millis(args.heartbeat_interval_millis));
 
 	vk_server.loop_server(Arc::new(AtomicBool::new(false)))?;
 
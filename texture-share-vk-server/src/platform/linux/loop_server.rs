@@ -2,7 +2,7 @@ use polling::{Event, Events, PollMode, Poller};
 use std::os::fd::{AsFd, AsRawFd};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::Instant;
 
 
 
@@ -14,7 +14,7 @@ impl VkServer {
 		stop_bit: Arc<AtomicBool>,
 	) -> Result<(), Box<dyn std::error::Error>> {
 		// Stop server if no connection was established after NO_CONNECTION_TIMEOUT
-		let mut conn_timeout = SystemTime::now() + self.connection_wait_timeout;
+		let mut conn_timeout = Instant::now() + self.connection_wait_timeout;
 
 		// Setup polling
 		let mut new_connection_waiting = false;
@@ -48,11 +48,24 @@ impl VkServer {
 					// Remove unused connections from both poller and connections vector
 					if !connections_to_close.is_empty() {
 						// Remove connections that were closed by peer
+						connections_to_close.sort();
+						connections_to_close.dedup();
 						for ci in connections_to_close.iter().rev() {
-							conn_lock.as_mut().unwrap().remove(*ci);
+							let conn = conn_lock.as_mut().unwrap().remove(*ci);
+
+							// Destroy images that are no longer used by any client
+							VkServer::release_connection_images(
+								conn.borrow().get_id(),
+								&conn.borrow().get_namespace(),
+								&self.vk_devices,
+								&mut self.namespaces,
+								self.ipc_timeout,
+							);
 						}
 
 						connections_to_close.clear();
+						VkServer::send_events(conn_lock.as_ref().unwrap(), &mut self.namespaces);
+						self.namespaces.retain(|_, x| !x.is_unused());
 					}
 				}
 
@@ -78,7 +91,13 @@ impl VkServer {
 			};
 
 			events.clear();
-			poller.wait(&mut events, Some(self.socket.timeout))?;
+			// Wake up at least once per heartbeat interval to check for stalled connections,
+			// and when the next image wait expires
+			let mut poll_timeout = self.socket.timeout.min(self.heartbeat_interval);
+			if let Some(wait_timeout) = self.get_next_image_wait_timeout() {
+				poll_timeout = poll_timeout.min(wait_timeout);
+			}
+			poller.wait(&mut events, Some(poll_timeout))?;
 
 			for ev in events.iter() {
 				if ev.key < VkServer::LISTENER_EVENT_KEY {
@@ -95,12 +114,18 @@ impl VkServer {
 							&self.vk_instance,
 							&mut self.vk_devices,
 							&self.shmem_prefix,
-							&mut self.images,
+							&mut self.namespaces,
+							&mut self.gpu_copy_count,
+							connections.len(),
+							self.socket.server_instance_id,
 							self.ipc_timeout,
 						)? {
 							connections_to_close.push(ev.key);
 						}
 
+						VkServer::send_events(connections, &mut self.namespaces);
+						VkServer::send_image_wait_results(connections, &mut self.namespaces);
+
 						poller.modify(
 							conn.borrow().get_socket().as_fd(),
 							Event::readable(ev.key).with_interrupt(),
@@ -115,14 +140,41 @@ impl VkServer {
 				}
 			}
 
+			// Answer image waits that timed out
+			VkServer::send_image_wait_results(
+				self.socket.connections.lock().as_ref().unwrap(),
+				&mut self.namespaces,
+			);
+
+			// Close connections that stopped sending their announced heartbeats.
+			// Connections blocked on an image wait can't send heartbeats
+			for (conn_id, conn) in self
+				.socket
+				.connections
+				.lock()
+				.as_ref()
+				.unwrap()
+				.iter()
+				.enumerate()
+			{
+				if self.has_image_wait(conn.borrow().get_id()) {
+					continue;
+				}
+
+				if VkServer::is_connection_stalled(&conn.borrow()) {
+					println!("Closing connection after missed heartbeats");
+					connections_to_close.push(conn_id);
+				}
+			}
+
 			// Stop if no connections active
 			if self.socket.connections.lock().as_ref().unwrap().is_empty() {
-				if SystemTime::now() > conn_timeout {
+				if Instant::now() > conn_timeout {
 					//println!("No connections active. Closing server...");
 					break;
 				}
 			} else {
-				conn_timeout = SystemTime::now() + self.connection_wait_timeout;
+				conn_timeout = Instant::now() + self.connection_wait_timeout;
 			}
 
 			// Break if externally requested
@@ -1,24 +1,33 @@
 mod vk_copy_images;
 
 use std::borrow::{BorrowMut};
+use std::cell::RefCell;
+use std::rc::Rc;
 
 use std::collections::hash_map::{Entry, OccupiedEntry};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::CStr;
 use std::fs;
-use std::io::{Error, ErrorKind};
-use std::mem::{ManuallyDrop, MaybeUninit};
 
-use std::os::fd::IntoRawFd;
-use std::time::Duration;
-use texture_share_vk_base::ipc::platform::img_data::{ImgData};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use texture_share_vk_base::ash::vk;
+use texture_share_vk_base::ipc::platform::img_data::{
+	img_name_matches, DeviceStatsEntry, ImgData, ImgEvent, ImgEventKind, ImgListEntry,
+	ImgMetadataEntry, ImgStatsEntry, IMG_METADATA_MAX_LEN,
+};
 use texture_share_vk_base::ipc::platform::ipc_commands::{
-	CommCopyImage, CommFindImage, CommInitImage, CommandTag, ResultData, ResultFindImage,
-	ResultInitImage, ResultMsg,
+	CommCopyImage, CommDeleteImage, CommFindImage, CommFindImages, CommGetImageMetadata,
+	CommInitImage, CommPing, CommReleaseImage, CommRenameImage, CommSetImageMetadata,
+	CommSubscribe, CommWaitForImage, CommandMsg, ResultDeleteImage, ResultError,
+	ResultErrorCode, ResultFindImage, ResultFindImages, ResultGetImageMetadata, ResultGetStats,
+	ResultInitImage, ResultListImages, ResultMsg, ResultPong, ResultReleaseImage,
+	ResultRenameImage, ResultSetImageMetadata, ResultSubscribe, ResultWaitForImage, ServerError,
+	DEFAULT_NAMESPACE,
 };
 use texture_share_vk_base::ipc::platform::ShmemDataInternal;
 use texture_share_vk_base::ipc::platform::{ReadLockGuard, Timeout};
-use texture_share_vk_base::ipc::{IpcConnection, IpcShmem, IpcSocket};
+use texture_share_vk_base::ipc::{Error, IpcConnection, IpcShmem, IpcSocket};
 use texture_share_vk_base::vk_cpu_shared_image::{AlignedRamBuffer, VkCpuSharedImage};
 use texture_share_vk_base::vk_device::{VkDevice, VkPhysicalDeviceOptions};
 use texture_share_vk_base::vk_instance::VkInstance;
@@ -29,48 +38,152 @@ use texture_share_vk_base::{uuid};
 use self::vk_copy_images::VkCopyImages;
 
 pub(super) struct ServerImageData {
-	pub ipc_info: IpcShmem,
+	// All GPU copies of an image share its shmem segment
+	pub ipc_info: Rc<IpcShmem>,
 	pub vk_shared_image: VkCpuSharedImage,
+	// Buffers 1.. of a multi-buffered image, vk_shared_image is buffer 0
+	pub extra_buffers: Vec<VkCpuSharedImage>,
+}
+
+impl ServerImageData {
+	pub fn get_buffer_count(&self) -> u32 {
+		1 + self.extra_buffers.len() as u32
+	}
+
+	pub fn get_buffer(&self, index: u32) -> Result<&VkCpuSharedImage, Error> {
+		match index {
+			0 => Ok(&self.vk_shared_image),
+			_ => self.extra_buffers.get(index as usize - 1).ok_or_else(|| {
+				Error::Protocol(format!(
+					"Buffer {} of {} out of range",
+					index,
+					self.get_buffer_count()
+				))
+			}),
+		}
+	}
+
+	pub fn buffers(&self) -> impl Iterator<Item = &VkCpuSharedImage> {
+		std::iter::once(&self.vk_shared_image).chain(self.extra_buffers.iter())
+	}
+
+	// Allocate or free buffers until buffer_count are left. New buffers still have to be resized
+	fn set_buffer_count(
+		&mut self,
+		vk_instance: &VkInstance,
+		vk_device: &VkDevice,
+		buffer_count: u32,
+		format: vk::Format,
+	) -> Result<(), vk::Result> {
+		while self.get_buffer_count() > buffer_count {
+			self.extra_buffers.pop().unwrap().destroy(vk_device);
+		}
+
+		while self.get_buffer_count() < buffer_count {
+			let data = self.vk_shared_image.image.get_image_data();
+			let buffer = VkCpuSharedImage::new(
+				vk_instance,
+				vk_device,
+				data.width,
+				data.height,
+				format,
+				data.id,
+			)?;
+			self.extra_buffers.push(buffer);
+		}
+
+		Ok(())
+	}
+
+	// One handle per buffer, in buffer order
+	fn export_handles(&self, vk_device: &VkDevice) -> Result<Vec<OwnedFd>, vk::Result> {
+		self.buffers()
+			.map(|x| x.image.export_handle(vk_device))
+			.collect()
+	}
 }
 
 #[derive(Default)]
 pub(super) struct GpuImageData {
 	pub images: GpuImagesMap,
 	pub ram_buffer: AlignedRamBuffer,
+	// Ids of the connections that created or imported this image
+	pub connections: HashSet<u64>,
+	// Ids of the connections that created this image
+	pub producers: HashSet<u64>,
+	// Time of the last InitImage or CopyImage
+	pub last_update: Option<SystemTime>,
+	// Entries set with SetImageMetadata, written to the shmem segment of the image
+	pub metadata: Vec<ImgMetadataEntry>,
+}
+
+// WaitForImage command that is answered once the image is initialized or deadline passed
+pub(crate) struct ImageWait {
+	pub connection_id: u64,
+	pub image_name: String,
+	pub deadline: Instant,
+}
+
+// Images, subscriptions and pending events and waits of one namespace. Connections only see the
+// namespace chosen in their handshake
+#[derive(Default)]
+pub(crate) struct NamespaceData {
+	pub images: NameImagesMap,
+	pub subscriptions: SubscriptionsMap,
+	pub events: Vec<ImgEvent>,
+	pub image_waits: Vec<ImageWait>,
+}
+
+impl NamespaceData {
+	// Namespaces are dropped once their last image, subscription and wait is gone
+	pub(crate) fn is_unused(&self) -> bool {
+		self.images.is_empty()
+			&& self.subscriptions.is_empty()
+			&& self.events.is_empty()
+			&& self.image_waits.is_empty()
+	}
 }
 
 type DevicesMap = HashMap<u128, VkDevice>;
 
 type GpuImagesMap = HashMap<u128, ServerImageData>;
 type NameImagesMap = HashMap<String, GpuImageData>;
+type SubscriptionsMap = HashMap<String, HashSet<u64>>;
+type NamespacesMap = HashMap<String, NamespaceData>;
 
 pub struct VkServer {
 	pub(crate) socket: IpcSocket,
 	pub(crate) socket_path: String,
 	pub(crate) shmem_prefix: String,
-	pub(crate) images: NameImagesMap,
+	pub(crate) namespaces: NamespacesMap,
 	pub(crate) vk_instance: VkInstance,
 	pub(crate) vk_devices: DevicesMap,
 	pub(crate) connection_wait_timeout: Duration,
 	pub(crate) ipc_timeout: Duration,
+	pub(crate) heartbeat_interval: Duration,
+	// Number of images copied from one GPU to another
+	pub(crate) gpu_copy_count: u64,
 }
 
 impl Drop for VkServer {
 	fn drop(&mut self) {
 		// Ensure that images are cleared before vk_devices are destroyed
-		self.images.drain().for_each(|mut map| {
+		let images = self.namespaces.drain().flat_map(|x| x.1.images);
+		images.for_each(|mut map| {
 			map.1.images.drain().for_each(|x| {
 				let _rlock =
 					x.1.ipc_info
 						.acquire_rlock(Timeout::Val(self.ipc_timeout))
 						.expect("Failed to acquire lock on IpcData");
 				let uuid = uuid::Uuid::from_u128(x.0);
-				x.1.vk_shared_image.destroy(
-					&self
-						.vk_devices
-						.get(&uuid.as_u128())
-						.expect("Failed to find device for VkSharedImage"),
-				);
+				let vk_device = self
+					.vk_devices
+					.get(&uuid.as_u128())
+					.expect("Failed to find device for VkSharedImage");
+				x.1.vk_shared_image.destroy(vk_device);
+				x.1.extra_buffers
+					.into_iter()
+					.for_each(|x| x.destroy(vk_device));
 			})
 		});
 
@@ -83,6 +196,9 @@ impl Drop for VkServer {
 
 impl VkServer {
 	pub(crate) const LISTENER_EVENT_KEY: usize = usize::MAX - 1;
+	pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_millis(1000);
+	// Number of heartbeats a client may miss before its connection is closed
+	pub(crate) const HEARTBEAT_MISSED_LIMIT: u32 = 3;
 
 	pub fn new(
 		socket_path: &str,
@@ -109,17 +225,17 @@ impl VkServer {
 		let mut vk_devices = HashMap::default();
 		vk_devices.insert(gpu_device_uuid.as_u128(), vk_device);
 
-		let images = HashMap::default();
-
 		Ok(VkServer {
 			socket,
 			socket_path: socket_path.to_string(),
 			shmem_prefix: shmem_prefix.to_string(),
-			images,
+			namespaces: HashMap::default(),
 			vk_instance,
 			vk_devices,
 			connection_wait_timeout,
 			ipc_timeout,
+			heartbeat_interval: VkServer::DEFAULT_HEARTBEAT_INTERVAL,
+			gpu_copy_count: 0,
 		})
 	}
 
@@ -127,83 +243,226 @@ impl VkServer {
 		self.socket.timeout = connection_timeout;
 	}
 
+	// How often connections are checked for missed heartbeats
+	pub fn set_heartbeat_interval(&mut self, heartbeat_interval: Duration) {
+		self.heartbeat_interval = heartbeat_interval;
+	}
+
+	// Connections that announced a heartbeat are considered dead after missing several of them
+	pub(crate) fn is_connection_stalled(conn: &IpcConnection) -> bool {
+		match conn.get_heartbeat_interval() {
+			Some(interval) => {
+				conn.get_last_recv().elapsed()
+					> interval.saturating_mul(VkServer::HEARTBEAT_MISSED_LIMIT)
+			}
+			None => false,
+		}
+	}
+
 	pub(crate) fn process_single_connection(
 		conn: &IpcConnection,
 		vk_instance: &VkInstance,
 		vk_devices: &mut DevicesMap,
 		shmem_prefix: &str,
-		images: &mut NameImagesMap,
+		namespaces: &mut NamespacesMap,
+		gpu_copy_count: &mut u64,
+		connection_count: usize,
+		server_instance_id: u128,
 		ipc_timeout: Duration,
 	) -> Result<bool, Box<dyn std::error::Error>> {
-		// Try to receive command. If connection was closed by peer, remove this connection from vector
+		// Try to receive command. If connection was closed by peer, remove this connection from vector.
+		// Malformed messages leave the stream in an unknown state, so such connections are dropped too
 		let cmd = match conn.recv_command_if_available() {
-			Err(e) => match e.kind() {
-				ErrorKind::BrokenPipe => {
-					return Ok(false);
+			Err(e) => {
+				if !e.is_disconnect() {
+					println!("Closing connection after invalid message '{:}'", e);
 				}
-				_ => Err(e),
-			},
-			o => o,
-		}?;
+				return Ok(false);
+			}
+			Ok(c) => c,
+		};
 
-		if cmd.is_none() {
-			return Ok(true);
+		let cmd = match cmd {
+			Some(c) => c,
+			None => return Ok(true),
+		};
+
+		// The first command of a connection has to be its handshake. Only connections that speak a
+		// compatible protocol are kept
+		if !conn.is_handshake_complete() {
+			return match conn.process_handshake(&cmd, server_instance_id) {
+				Ok(true) => Ok(true),
+				Ok(false) => {
+					println!("Rejected connection with incompatible protocol version");
+					Ok(false)
+				}
+				Err(e) => {
+					println!("Rejected connection, handshake failed with error '{:}'", e);
+					Ok(false)
+				}
+			};
 		}
 
-		let cmd = cmd.unwrap();
-		let res = match cmd.tag {
-			CommandTag::InitImage => VkServer::process_cmd_init_image(
+		// Commands only see the images of the connection's namespace
+		let namespace = conn.get_namespace();
+		let shmem_prefix = &VkServer::get_namespace_shmem_prefix(shmem_prefix, &namespace);
+		let NamespaceData {
+			images,
+			subscriptions,
+			events,
+			image_waits,
+		} = namespaces.entry(namespace.clone()).or_default();
+
+		let res = match &cmd {
+			// The handshake was processed above. Drop clients that repeat it
+			CommandMsg::Hello(_) => return Ok(false),
+			CommandMsg::InitImage(cmd) => VkServer::process_cmd_init_image(
 				conn,
-				unsafe { &cmd.data.init_img },
+				cmd,
 				vk_instance,
 				vk_devices,
 				shmem_prefix,
 				images,
+				events,
+				ipc_timeout,
+			),
+			CommandMsg::FindImage(cmd) => VkServer::process_cmd_find_image(
+				conn,
+				cmd,
+				vk_instance,
+				vk_devices,
+				images,
 				ipc_timeout,
 			),
-			CommandTag::FindImage => VkServer::process_cmd_find_image(
+			CommandMsg::FindImages(cmd) => VkServer::process_cmd_find_images(
 				conn,
-				unsafe { &cmd.data.find_img },
+				cmd,
 				vk_instance,
 				vk_devices,
 				images,
 				ipc_timeout,
 			),
-			CommandTag::CopyImage => VkServer::process_cmd_copy_image(
+			CommandMsg::CopyImage(cmd) => VkServer::process_cmd_copy_image(
 				conn,
-				unsafe { &cmd.data.copy_img },
+				cmd,
 				vk_instance,
 				vk_devices,
 				images,
+				gpu_copy_count,
+				ipc_timeout,
+			),
+			CommandMsg::RenameImage(cmd) => VkServer::process_cmd_rename_image(
+				conn,
+				cmd,
+				shmem_prefix,
+				images,
+				subscriptions,
+				events,
+				image_waits,
+				ipc_timeout,
+			),
+			CommandMsg::ListImages(_) => VkServer::process_cmd_list_images(
+				conn,
+				None,
+				images,
+				ipc_timeout,
+			),
+			CommandMsg::ListImagesMatching(cmd) => VkServer::process_cmd_list_images(
+				conn,
+				Some(cmd.pattern.as_str()),
+				images,
+				ipc_timeout,
+			),
+			CommandMsg::ReleaseImage(cmd) => VkServer::process_cmd_release_image(
+				conn,
+				cmd,
+				vk_devices,
+				images,
+				events,
+				ipc_timeout,
+			),
+			CommandMsg::DeleteImage(cmd) => VkServer::process_cmd_delete_image(
+				conn,
+				cmd,
+				vk_devices,
+				images,
+				events,
 				ipc_timeout,
 			),
-			// CommandTag::RenameImage => Server::process_cmd_rename_image(
-			//     &conn.borrow(),
-			//     unsafe { &cmd.data.rename_img },
-			//     vk_setup,
-			//     images,
-			// ),
-			#[allow(unreachable_patterns)]
-			_ => Err::<(), Box<dyn std::error::Error>>(Box::new(Error::new(
-				ErrorKind::InvalidData,
-				"Unknown command received",
-			))),
+			CommandMsg::Subscribe(cmd) => VkServer::process_cmd_subscribe(
+				conn,
+				cmd,
+				images,
+				subscriptions,
+			),
+			CommandMsg::Ping(cmd) => VkServer::process_cmd_ping(conn, cmd),
+			CommandMsg::GetStats(_) => VkServer::process_cmd_get_stats(
+				conn,
+				vk_devices,
+				namespaces,
+				&namespace,
+				*gpu_copy_count,
+				connection_count,
+				ipc_timeout,
+			),
+			CommandMsg::SetImageMetadata(cmd) => VkServer::process_cmd_set_image_metadata(
+				conn,
+				cmd,
+				images,
+				events,
+				ipc_timeout,
+			),
+			CommandMsg::GetImageMetadata(cmd) => VkServer::process_cmd_get_image_metadata(
+				conn,
+				cmd,
+				images,
+			),
+			CommandMsg::WaitForImage(cmd) => VkServer::process_cmd_wait_for_image(
+				conn,
+				cmd,
+				images,
+				image_waits,
+			),
+		};
+
+		let err = match res {
+			Ok(_) => return Ok(true),
+			Err(e) => e,
 		};
 
+		// Socket errors leave the connection in an unknown state, close it
+		if let Some(ipc_err) = err.downcast_ref::<Error>() {
+			if ipc_err.is_disconnect() || ipc_err.is_timeout() {
+				println!("Closing connection after error '{:}'", err);
+				return Ok(false);
+			}
+		}
+
+		// Report all other errors to the client
+		let res = conn.send_result(ResultMsg::Error(VkServer::get_result_error(&err)));
 		match res {
-			Err(e) => match e.downcast_ref::<Error>() {
-				None => Err(e),
-				Some(ioe) => match ioe.kind() {
-					ErrorKind::BrokenPipe => return Ok(false),
-					_ => Err(e),
-				},
-			},
+			Err(e) if e.is_disconnect() || e.is_timeout() => return Ok(false),
 			s => s,
 		}?;
 
 		Ok(true)
 	}
 
+	// Shared memory names can't contain '/'. Escape it, and '%' to keep names of different images
+	// apart, so that structured image names like "studio/cam1/color" can be used
+	fn get_shmem_name(shmem_prefix: &str, image_name: &str) -> String {
+		shmem_prefix.to_owned() + &image_name.replace('%', "%25").replace('/', "%2F")
+	}
+
+	// Images of other namespaces than the default one get their own shmem prefix. get_shmem_name
+	// never produces the separator, so that shmem names of different namespaces can't collide
+	fn get_namespace_shmem_prefix(shmem_prefix: &str, namespace: &str) -> String {
+		match namespace {
+			DEFAULT_NAMESPACE => shmem_prefix.to_string(),
+			_ => VkServer::get_shmem_name(shmem_prefix, namespace) + "%%",
+		}
+	}
+
 	fn process_cmd_init_image(
 		connection: &IpcConnection,
 		cmd: &CommInitImage,
@@ -211,43 +470,66 @@ impl VkServer {
 		vk_devices: &mut DevicesMap,
 		shmem_prefix: &str,
 		images: &mut NameImagesMap,
+		events: &mut Vec<ImgEvent>,
 		ipc_timeout: Duration,
 	) -> Result<(), Box<dyn std::error::Error>> {
-		// Get or create correct device
-		let vk_device_entry =
-			Self::get_or_create_device(vk_devices, vk_instance, cmd.gpu_device_uuid)?;
-		let vk_device = vk_device_entry.get();
+		// Get or create correct device. The other GPU copies of the image are updated with their
+		// own devices
+		Self::get_or_create_device(vk_devices, vk_instance, cmd.gpu_device_uuid)?;
+		let vk_devices = &*vk_devices;
+		let vk_device = &vk_devices[&cmd.gpu_device_uuid];
 
-		let img_name_str = ImgData::convert_shmem_array_to_str(&cmd.image_name);
-		let shmem_name_str = shmem_prefix.to_owned() + &img_name_str;
+		let img_name_str = cmd.image_name.to_string();
+		let shmem_name_str = VkServer::get_shmem_name(shmem_prefix, &img_name_str);
 
-		let gpu_images_map = images.entry(img_name_str.clone()).or_default();
+		// Connections share the id of the server that accepted them
+		let server_instance_id = connection.get_server_instance_id().unwrap_or_default();
 
 		// Find image data
-		let img_loaded = gpu_images_map.images.contains_key(&cmd.gpu_device_uuid);
-
-		// Process initialization
-		let (result_msg_data, vk_shared_image, _lock) = if !img_loaded || cmd.overwrite_existing {
-			// Only initialize image if none exists or the cmd explicitly allows overriding an image
-			if !img_loaded {
-				// Create image if it doesn't exist yet
-				let ipc_info = IpcShmem::new(&shmem_name_str, &img_name_str, true)?;
-				let vk_shared_image = VkCpuSharedImage::new(
+		let img_loaded = images
+			.get(&img_name_str)
+			.map_or(false, |x| x.images.contains_key(&cmd.gpu_device_uuid));
+
+		// New GPU copies of an existing image share its segment
+		let ipc_info = images
+			.get(&img_name_str)
+			.and_then(|x| x.images.values().next())
+			.map(|x| x.ipc_info.clone());
+
+		// Create image if it doesn't exist yet. This happens before the name is added, so that a
+		// failed creation doesn't leave an entry without images behind
+		let new_image = match img_loaded {
+			true => None,
+			false => Some(ServerImageData {
+				ipc_info: match ipc_info {
+					Some(ipc_info) => ipc_info,
+					None => Rc::new(IpcShmem::new(&shmem_name_str, &img_name_str, true)?),
+				},
+				vk_shared_image: VkCpuSharedImage::new(
 					vk_instance,
 					vk_device,
 					cmd.width,
 					cmd.height,
 					VkSharedImage::get_vk_format(cmd.format),
 					0,
-				)?;
-				let _ = gpu_images_map
-					.images
-					.entry(cmd.gpu_device_uuid)
-					.insert_entry(ServerImageData {
-						ipc_info,
-						vk_shared_image,
-					});
-			};
+				)?,
+				extra_buffers: Vec::new(),
+			}),
+		};
+
+		let gpu_images_map = images.entry(img_name_str.clone()).or_default();
+
+		// Process initialization
+		let cur_ipc_info;
+		let (result_msg_data, server_image, _lock) = if !img_loaded || cmd.overwrite_existing {
+			// Only initialize image if none exists or the cmd explicitly allows overriding an image
+			if let Some(new_image) = new_image {
+				gpu_images_map.images.insert(cmd.gpu_device_uuid, new_image);
+			}
+
+			gpu_images_map.connections.insert(connection.get_id());
+			gpu_images_map.producers.insert(connection.get_id());
+			gpu_images_map.last_update = Some(SystemTime::now());
 
 			// Acquire write lock to image
 			// let lock = server_image_data
@@ -256,44 +538,58 @@ impl VkServer {
 			// let mut data = IpcShmem::acquire_data(&lock);
 
 			let format = VkSharedImage::get_vk_format(cmd.format);
-			let mut cur_img_lock = MaybeUninit::uninit();
-			let mut cur_img_data = MaybeUninit::uninit();
-			let _locks = gpu_images_map
-				.images
-				.iter_mut()
-				.map(|image| {
-					// Update all shared images with the new size
-					let lock = image.1.ipc_info.acquire_lock(Timeout::Val(ipc_timeout))?;
-					let data = IpcShmem::acquire_data(&lock);
 
-					image.1.vk_shared_image.borrow_mut().resize_image(
+			// The requested device's image was created above if it didn't exist
+			cur_ipc_info = gpu_images_map.images[&cmd.gpu_device_uuid].ipc_info.clone();
+			let cur_img_lock = cur_ipc_info.acquire_lock(Timeout::Val(ipc_timeout))?;
+			let data = IpcShmem::acquire_data(&cur_img_lock);
+
+			// Update all shared images with the new size and buffer count. They share the
+			// segment, and with it the handle id
+			let handle_id = data.handle_id + 1;
+			for image in gpu_images_map.images.iter_mut() {
+				let image_device = &vk_devices[image.0];
+				image
+					.1
+					.set_buffer_count(vk_instance, image_device, cmd.buffer_count, format)?;
+				for buffer in std::iter::once(&mut image.1.vk_shared_image)
+					.chain(image.1.extra_buffers.iter_mut())
+				{
+					buffer.borrow_mut().resize_image(
 						&vk_instance,
-						&vk_device,
+						image_device,
 						cmd.width,
 						cmd.height,
 						format,
-						data.handle_id + 1,
+						handle_id,
 						&mut gpu_images_map.ram_buffer,
 					)?;
+				}
+			}
 
-					// Update Shmem data
-					VkServer::update_shmem_data(data, &image.1.vk_shared_image.image);
+			// Update Shmem data
+			let cur_img_data = &gpu_images_map.images[&cmd.gpu_device_uuid];
+			VkServer::update_shmem_data(data, &cur_img_data.vk_shared_image.image);
+			data.set_server_instance_id(server_instance_id);
+			data.buffer_count = cmd.buffer_count;
+			if data.latest_buffer >= cmd.buffer_count {
+				data.latest_buffer = 0;
+			}
 
-					if *image.0 == cmd.gpu_device_uuid {
-						cur_img_lock = MaybeUninit::new(lock);
-						cur_img_data = MaybeUninit::new(&image.1.vk_shared_image);
-						Ok::<_, Box<dyn std::error::Error>>(None)
-					} else {
-						Ok::<_, Box<dyn std::error::Error>>(Some(lock))
-					}
-				})
-				.collect::<Result<Vec<_>, _>>()?;
+			// New segments start without metadata
+			IpcShmem::set_metadata(&cur_img_lock, &gpu_images_map.metadata)?;
 
-			let data = IpcShmem::acquire_data(unsafe { cur_img_lock.assume_init_ref() });
+			let data = IpcShmem::acquire_data(&cur_img_lock);
+			events.push(ImgEvent {
+				kind: ImgEventKind::ImageResized,
+				image_name: img_name_str.clone(),
+				data: data.clone(),
+			});
 
 			// Generate ResultMsg data
 			let img_data = ImgData::from_shmem_data_internal(
-				ImgData::convert_shmem_str_to_array(&shmem_name_str),
+				shmem_name_str.clone(),
+				img_name_str.clone(),
 				data.clone(),
 			);
 
@@ -303,8 +599,8 @@ impl VkServer {
 					image_created: true,
 					img_data,
 				},
-				Some(unsafe { cur_img_data.assume_init() }),
-				Some(unsafe { cur_img_lock.assume_init() }),
+				Some(cur_img_data),
+				Some(cur_img_lock),
 			)
 		} else {
 			// If image not loaded or cmd.overwrite_existing is false, send empty result back
@@ -319,18 +615,15 @@ impl VkServer {
 		};
 
 		// Send result to client
-		let res_msg = ResultMsg {
-			tag: CommandTag::InitImage,
-			data: ResultData {
-				init_img: ManuallyDrop::new(result_msg_data),
-			},
-		};
+		let res_msg = ResultMsg::InitImage(result_msg_data);
 		connection.send_result(res_msg)?;
 
-		// If image was created/updated, send handles to client
-		if vk_shared_image.is_some() {
-			let handles = vk_shared_image.unwrap().image.export_handle(vk_device)?;
-			connection.send_anillary_handles(&[handles.into_raw_fd()])?;
+		// If image was created/updated, send the handles of all buffers to client. The exported
+		// fds are closed afterwards
+		if let Some(server_image) = server_image {
+			let fds = server_image.export_handles(vk_device)?;
+			let raw_fds = fds.iter().map(|fd| fd.as_raw_fd()).collect::<Vec<_>>();
+			connection.send_anillary_handles(&raw_fds)?;
 
 			// Receive ack
 			connection.recv_ack()?;
@@ -353,34 +646,36 @@ impl VkServer {
 
 		let vk_device = vk_device_entry.get();
 
-		let img_name_str = ImgData::convert_shmem_array_to_str(&cmd.image_name);
-
-		let gpu_images_map = images.entry(img_name_str).or_default();
-		let image_and_lock: Option<(ImgData, &mut VkCpuSharedImage, ReadLockGuard)> =
-			match gpu_images_map.images.entry(cmd.gpu_device_uuid) {
-				Entry::Occupied(e) => {
-					let entry = e.into_mut();
-					let rlock = entry
-						.ipc_info
-						.acquire_rlock(Timeout::Val(ipc_timeout))
-						.unwrap();
+		let img_name_str = cmd.image_name.to_string();
+
+		// Lookups don't add names, only init_image does
+		let gpu_images_map = images
+			.get_mut(&img_name_str)
+			.filter(|x| x.images.contains_key(&cmd.gpu_device_uuid));
+		let image_and_lock: Option<(ImgData, &ServerImageData, ReadLockGuard)> =
+			match gpu_images_map {
+				Some(gpu_images_map) => {
+					let entry = &gpu_images_map.images[&cmd.gpu_device_uuid];
+					let rlock = entry.ipc_info.acquire_rlock(Timeout::Val(ipc_timeout))?;
 					let rdata = IpcShmem::acquire_rdata(&rlock);
+					gpu_images_map.connections.insert(connection.get_id());
 
 					Some((
 						ImgData::from_shmem_data_internal(
-							ImgData::convert_shmem_str_to_array(entry.ipc_info.get_name()),
+							entry.ipc_info.get_name().to_string(),
+							cmd.image_name.clone(),
 							rdata.clone(),
 						),
-						&mut entry.vk_shared_image,
+						entry,
 						rlock,
 					))
 				}
-				Entry::Vacant(_) => None,
+				None => None,
 			};
 
 		// Keep lock, extract image
-		let (image, vk_shared_image, _opt_lock) = match image_and_lock {
-			Some((image, vk_img, lock)) => (Some(image), Some(vk_img), Some(lock)),
+		let (image, server_image, _opt_lock) = match image_and_lock {
+			Some((image, server_img, lock)) => (Some(image), Some(server_img), Some(lock)),
 			_ => (None, None, None),
 		};
 
@@ -396,16 +691,100 @@ impl VkServer {
 			},
 		};
 
-		connection.send_result(ResultMsg {
-			tag: CommandTag::FindImage,
-			data: ResultData {
-				find_img: ManuallyDrop::new(res_data),
-			},
-		})?;
+		connection.send_result(ResultMsg::FindImage(res_data))?;
 
-		if vk_shared_image.is_some() {
-			let fd = vk_shared_image.unwrap().image.export_handle(vk_device)?;
-			connection.send_anillary_handles(&[fd.into_raw_fd()])?;
+		if let Some(server_image) = server_image {
+			let fds = server_image.export_handles(vk_device)?;
+			let raw_fds = fds.iter().map(|fd| fd.as_raw_fd()).collect::<Vec<_>>();
+			connection.send_anillary_handles(&raw_fds)?;
+			connection.recv_ack()?;
+		}
+
+		Ok(())
+	}
+
+	fn process_cmd_find_images(
+		connection: &IpcConnection,
+		cmd: &CommFindImages,
+		vk_instance: &VkInstance,
+		vk_devices: &mut DevicesMap,
+		images: &mut NameImagesMap,
+		ipc_timeout: Duration,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		// Get or create correct device
+		let vk_device_entry =
+			Self::get_or_create_device(vk_devices, vk_instance, cmd.gpu_device_uuid)?;
+
+		let vk_device = vk_device_entry.get();
+
+		// Validated on receive
+		let image_names = &cmd.image_names[0..cmd.image_count as usize];
+
+		for image_name in image_names.iter() {
+			if let Some(gpu_images_map) = images.get_mut(image_name.as_str()) {
+				if gpu_images_map.images.contains_key(&cmd.gpu_device_uuid) {
+					gpu_images_map.connections.insert(connection.get_id());
+				}
+			}
+		}
+
+		// Export all handles before sending results, so that a failure can still be reported. Keep
+		// the images locked until the client acknowledged the handles
+		let mut results = Vec::with_capacity(image_names.len());
+		let mut fds = Vec::new();
+		let mut _locks = Vec::new();
+		for (image_index, image_name) in image_names.iter().enumerate() {
+			let image = images
+				.get(image_name.as_str())
+				.and_then(|x| x.images.get(&cmd.gpu_device_uuid));
+
+			let res_data = match image {
+				Some(image) => {
+					// Names requested twice are already locked, reuse the first result
+					let prev_res = image_names[0..image_index]
+						.iter()
+						.position(|x| x == image_name)
+						.map(|i| &results[i]);
+					let img_data = match prev_res {
+						Some(ResultFindImages { img_data, .. }) => img_data.clone(),
+						None => {
+							let rlock = image.ipc_info.acquire_rlock(Timeout::Val(ipc_timeout))?;
+							let img_data = ImgData::from_shmem_data_internal(
+								image.ipc_info.get_name().to_string(),
+								image_name.clone(),
+								IpcShmem::acquire_rdata(&rlock).clone(),
+							);
+							_locks.push(rlock);
+							img_data
+						}
+					};
+					fds.extend(image.export_handles(vk_device)?);
+
+					ResultFindImages {
+						image_count: cmd.image_count,
+						image_index: image_index as u32,
+						image_found: true,
+						img_data,
+					}
+				}
+				None => ResultFindImages {
+					image_count: cmd.image_count,
+					image_index: image_index as u32,
+					image_found: false,
+					img_data: ImgData::default(),
+				},
+			};
+			results.push(res_data);
+		}
+
+		for res_data in results {
+			connection.send_result(ResultMsg::FindImages(res_data))?;
+		}
+
+		// All handles are sent in a single message. The exported fds are closed afterwards
+		if !fds.is_empty() {
+			let raw_fds = fds.iter().map(|fd| fd.as_raw_fd()).collect::<Vec<_>>();
+			connection.send_anillary_handles(&raw_fds)?;
 			connection.recv_ack()?;
 		}
 
@@ -418,9 +797,15 @@ impl VkServer {
 		_vk_instance: &VkInstance,
 		vk_devices: &mut DevicesMap,
 		images: &mut NameImagesMap,
+		gpu_copy_count: &mut u64,
 		ipc_timeout: Duration,
 	) -> Result<(), Box<dyn std::error::Error>> {
-		let img_name_str = ImgData::convert_shmem_array_to_str(&cmd.image_name);
+		let img_name_str = cmd.image_name.to_string();
+
+		// Clients request a copy after writing to their image
+		if let Some(gpu_images_map) = images.get_mut(&img_name_str) {
+			gpu_images_map.last_update = Some(SystemTime::now());
+		}
 
 		// Get gpu map
 		let gpu_images_map = images.get(&img_name_str);
@@ -430,46 +815,679 @@ impl VkServer {
 				return Ok(());
 			}
 
-			// Get read and write images
-			let mut read_image = None;
-			let mut read_lock = None;
-			let mut write_images = Vec::new();
-			write_images.reserve(gpu_images_map.images.len() - 1);
-			let _write_locks = gpu_images_map
+			// The latest buffer of the written image is copied into the same buffer of all others
+			let read_image_data = match gpu_images_map.images.get(&cmd.gpu_device_uuid) {
+				Some(image) => image,
+				None => return Ok(()),
+			};
+			let read_lock = read_image_data
+				.ipc_info
+				.acquire_rlock(Timeout::Val(ipc_timeout))?;
+			let read_data = IpcShmem::acquire_rdata(&read_lock);
+			let buffer = read_data.latest_buffer;
+			let read_image = (
+				vk_devices.get(&cmd.gpu_device_uuid).unwrap(),
+				read_image_data.get_buffer(buffer)?,
+			);
+
+			// Get write images. They share the segment of the written image, so they already show
+			// its frame, and the read lock keeps writers out until the copy is done
+			let write_images = gpu_images_map
 				.images
 				.iter()
-				.map(|image| {
-					if *image.0 == cmd.gpu_device_uuid {
-						read_lock =
-							Some(image.1.ipc_info.acquire_rlock(Timeout::Val(ipc_timeout))?);
-						read_image =
-							Some((vk_devices.get(image.0).unwrap(), &image.1.vk_shared_image));
-						Ok::<_, Box<dyn std::error::Error>>(None)
-					} else {
-						let write_lock =
-							image.1.ipc_info.acquire_lock(Timeout::Val(ipc_timeout))?;
-						write_images
-							.push((vk_devices.get(image.0).unwrap(), &image.1.vk_shared_image));
-						Ok::<_, Box<dyn std::error::Error>>(Some(write_lock))
+				.filter(|image| *image.0 != cmd.gpu_device_uuid)
+				.map(|image| Ok((vk_devices.get(image.0).unwrap(), image.1.get_buffer(buffer)?)))
+				.collect::<Result<Vec<_>, Error>>()?;
+
+			VkCopyImages::copy_images(read_image, &write_images)?;
+			*gpu_copy_count += write_images.len() as u64;
+		}
+
+		Ok(())
+	}
+
+	fn process_cmd_rename_image(
+		connection: &IpcConnection,
+		cmd: &CommRenameImage,
+		shmem_prefix: &str,
+		images: &mut NameImagesMap,
+		subscriptions: &mut SubscriptionsMap,
+		events: &mut Vec<ImgEvent>,
+		image_waits: &mut Vec<ImageWait>,
+		ipc_timeout: Duration,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		let old_name_str = cmd.old_image_name.to_string();
+		let new_name_str = cmd.new_image_name.to_string();
+
+		let rename = images.contains_key(&old_name_str)
+			&& (old_name_str == new_name_str || !images.contains_key(&new_name_str));
+
+		let renamed = rename && old_name_str != new_name_str;
+		if renamed {
+			VkServer::rename_image(
+				&old_name_str,
+				&new_name_str,
+				shmem_prefix,
+				images,
+				ipc_timeout,
+			)?;
+
+			// Subscriptions and pending waits follow the image to its new name
+			if let Some(subscribers) = subscriptions.remove(&old_name_str) {
+				subscriptions
+					.entry(new_name_str.clone())
+					.or_default()
+					.extend(subscribers);
+			}
+			image_waits
+				.iter_mut()
+				.filter(|x| x.image_name == old_name_str)
+				.for_each(|x| x.image_name = new_name_str.clone());
+		}
+
+		let res_data = if rename {
+			// Any GPU copy will do, they all share the same name and dimensions
+			let image = images
+				.get(&new_name_str)
+				.unwrap()
+				.images
+				.values()
+				.next()
+				.unwrap();
+			let rlock = image.ipc_info.acquire_rlock(Timeout::Val(ipc_timeout))?;
+
+			// Subscribers of the old name now receive the events of the new one. Local copies have
+			// to be updated with find_image, as the shmem segment was recreated
+			if renamed {
+				events.push(ImgEvent {
+					kind: ImgEventKind::ImageResized,
+					image_name: new_name_str.clone(),
+					data: IpcShmem::acquire_rdata(&rlock).clone(),
+				});
+			}
+
+			ResultRenameImage {
+				image_found: true,
+				img_data: ImgData::from_shmem_data_internal(
+					image.ipc_info.get_name().to_string(),
+					new_name_str.clone(),
+					IpcShmem::acquire_rdata(&rlock).clone(),
+				),
+			}
+		} else {
+			ResultRenameImage {
+				image_found: false,
+				img_data: ImgData::default(),
+			}
+		};
+
+		connection.send_result(ResultMsg::RenameImage(res_data))?;
+
+		Ok(())
+	}
+
+	// Answers ListImages, or ListImagesMatching if a pattern is given
+	fn process_cmd_list_images(
+		connection: &IpcConnection,
+		pattern: Option<&str>,
+		images: &NameImagesMap,
+		ipc_timeout: Duration,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		let mut image_names = images
+			.keys()
+			.filter(|x| pattern.map_or(true, |pattern| img_name_matches(x, pattern)))
+			.collect::<Vec<_>>();
+		image_names.sort();
+
+		let entries = image_names
+			.iter()
+			.map(|name| {
+				let gpu_images_map = images.get(*name).unwrap();
+				let gpu_device_uuids = gpu_images_map.images.keys().copied().collect::<Vec<_>>();
+
+				// Any GPU copy will do, they all share the same name and dimensions
+				let image = gpu_images_map.images.values().next().unwrap();
+				let rlock = image.ipc_info.acquire_rlock(Timeout::Val(ipc_timeout))?;
+				Ok::<_, Box<dyn std::error::Error>>(ImgListEntry::new(
+					name.to_string(),
+					IpcShmem::acquire_rdata(&rlock),
+					&gpu_device_uuids,
+				))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		let image_count = entries.len() as u32;
+		let send_entry = |image_index: u32, image: ImgListEntry| {
+			let res = ResultListImages {
+				image_count,
+				image_index,
+				image,
+			};
+			connection.send_result(match pattern {
+				Some(_) => ResultMsg::ListImagesMatching(res),
+				None => ResultMsg::ListImages(res),
+			})
+		};
+
+		// Always send at least one result, so that the client learns the image count
+		if entries.is_empty() {
+			send_entry(0, ImgListEntry::default())?;
+		}
+
+		for (image_index, image) in entries.into_iter().enumerate() {
+			send_entry(image_index as u32, image)?;
+		}
+
+		Ok(())
+	}
+
+	fn process_cmd_release_image(
+		connection: &IpcConnection,
+		cmd: &CommReleaseImage,
+		vk_devices: &DevicesMap,
+		images: &mut NameImagesMap,
+		events: &mut Vec<ImgEvent>,
+		ipc_timeout: Duration,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		let img_name_str = cmd.image_name.to_string();
+
+		let image_released = images.get_mut(&img_name_str).map_or(false, |x| {
+			x.producers.remove(&connection.get_id());
+			x.connections.remove(&connection.get_id())
+		});
+		let image_destroyed = image_released
+			&& images
+				.get(&img_name_str)
+				.map_or(false, |x| x.connections.is_empty());
+
+		if image_destroyed {
+			VkServer::destroy_image(&img_name_str, vk_devices, images, events, ipc_timeout);
+		}
+
+		connection.send_result(ResultMsg::ReleaseImage(ResultReleaseImage {
+			image_released,
+			image_destroyed,
+		}))?;
+
+		Ok(())
+	}
+
+	fn process_cmd_delete_image(
+		connection: &IpcConnection,
+		cmd: &CommDeleteImage,
+		vk_devices: &DevicesMap,
+		images: &mut NameImagesMap,
+		events: &mut Vec<ImgEvent>,
+		ipc_timeout: Duration,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		let img_name_str = cmd.image_name.to_string();
+
+		let image_found = images.contains_key(&img_name_str);
+
+		VkServer::destroy_image(&img_name_str, vk_devices, images, events, ipc_timeout);
+
+		connection.send_result(ResultMsg::DeleteImage(ResultDeleteImage { image_found }))?;
+
+		Ok(())
+	}
+
+	// Drop all image references, subscriptions and waits held by a closed connection
+	pub(crate) fn release_connection_images(
+		connection_id: u64,
+		namespace: &str,
+		vk_devices: &DevicesMap,
+		namespaces: &mut NamespacesMap,
+		ipc_timeout: Duration,
+	) {
+		let NamespaceData {
+			images,
+			subscriptions,
+			events,
+			image_waits,
+		} = match namespaces.get_mut(namespace) {
+			Some(namespace_data) => namespace_data,
+			None => return,
+		};
+
+		subscriptions.retain(|_, x| {
+			x.remove(&connection_id);
+			!x.is_empty()
+		});
+		image_waits.retain(|x| x.connection_id != connection_id);
+
+		let mut unused_images = Vec::new();
+		for (image_name, gpu_images_map) in images.iter_mut() {
+			if gpu_images_map.producers.remove(&connection_id) {
+				events.push(ImgEvent::new(ImgEventKind::ProducerDisconnected, image_name));
+			}
+
+			if gpu_images_map.connections.remove(&connection_id)
+				&& gpu_images_map.connections.is_empty()
+			{
+				unused_images.push(image_name.clone());
+			}
+		}
+
+		for image_name in unused_images {
+			VkServer::destroy_image(&image_name, vk_devices, images, events, ipc_timeout);
+		}
+	}
+
+	// Free the GPU memory of all GPU copies of an image and its shmem segment. Clients keep their
+	// imported memory, so copies are freed even if a reader doesn't release its lock in time
+	fn destroy_image(
+		image_name: &str,
+		vk_devices: &DevicesMap,
+		images: &mut NameImagesMap,
+		events: &mut Vec<ImgEvent>,
+		ipc_timeout: Duration,
+	) {
+		if let Some(mut gpu_images_map) = images.remove(image_name) {
+			events.push(ImgEvent::new(ImgEventKind::ImageRemoved, image_name));
+
+			for (gpu_device_uuid, image) in gpu_images_map.images.drain() {
+				// Wait until no client is reading the image anymore
+				let _lock = match image.ipc_info.acquire_lock(Timeout::Val(ipc_timeout)) {
+					Ok(lock) => Some(lock),
+					Err(e) => {
+						println!("Destroying image {} without lock: {}", image_name, e);
+						None
 					}
+				};
+				let vk_device = vk_devices
+					.get(&gpu_device_uuid)
+					.expect("Failed to find device for VkSharedImage");
+				image.vk_shared_image.destroy(vk_device);
+				image
+					.extra_buffers
+					.into_iter()
+					.for_each(|x| x.destroy(vk_device));
+			}
+		}
+	}
+
+	fn process_cmd_subscribe(
+		connection: &IpcConnection,
+		cmd: &CommSubscribe,
+		images: &NameImagesMap,
+		subscriptions: &mut SubscriptionsMap,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		let img_name_str = cmd.image_name.to_string();
+
+		if cmd.subscribe {
+			subscriptions
+				.entry(img_name_str.clone())
+				.or_default()
+				.insert(connection.get_id());
+		} else if let Entry::Occupied(mut e) = subscriptions.entry(img_name_str.clone()) {
+			e.get_mut().remove(&connection.get_id());
+			if e.get().is_empty() {
+				e.remove();
+			}
+		}
+
+		let image_found = images.contains_key(&img_name_str);
+
+		connection.send_result(ResultMsg::Subscribe(ResultSubscribe { image_found }))?;
+
+		Ok(())
+	}
+
+	fn process_cmd_ping(
+		connection: &IpcConnection,
+		cmd: &CommPing,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		let heartbeat_interval = match cmd.heartbeat_interval_millis {
+			0 => None,
+			millis => Some(Duration::from_millis(millis)),
+		};
+		connection.set_heartbeat_interval(heartbeat_interval);
+
+		connection.send_result(ResultMsg::Ping(ResultPong {
+			sequence: cmd.sequence,
+		}))?;
+
+		Ok(())
+	}
+
+	// Images are listed for the connection's namespace, device statistics cover all namespaces
+	fn process_cmd_get_stats(
+		connection: &IpcConnection,
+		vk_devices: &DevicesMap,
+		namespaces: &NamespacesMap,
+		namespace: &str,
+		gpu_copy_count: u64,
+		connection_count: usize,
+		ipc_timeout: Duration,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		let images = &namespaces.get(namespace).unwrap().images;
+
+		let mut image_names = images.keys().collect::<Vec<_>>();
+		image_names.sort();
+
+		let image_entries = image_names
+			.iter()
+			.map(|name| {
+				let gpu_images_map = images.get(*name).unwrap();
+				let gpu_device_uuids = gpu_images_map.images.keys().copied().collect::<Vec<_>>();
+
+				// Any GPU copy will do, they all share the same name and dimensions
+				let image = gpu_images_map.images.values().next().unwrap();
+				let rlock = image.ipc_info.acquire_rlock(Timeout::Val(ipc_timeout))?;
+				let rdata = IpcShmem::acquire_rdata(&rlock);
+				Ok::<_, Box<dyn std::error::Error>>(ImgStatsEntry {
+					image: ImgListEntry::new(name.to_string(), rdata, &gpu_device_uuids),
+					allocation_size: rdata.allocation_size * rdata.buffer_count as u64,
+					producer_count: gpu_images_map.producers.len() as u32,
+					consumer_count: gpu_images_map
+						.connections
+						.difference(&gpu_images_map.producers)
+						.count() as u32,
+					last_update_millis: gpu_images_map.last_update.map_or(0, |t| {
+						t.duration_since(UNIX_EPOCH)
+							.map_or(0, |d| d.as_millis() as u64)
+					}),
 				})
-				.collect::<Result<Vec<_>, _>>();
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		let mut gpu_device_uuids = vk_devices.keys().copied().collect::<Vec<_>>();
+		gpu_device_uuids.sort();
+
+		let device_entries = gpu_device_uuids
+			.iter()
+			.map(|gpu_device_uuid| {
+				let device_images = namespaces
+					.values()
+					.flat_map(|x| x.images.values())
+					.filter_map(|x| x.images.get(gpu_device_uuid))
+					.collect::<Vec<_>>();
+				let memory_usage = device_images
+					.iter()
+					.flat_map(|x| x.buffers())
+					.map(|x| x.image.get_image_data().allocation_size)
+					.sum();
+				DeviceStatsEntry::new(*gpu_device_uuid, device_images.len() as u32, memory_usage)
+			})
+			.collect::<Vec<_>>();
+
+		let image_count = image_entries.len() as u32;
+		let device_count = device_entries.len() as u32;
+		let send_entry = |entry_index: u32, image: ImgStatsEntry, device: DeviceStatsEntry| {
+			connection.send_result(ResultMsg::GetStats(ResultGetStats {
+				connection_count: connection_count as u32,
+				gpu_copy_count,
+				image_count,
+				device_count,
+				entry_index,
+				image,
+				device,
+			}))
+		};
+
+		// Always send at least one result, so that the client learns the counts
+		if image_entries.is_empty() && device_entries.is_empty() {
+			send_entry(0, ImgStatsEntry::default(), DeviceStatsEntry::default())?;
+		}
+
+		for (entry_index, image) in image_entries.into_iter().enumerate() {
+			send_entry(entry_index as u32, image, DeviceStatsEntry::default())?;
+		}
+
+		for (entry_index, device) in device_entries.into_iter().enumerate() {
+			send_entry(
+				image_count + entry_index as u32,
+				ImgStatsEntry::default(),
+				device,
+			)?;
+		}
+
+		Ok(())
+	}
+
+	fn process_cmd_set_image_metadata(
+		connection: &IpcConnection,
+		cmd: &CommSetImageMetadata,
+		images: &mut NameImagesMap,
+		events: &mut Vec<ImgEvent>,
+		ipc_timeout: Duration,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		let img_name_str = cmd.image_name.to_string();
+
+		// Names received from clients may be empty, keys may not
+		if cmd.entry.get_key().is_empty() {
+			return Err(Box::new(ServerError::new(
+				ResultErrorCode::InvalidCommand,
+				"Metadata key is empty".to_string(),
+			)));
+		}
+
+		let gpu_images_map = images.get_mut(&img_name_str);
+
+		let image_found = gpu_images_map.is_some();
+		if let Some(gpu_images_map) = gpu_images_map {
+			let mut metadata = gpu_images_map.metadata.clone();
+			let index = metadata
+				.iter()
+				.position(|x| x.get_key() == cmd.entry.get_key());
+			match (index, cmd.remove) {
+				(Some(index), true) => {
+					metadata.remove(index);
+				}
+				(Some(index), false) => metadata[index] = cmd.entry.clone(),
+				(None, true) => (),
+				(None, false) => metadata.push(cmd.entry.clone()),
+			}
+
+			// Reject entries that don't fit into the shmem segment before it is changed
+			let metadata_len = metadata.iter().map(|x| x.encoded_len()).sum::<usize>();
+			if metadata_len > IMG_METADATA_MAX_LEN {
+				return Err(Box::new(ServerError::new(
+					ResultErrorCode::MetadataTooLarge,
+					format!(
+						"Metadata of {} bytes exceeds the maximum of {} bytes",
+						metadata_len, IMG_METADATA_MAX_LEN
+					),
+				)));
+			}
+
+			// All GPU copies share the segment
+			let image = gpu_images_map.images.values().next().unwrap();
+			let lock = image.ipc_info.acquire_lock(Timeout::Val(ipc_timeout))?;
+			IpcShmem::set_metadata(&lock, &metadata)?;
 
-			if let Some(read_image) = read_image {
-				VkCopyImages::copy_images(read_image, &write_images)?;
+			if metadata != gpu_images_map.metadata {
+				gpu_images_map.metadata = metadata;
+				events.push(ImgEvent::new(ImgEventKind::MetadataChanged, &img_name_str));
 			}
 		}
 
+		connection.send_result(ResultMsg::SetImageMetadata(ResultSetImageMetadata { image_found }))?;
+
 		Ok(())
 	}
 
-	// fn process_cmd_rename_image(
-	//     connection: &IpcConnection,
-	//     cmd: &CommRenameImage,
-	//     vk_setup: &VkSetup,
-	//     images: &mut Vec<ImageData>,
-	// ) {
-	// }
+	fn process_cmd_get_image_metadata(
+		connection: &IpcConnection,
+		cmd: &CommGetImageMetadata,
+		images: &NameImagesMap,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		let gpu_images_map = images.get(cmd.image_name.as_str());
+
+		let image_found = gpu_images_map.is_some();
+		let metadata = gpu_images_map.map_or(&[][..], |x| &x.metadata);
+		let entry_count = metadata.len() as u32;
+		let send_entry = |entry_index: u32, entry: ImgMetadataEntry| {
+			connection.send_result(ResultMsg::GetImageMetadata(ResultGetImageMetadata {
+				image_found,
+				entry_count,
+				entry_index,
+				entry,
+			}))
+		};
+
+		// Always send at least one result, so that the client learns the entry count
+		if metadata.is_empty() {
+			send_entry(0, ImgMetadataEntry::default())?;
+		}
+
+		for (entry_index, entry) in metadata.iter().enumerate() {
+			send_entry(entry_index as u32, entry.clone())?;
+		}
+
+		Ok(())
+	}
+
+	fn process_cmd_wait_for_image(
+		connection: &IpcConnection,
+		cmd: &CommWaitForImage,
+		images: &NameImagesMap,
+		image_waits: &mut Vec<ImageWait>,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		let img_name_str = cmd.image_name.to_string();
+
+		// Answer right away if the image exists, otherwise once it is initialized
+		let image_found = images.contains_key(&img_name_str);
+		if image_found || cmd.timeout_millis == 0 {
+			return VkServer::send_wait_result(connection, image_found);
+		}
+
+		image_waits.push(ImageWait {
+			connection_id: connection.get_id(),
+			image_name: img_name_str,
+			deadline: Instant::now() + Duration::from_millis(cmd.timeout_millis),
+		});
+
+		Ok(())
+	}
+
+	fn send_wait_result(
+		connection: &IpcConnection,
+		image_found: bool,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		connection.send_result(ResultMsg::WaitForImage(ResultWaitForImage { image_found }))?;
+
+		Ok(())
+	}
+
+	// Answer all waits whose image was initialized or whose deadline passed
+	pub(crate) fn send_image_wait_results(
+		connections: &[RefCell<IpcConnection>],
+		namespaces: &mut NamespacesMap,
+	) {
+		let now = Instant::now();
+		for namespace_data in namespaces.values_mut() {
+			let images = &namespace_data.images;
+			namespace_data.image_waits.retain(|wait| {
+				let image_found = images.contains_key(&wait.image_name);
+				if !image_found && now < wait.deadline {
+					return true;
+				}
+
+				let conn = connections
+					.iter()
+					.find(|x| x.borrow().get_id() == wait.connection_id);
+
+				// Closed connections are removed by the server loop
+				if let Some(conn) = conn {
+					if let Err(e) = VkServer::send_wait_result(&conn.borrow(), image_found) {
+						println!("Failed to send wait result with error '{:}'", e);
+					}
+				}
+
+				false
+			});
+		}
+	}
+
+	pub(crate) fn has_image_wait(&self, connection_id: u64) -> bool {
+		self.namespaces.values().any(|x| {
+			x.image_waits
+				.iter()
+				.any(|x| x.connection_id == connection_id)
+		})
+	}
+
+	// Time until the next wait expires
+	pub(crate) fn get_next_image_wait_timeout(&self) -> Option<Duration> {
+		self.namespaces
+			.values()
+			.flat_map(|x| x.image_waits.iter())
+			.map(|x| x.deadline.saturating_duration_since(Instant::now()))
+			.min()
+	}
+
+	// Push queued events to all connections subscribed to the affected image
+	pub(crate) fn send_events(
+		connections: &[RefCell<IpcConnection>],
+		namespaces: &mut NamespacesMap,
+	) {
+		for namespace_data in namespaces.values_mut() {
+			for event in namespace_data.events.drain(..) {
+				let subscribers = match namespace_data.subscriptions.get(event.get_image_name()) {
+					Some(s) => s,
+					None => continue,
+				};
+
+				for conn in connections.iter() {
+					let conn = conn.borrow();
+					if !subscribers.contains(&conn.get_id()) {
+						continue;
+					}
+
+					let res = conn.send_result(ResultMsg::ImageEvent(event.clone()));
+
+					// Closed connections are removed by the server loop
+					if let Err(e) = res {
+						if !e.is_disconnect() {
+							println!("Failed to send event with error '{:}'", e);
+						}
+					}
+				}
+			}
+		}
+	}
+
+	// Move an image to a new name. The GPU memory is kept, only the shmem segment is recreated
+	// under the new name, as shared memory can't be renamed
+	fn rename_image(
+		old_name: &str,
+		new_name: &str,
+		shmem_prefix: &str,
+		images: &mut NameImagesMap,
+		ipc_timeout: Duration,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		let shmem_name_str = VkServer::get_shmem_name(shmem_prefix, new_name);
+		let gpu_images_map = images.get_mut(old_name).unwrap();
+
+		// Create the new segment first, so that a failure leaves the image untouched. All GPU
+		// copies share the old segment, any of them will do
+		let ipc_info = Rc::new(IpcShmem::new(&shmem_name_str, new_name, true)?);
+		{
+			let image = gpu_images_map.images.values().next().unwrap();
+			let rlock = image.ipc_info.acquire_rlock(Timeout::Val(ipc_timeout))?;
+			let lock = ipc_info.acquire_lock(Timeout::Val(ipc_timeout))?;
+			let data = IpcShmem::acquire_data(&lock);
+
+			// Keep the name written by IpcShmem::new
+			let (name_offset, name_len) = (data.name_offset, data.name_len);
+			*data = IpcShmem::acquire_rdata(&rlock).clone();
+			data.name_offset = name_offset;
+			data.name_len = name_len;
+
+			// Metadata is stored after the name, copy it as well
+			IpcShmem::set_metadata(&lock, &gpu_images_map.metadata)?;
+		}
+
+		for image in gpu_images_map.images.values_mut() {
+			image.ipc_info = ipc_info.clone();
+		}
+
+		let gpu_images_map = images.remove(old_name).unwrap();
+		images.insert(new_name.to_string(), gpu_images_map);
+
+		Ok(())
+	}
 
 	// fn update_shared_image<'a>(
 	// 	cmd: &CommInitImage,
@@ -545,7 +1563,7 @@ impl VkServer {
 
 	// 	// Generate ResultMsg data
 	// 	let img_data = ImgData::from_shmem_data_internal(
-	// 		ImgData::convert_shmem_str_to_array(image.ipc_info.get_name()),
+	// 		ImgData::convert_shmem_str_to_array(image.ipc_info.get_name())?,
 	// 		data.clone(),
 	// 	);
 
@@ -570,6 +1588,22 @@ impl VkServer {
 		shmem_data.handle_id = vk_data.id;
 	}
 
+	// Translate the error of a failed command into a code the client can act on
+	fn get_result_error(err: &Box<dyn std::error::Error>) -> ResultError {
+		if let Some(e) = err.downcast_ref::<ServerError>() {
+			return ResultError::new(e.code, &e.message);
+		}
+
+		let code = match err.downcast_ref::<vk::Result>() {
+			Some(&vk::Result::ERROR_FORMAT_NOT_SUPPORTED) => ResultErrorCode::FormatUnsupported,
+			Some(&vk::Result::ERROR_OUT_OF_HOST_MEMORY)
+			| Some(&vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => ResultErrorCode::OutOfMemory,
+			Some(_) => ResultErrorCode::VulkanFailed,
+			None => ResultErrorCode::Unknown,
+		};
+		ResultError::new(code, &err.to_string())
+	}
+
 	fn get_or_create_device<'a>(
 		vk_devices: &'a mut DevicesMap,
 		vk_instance: &VkInstance,
@@ -586,7 +1620,16 @@ impl VkServer {
 						..Default::default()
 					}),
 				)
-				.map_err(|err| err)?; // TODO: Handle wrong UUID
+				.map_err(|err| {
+					ServerError::new(
+						ResultErrorCode::GpuNotFound,
+						format!(
+							"Failed to open GPU with UUID {} with error '{:}'",
+							uuid::Uuid::from_u128(gpu_device_uuid),
+							err
+						),
+					)
+				})?;
 				v.insert_entry(new_vk_device)
 			}
 		};
@@ -606,7 +1649,23 @@ mod tests {
 		time::Duration,
 	};
 
-	use texture_share_vk_base::ipc::IpcConnection;
+	use std::io::Write;
+	use std::net::Shutdown;
+	use std::os::unix::net::UnixStream;
+	use std::rc::Rc;
+
+	use texture_share_vk_base::ipc::platform::img_data::{img_name, ImgFormat};
+	use texture_share_vk_base::ipc::platform::ipc_commands::{
+		CommFindImage, CommInitImage, CommandMsg, CommandTag, ResultErrorCode, ResultMsg,
+		DEFAULT_NAMESPACE,
+	};
+	use texture_share_vk_base::ipc::platform::ipc_protocol::{
+		MsgHeader, PROTOCOL_VERSION, PROTOCOL_VERSION_MIN,
+	};
+	use texture_share_vk_base::ipc::platform::Timeout;
+	use texture_share_vk_base::ipc::{Error, IpcConnection, IpcShmem};
+	use texture_share_vk_base::uuid::Uuid;
+	use texture_share_vk_base::vk_device::VkDevice;
 
 	use super::VkServer;
 
@@ -678,4 +1737,327 @@ mod tests {
 
 		server_thread.join().unwrap();
 	}
+
+	#[test]
+	fn server_shmem_name() {
+		assert_eq!(VkServer::get_shmem_name("prefix_", "img"), "prefix_img");
+		assert_eq!(
+			VkServer::get_shmem_name("prefix_", "studio/cam1/color"),
+			"prefix_studio%2Fcam1%2Fcolor"
+		);
+
+		// Escaped names don't collide with names containing the escape sequence
+		assert_ne!(
+			VkServer::get_shmem_name("prefix_", "a/b"),
+			VkServer::get_shmem_name("prefix_", "a%2Fb")
+		);
+
+		// The default namespace keeps the plain prefix, others can't collide with it
+		assert_eq!(
+			VkServer::get_namespace_shmem_prefix("prefix_", DEFAULT_NAMESPACE),
+			"prefix_"
+		);
+		let namespace_prefix = VkServer::get_namespace_shmem_prefix("prefix_", "app/1");
+		assert_eq!(namespace_prefix, "prefix_app%2F1%%");
+		assert_ne!(
+			VkServer::get_shmem_name(&namespace_prefix, "img"),
+			VkServer::get_shmem_name("prefix_", "app/1%%img")
+		);
+	}
+
+	#[test]
+	fn server_error_result() {
+		let _ = fs::remove_file(SOCKET_PATH);
+		let stop_bit = Arc::new(AtomicBool::new(false));
+
+		let stop_clone = stop_bit.clone();
+		let server_thread = spawn(move || {
+			let server = _server_create();
+			server.loop_server(stop_clone).expect("Server loop failed")
+		});
+
+		let conn = IpcConnection::try_connect(SOCKET_PATH, SOCKET_TIMEOUT)
+			.unwrap()
+			.unwrap();
+
+		// No GPU has this UUID
+		let image_name = img_name("test_img").unwrap();
+		conn.send_command(CommandMsg::InitImage(CommInitImage {
+			shmem_name: image_name.clone(),
+			image_name,
+			width: 1,
+			height: 1,
+			format: ImgFormat::R8G8B8A8,
+			overwrite_existing: false,
+			gpu_device_uuid: u128::MAX,
+			buffer_count: 1,
+		}))
+		.unwrap();
+
+		let err = match conn.recv_result() {
+			Err(Error::Server(e)) => e,
+			Err(e) => panic!("Expected server error, received '{}'", e),
+			Ok(_) => panic!("Expected error result"),
+		};
+		assert_eq!(err.code, ResultErrorCode::GpuNotFound);
+
+		stop_bit.store(true, Ordering::Relaxed);
+
+		server_thread.join().unwrap();
+	}
+
+	// Xorshift generator, so that a failing input can be reproduced from its seed
+	fn _random_bytes(state: &mut u64, len: usize) -> Vec<u8> {
+		(0..len)
+			.map(|_| {
+				*state ^= *state << 13;
+				*state ^= *state >> 7;
+				*state ^= *state << 17;
+				(*state >> 32) as u8
+			})
+			.collect()
+	}
+
+	// Returns a server connection after the handshake, and the raw client stream
+	fn _connection_pair() -> (IpcConnection, UnixStream) {
+		let (server_stream, client_stream) = UnixStream::pair().unwrap();
+		let server_conn = IpcConnection::new(server_stream, IPC_TIMEOUT).unwrap();
+
+		let client_thread = spawn(move || {
+			let client_conn = IpcConnection::new(client_stream, IPC_TIMEOUT).unwrap();
+			client_conn
+				.handshake(PROTOCOL_VERSION_MIN, PROTOCOL_VERSION, DEFAULT_NAMESPACE)
+				.unwrap();
+			client_conn
+		});
+		assert!(server_conn
+			.accept_handshake(Uuid::new_v4().as_u128())
+			.unwrap());
+
+		let client_conn = client_thread.join().unwrap();
+		let client_stream = client_conn.get_socket().try_clone().unwrap();
+		(server_conn, client_stream)
+	}
+
+	#[test]
+	fn server_find_missing_image() {
+		let _ = fs::remove_file(SOCKET_PATH);
+		let mut server = _server_create();
+
+		let (conn, client_stream) = _connection_pair();
+		let client_conn = IpcConnection::new(client_stream, IPC_TIMEOUT).unwrap();
+		client_conn
+			.send_command(CommandMsg::FindImage(CommFindImage {
+				image_name: img_name("missing_img").unwrap(),
+				gpu_device_uuid: *server.vk_devices.keys().next().unwrap(),
+			}))
+			.unwrap();
+
+		let keep_open = VkServer::process_single_connection(
+			&conn,
+			&server.vk_instance,
+			&mut server.vk_devices,
+			&server.shmem_prefix,
+			&mut server.namespaces,
+			&mut server.gpu_copy_count,
+			1,
+			server.socket.server_instance_id,
+			server.ipc_timeout,
+		)
+		.unwrap();
+		assert!(keep_open);
+
+		let res = client_conn.recv_result().unwrap().unwrap();
+		assert!(matches!(res, ResultMsg::FindImage(res) if !res.image_found));
+
+		// Lookups don't add names, only init_image does
+		assert!(server.namespaces[DEFAULT_NAMESPACE].images.is_empty());
+	}
+
+	// Register a second device under another UUID, so that images can have several GPU copies
+	// on machines with a single GPU
+	fn _add_device(server: &mut VkServer) -> u128 {
+		let gpu_device_uuid = Uuid::new_v4().as_u128();
+		let vk_device = VkDevice::new(&server.vk_instance, None).unwrap();
+		server.vk_devices.insert(gpu_device_uuid, vk_device);
+		gpu_device_uuid
+	}
+
+	// Initialize an image in the default namespace, as a client on the given device would
+	fn _init_image(
+		server: &mut VkServer,
+		gpu_device_uuid: u128,
+		image_name: &str,
+		width: u32,
+		buffer_count: u32,
+	) {
+		let (conn, client_stream) = _connection_pair();
+		let client_thread = spawn(move || {
+			let client_conn = IpcConnection::new(client_stream, IPC_TIMEOUT).unwrap();
+			let buffer_count = match client_conn.recv_result().unwrap().unwrap() {
+				ResultMsg::InitImage(res) => res.img_data.data.buffer_count,
+				res => panic!("Received {:?}", res.tag()),
+			};
+			client_conn.recv_ancillary(buffer_count as usize).unwrap();
+			client_conn.send_ack().unwrap();
+		});
+
+		let namespace = server
+			.namespaces
+			.entry(DEFAULT_NAMESPACE.to_string())
+			.or_default();
+		VkServer::process_cmd_init_image(
+			&conn,
+			&CommInitImage {
+				image_name: img_name(image_name).unwrap(),
+				shmem_name: img_name(image_name).unwrap(),
+				width,
+				height: 4,
+				format: ImgFormat::R8G8B8A8,
+				overwrite_existing: true,
+				gpu_device_uuid,
+				buffer_count,
+			},
+			&server.vk_instance,
+			&mut server.vk_devices,
+			&server.shmem_prefix,
+			&mut namespace.images,
+			&mut namespace.events,
+			server.ipc_timeout,
+		)
+		.unwrap();
+		client_thread.join().unwrap();
+	}
+
+	#[test]
+	fn server_resize_gpu_copies() {
+		let _ = fs::remove_file(SOCKET_PATH);
+		let mut server = _server_create();
+
+		let first_uuid = *server.vk_devices.keys().next().unwrap();
+		let second_uuid = _add_device(&mut server);
+		_init_image(&mut server, first_uuid, "resize_img", 4, 1);
+		_init_image(&mut server, second_uuid, "resize_img", 4, 1);
+
+		// Resizing from one device recreates the buffers of the other copy on its own device.
+		// Dropping the server destroys each copy with the device stored under its UUID
+		_init_image(&mut server, first_uuid, "resize_img", 8, 3);
+
+		let images = &server.namespaces[DEFAULT_NAMESPACE].images;
+		for (gpu_device_uuid, image) in images["resize_img"].images.iter() {
+			assert_eq!(image.get_buffer_count(), 3, "device {}", gpu_device_uuid);
+			for buffer in std::iter::once(&image.vk_shared_image)
+				.chain(image.extra_buffers.iter())
+			{
+				assert_eq!(buffer.image.get_image_data().width, 8);
+			}
+		}
+	}
+
+	#[test]
+	fn server_rename_gpu_copies() {
+		let _ = fs::remove_file(SOCKET_PATH);
+		let mut server = _server_create();
+
+		let gpu_device_uuids = [
+			*server.vk_devices.keys().next().unwrap(),
+			_add_device(&mut server),
+		];
+		for gpu_device_uuid in gpu_device_uuids {
+			_init_image(&mut server, gpu_device_uuid, "rename_img", 4, 1);
+		}
+
+		let images = &mut server.namespaces.get_mut(DEFAULT_NAMESPACE).unwrap().images;
+		VkServer::rename_image(
+			"rename_img",
+			"renamed_img",
+			&server.shmem_prefix,
+			images,
+			IPC_TIMEOUT,
+		)
+		.unwrap();
+		assert!(!images.contains_key("rename_img"));
+
+		// Both copies moved to one new segment, which clients open by name
+		let copies = images["renamed_img"].images.values().collect::<Vec<_>>();
+		assert_eq!(copies.len(), 2);
+		assert!(Rc::ptr_eq(&copies[0].ipc_info, &copies[1].ipc_info));
+
+		let shmem_name = VkServer::get_shmem_name(&server.shmem_prefix, "renamed_img");
+		let client_shmem = IpcShmem::new(&shmem_name, "renamed_img", false).unwrap();
+		{
+			let lock = copies[1].ipc_info.acquire_lock(Timeout::Val(IPC_TIMEOUT)).unwrap();
+			IpcShmem::acquire_data(&lock).frame_number = 7;
+		}
+		let rlock = client_shmem.acquire_rlock(Timeout::Val(IPC_TIMEOUT)).unwrap();
+		assert_eq!(IpcShmem::acquire_rdata(&rlock).frame_number, 7);
+	}
+
+	// Feed data into a connection and process it until the server drops the connection
+	fn _process_untrusted_input(server: &mut VkServer, data: &[u8]) {
+		let (conn, mut client_stream) = _connection_pair();
+		client_stream.write_all(data).unwrap();
+		client_stream.shutdown(Shutdown::Write).unwrap();
+
+		// Every call consumes at least one byte, the connection has to be closed at the end
+		for _ in 0..=data.len() {
+			let keep_open = VkServer::process_single_connection(
+				&conn,
+				&server.vk_instance,
+				&mut server.vk_devices,
+				&server.shmem_prefix,
+				&mut server.namespaces,
+				&mut server.gpu_copy_count,
+				1,
+				server.socket.server_instance_id,
+				server.ipc_timeout,
+			)
+			.expect("Server failed to process untrusted input");
+			if !keep_open {
+				return;
+			}
+		}
+
+		panic!("Server did not close connection after end of input");
+	}
+
+	#[test]
+	fn server_random_bytes() {
+		let _ = fs::remove_file(SOCKET_PATH);
+		let mut server = _server_create();
+
+		let mut state: u64 = 0x2545F4914F6CDD1D;
+		for _ in 0..64 {
+			let len = _random_bytes(&mut state, 1)[0] as usize * 16;
+			let data = _random_bytes(&mut state, len);
+			_process_untrusted_input(&mut server, &data);
+		}
+	}
+
+	#[test]
+	fn server_random_payloads() {
+		let _ = fs::remove_file(SOCKET_PATH);
+		let mut server = _server_create();
+
+		// Valid headers followed by random payloads, including tags outside the valid range
+		let mut state: u64 = 0x9E3779B97F4A7C15;
+		for i in 0..256 {
+			let tag = _random_bytes(&mut state, 1)[0] as u32
+				% (CommandTag::ListImagesMatching as u32 + 3);
+			let payload_len = _random_bytes(&mut state, 1)[0] as usize * 4;
+
+			let mut data = MsgHeader::new(PROTOCOL_VERSION, tag, payload_len)
+				.to_bytes()
+				.to_vec();
+			data.extend(_random_bytes(&mut state, payload_len));
+
+			// Some payloads start with a short name, so that later fields are validated too
+			if i % 2 == 0 && payload_len >= 4 {
+				let name_len = _random_bytes(&mut state, 1)[0] as u32 % 16;
+				data[MsgHeader::SIZE..MsgHeader::SIZE + 4].copy_from_slice(&name_len.to_ne_bytes());
+			}
+
+			_process_untrusted_input(&mut server, &data);
+		}
+	}
 }